@@ -3,18 +3,13 @@ slint::slint! {
 }
 
 mod proxy;
-use proxy::{ProxyController, get_system_proxy, set_system_proxy, clear_system_proxy};
+mod traffic;
+use proxy::{ProxyController, get_system_proxy, set_system_proxy, original};
 use std::net::{IpAddr, SocketAddr};
 use std::str::FromStr;
 use slint::ComponentHandle;
-use once_cell::sync::Lazy;
-use std::sync::Mutex;
 use std::sync::mpsc;
 
-
-// 添加新的静态变量来存储原始代理设置
-static ORIGINAL_PROXY: Lazy<Mutex<Option<String>>> = Lazy::new(|| Mutex::new(None));
-
 fn is_port_available(port: u16) -> bool {
     // 同时检查 TCP 和 UDP，使用 0.0.0.0
     let tcp_available = std::net::TcpListener::bind(("0.0.0.0", port)).is_ok();
@@ -35,10 +30,37 @@ fn is_port_available(port: u16) -> bool {
     tcp_available && udp_available
 }
 
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate()).expect("Failed to install SIGTERM handler");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
 #[tokio::main]
 async fn main() -> Result<(), slint::PlatformError> {
-    let (tx, _rx) = mpsc::sync_channel(100);
+    // 上次没有正常退出时系统代理还指向已经不存在的代理，先恢复
+    original::restore_or_clear();
+
+    // 被 SIGINT/SIGTERM 结束时也恢复系统代理
+    tokio::spawn(async {
+        shutdown_signal().await;
+        original::restore_or_clear();
+        std::process::exit(0);
+    });
+
+    let (tx, rx) = mpsc::sync_channel(100);
     let main_window = MainWindow::new()?;
+    traffic::setup(&main_window, rx);
     let proxy_controller = std::sync::Arc::new(tokio::sync::Mutex::new(ProxyController::new(tx)));
     
     // 设置 window
@@ -92,10 +114,16 @@ async fn main() -> Result<(), slint::PlatformError> {
                 return;
             }
             
-            // 保存当前系统代理设置
-            if let Ok(current_proxy) = get_system_proxy() {
-                let mut original_proxy = ORIGINAL_PROXY.lock().unwrap();
-                *original_proxy = Some(current_proxy);
+            // 修改前把当前系统代理设置保存到磁盘，重启代理时保留最初的设置
+            if !original::is_saved() {
+                let saved = get_system_proxy().and_then(|current| original::save(&current));
+                if let Err(e) = saved {
+                    println!("Failed to save system proxy: {}", e);
+                    if let Some(window) = window.upgrade() {
+                        window.set_proxy_running(false);
+                    }
+                    return;
+                }
             }
 
             let addr = SocketAddr::new(IpAddr::from_str("0.0.0.0").unwrap(), port as u16);
@@ -121,10 +149,8 @@ async fn main() -> Result<(), slint::PlatformError> {
                     if let Some(window) = window.upgrade() {
                         window.set_proxy_running(false);
                     }
-                    // 如果启动失败，清除代理设置
-                    if let Err(e) = clear_system_proxy() {
-                        println!("Failed to clear system proxy: {}", e);
-                    }
+                    // 如果启动失败，恢复原来的代理设置
+                    original::restore_or_clear();
                 }
             }
         });
@@ -143,9 +169,6 @@ async fn main() -> Result<(), slint::PlatformError> {
             window.set_proxy_running(false);
         }
         
-        // 在 spawn 之前获取代理设置
-        let proxy_setting = ORIGINAL_PROXY.lock().unwrap().clone();
-        
         tokio::spawn(async move {
             
             // 先停止代理服务
@@ -156,57 +179,55 @@ async fn main() -> Result<(), slint::PlatformError> {
             }
 
             // 然后恢复系统代理设置
-            if let Some(proxy_setting) = proxy_setting {
-                if let Err(e) = set_system_proxy(&proxy_setting) {
-                    println!("Failed to restore original proxy settings: {}", e);
-                    // 如果恢复失败，尝试完全清除代理设置
-                    if let Err(e) = clear_system_proxy() {
-                        println!("Failed to clear system proxy as fallback: {}", e);
-                    }
-                }
-            } else {
-                if let Err(e) = clear_system_proxy() {
-                    println!("Failed to clear system proxy: {}", e);
-                }
-            }
+            original::restore_or_clear();
         });
     });
 
-    // 修改证书安装处理
-    let proxy_weak = proxy_controller.clone();
-    let window_weak = main_window.as_weak();
+    // 证书安装处理
     main_window.on_install_certificate(move || {
         println!("Installing certificate...");
-        let proxy = proxy_weak.clone();
-        let window = window_weak.clone();
-        
-        tokio::spawn(async move {
-            // // 1. 先停止代理
-            // let mut proxy = proxy.lock().await;
-            // let _ = proxy.stop().await;
-            // if let Some(window) = window.upgrade() {
-            //     window.set_proxy_running(false);
-            // }
-
-            // // 2. 安装证书
-            // match proxyapi::ca::Ssl::install_certificate().await {
-            //     Ok(()) => {
-            //         println!("Certificate installed successfully");
-            //         #[cfg(target_os = "windows")]
-            //         println!("If automatic installation failed, please manually install the certificate from: %APPDATA%\\proxelar\\ca.crt");
-            //         #[cfg(target_os = "macos")]
-            //         println!("If automatic installation failed, please manually install the certificate from: ~/Library/Application Support/proxelar/ca.crt");
-            //         #[cfg(target_os = "linux")]
-            //         println!("If automatic installation failed, please manually install the certificate from: ~/.local/share/proxelar/ca.crt");
-            //         println!("Please restart your browser after installing the certificate");
-            //     }
-            //     Err(e) => {
-            //         println!("Failed to install certificate: {}", e);
-            //     }
-            // }
+
+        tokio::task::spawn_blocking(|| {
+            let store = proxyapi::ca::CaStore::default();
+            let cert = match store.load_or_generate() {
+                Ok((cert, _)) => cert.into_bytes(),
+                Err(e) => {
+                    println!("Failed to load certificate: {}", e);
+                    return;
+                }
+            };
+
+            #[cfg(target_os = "linux")]
+            {
+                let trust_store = proxyapi::ca::trust::TrustStore::new();
+                if trust_store.is_trusted(&cert) {
+                    println!("Certificate is already trusted");
+                    return;
+                }
+                match trust_store.install(&cert) {
+                    Ok(()) => {
+                        println!("Certificate installed successfully");
+                        println!("Please restart your browser after installing the certificate");
+                    }
+                    Err(e) => {
+                        println!("Failed to install certificate: {}", e);
+                        println!("Please manually install the certificate from: {}", store.cert_path().display());
+                    }
+                }
+            }
+
+            #[cfg(not(target_os = "linux"))]
+            {
+                let _ = cert;
+                println!("Please manually install the certificate from: {}", store.cert_path().display());
+            }
         });
     });
 
     println!("Application started");
-    main_window.run()
+    let result = main_window.run();
+
+    // 关闭窗口时恢复系统代理
+    original::restore_or_clear();
+    result
 } 
\ No newline at end of file
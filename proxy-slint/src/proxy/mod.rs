@@ -1,31 +1,36 @@
 use std::{
-    convert::Infallible,
-    net::{SocketAddr, IpAddr},
+    net::SocketAddr,
     sync::{mpsc::SyncSender, Arc},
-    str::FromStr,
 };
 
-use proxyapi::{Proxy, proxy_handler::ProxyHandler};
+use proxyapi::{
+    ca::{CaStore, Ssl},
+    proxy_handler::{FlowEvent, ProxyHandler},
+    Proxy, ProxyBuilder,
+};
 use slint::Weak;
 use crate::MainWindow;
 use std::sync::atomic::{AtomicBool, Ordering};
 
+pub mod original;
 mod system_proxy;
-pub use system_proxy::{get_system_proxy, set_system_proxy, clear_system_proxy};
+pub use system_proxy::{
+    clear_system_proxy, get_system_proxy, restore_system_proxy, set_system_proxy, SystemProxy,
+};
 
 pub struct ProxyController {
     window: Option<Weak<MainWindow>>,
-    tx: Option<SyncSender<ProxyHandler>>,
+    tx: Option<SyncSender<FlowEvent>>,
     running: Arc<AtomicBool>,
     addr: Option<SocketAddr>,
     proxy: Option<Arc<Proxy>>,
 }
 
 impl ProxyController {
-    pub fn new(proxy_handler: SyncSender<ProxyHandler>) -> Self {
+    pub fn new(flow_tx: SyncSender<FlowEvent>) -> Self {
         Self {
             window: None,
-            tx: Some(proxy_handler),
+            tx: Some(flow_tx),
             running: Arc::new(AtomicBool::new(false)),
             addr: None,
             proxy: None,
@@ -38,7 +43,18 @@ impl ProxyController {
 
     pub async fn start(&mut self, addr: SocketAddr) -> Result<(), Box<dyn std::error::Error>> {
         self.addr = Some(addr);
-        let proxy = Arc::new(Proxy::new(addr, self.tx.clone()));
+        let http_handler = match &self.tx {
+            Some(tx) => ProxyHandler::default().with_flow_sender(tx.clone()),
+            None => ProxyHandler::default(),
+        };
+        let proxy = Arc::new(
+            ProxyBuilder::new()
+                .with_addr(addr)
+                .with_rustls_client()
+                .with_ca(Ssl::from_store(&CaStore::default())?)
+                .with_http_handler(http_handler)
+                .build(),
+        );
         self.proxy = Some(Arc::clone(&proxy));
         
         let proxy_ref = Arc::clone(&proxy);
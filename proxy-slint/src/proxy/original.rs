@@ -0,0 +1,61 @@
+//! 把修改前的系统代理设置保存在磁盘上，程序崩溃或被杀掉后下次启动时还能恢复
+use std::{error::Error, fs, io, path::PathBuf};
+
+use super::{clear_system_proxy, restore_system_proxy, SystemProxy};
+
+fn marker_path() -> PathBuf {
+    dirs_next::data_dir()
+        .unwrap_or_default()
+        .join("proxelar")
+        .join("original-proxy.json")
+}
+
+/// Whether original settings are saved, i.e. the system proxy currently points at us.
+pub fn is_saved() -> bool {
+    marker_path().exists()
+}
+
+/// Saves the settings to restore before the system proxy is changed.
+pub fn save(original: &SystemProxy) -> Result<(), Box<dyn Error>> {
+    let path = marker_path();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    // 先写临时文件再重命名，崩溃时不会留下写了一半的文件
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_vec(original)?)?;
+    fs::rename(tmp, path)?;
+    Ok(())
+}
+
+/// Restores the saved settings and removes them, returns whether there were any.
+///
+/// The saved settings are kept if restoring fails, so it can be retried on next start.
+pub fn restore() -> Result<bool, Box<dyn Error>> {
+    let path = marker_path();
+    let original: SystemProxy = match fs::read(&path) {
+        Ok(contents) => serde_json::from_slice(&contents)?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+
+    restore_system_proxy(&original)?;
+    fs::remove_file(path)?;
+    Ok(true)
+}
+
+/// Restores the saved settings, clearing the system proxy if that fails.
+pub fn restore_or_clear() {
+    match restore() {
+        Ok(true) => println!("Restored original system proxy"),
+        Ok(false) => {}
+        Err(e) => {
+            println!("Failed to restore original proxy settings: {}", e);
+            // 如果恢复失败，尝试完全清除代理设置
+            if let Err(e) = clear_system_proxy() {
+                println!("Failed to clear system proxy as fallback: {}", e);
+            }
+        }
+    }
+}
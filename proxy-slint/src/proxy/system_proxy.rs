@@ -89,21 +89,42 @@ pub fn clear_system_proxy() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-// Linux 实现可以根据具体发行版添加
+#[cfg(any(target_os = "windows", target_os = "macos"))]
+pub type SystemProxy = String;
+
+/// 恢复启动代理前的设置
+#[cfg(any(target_os = "windows", target_os = "macos"))]
+pub fn restore_system_proxy(original: &SystemProxy) -> Result<(), Box<dyn Error>> {
+    if original.is_empty() {
+        clear_system_proxy()
+    } else {
+        set_system_proxy(original)
+    }
+}
+
 #[cfg(target_os = "linux")]
-pub fn get_system_proxy() -> Result<String, Box<dyn Error>> {
-    // 实现 Linux 的代理获取逻辑
-    Ok("".to_string())
+mod linux;
+
+#[cfg(target_os = "linux")]
+pub use linux::SystemProxy;
+
+#[cfg(target_os = "linux")]
+pub fn get_system_proxy() -> Result<SystemProxy, Box<dyn Error>> {
+    linux::LinuxProxy::new().capture()
 }
 
 #[cfg(target_os = "linux")]
 pub fn set_system_proxy(proxy: &str) -> Result<(), Box<dyn Error>> {
-    // 实现 Linux 的代理设置逻辑
-    Ok(())
+    linux::LinuxProxy::new().set(proxy)
 }
 
 #[cfg(target_os = "linux")]
 pub fn clear_system_proxy() -> Result<(), Box<dyn Error>> {
-    // 实现 Linux 的代理清除逻辑
-    Ok(())
-} 
\ No newline at end of file
+    linux::LinuxProxy::new().clear()
+}
+
+/// 恢复启动代理前的设置
+#[cfg(target_os = "linux")]
+pub fn restore_system_proxy(original: &SystemProxy) -> Result<(), Box<dyn Error>> {
+    linux::LinuxProxy::new().restore(original)
+}
@@ -0,0 +1,449 @@
+use std::{error::Error, fs, io, path::PathBuf, process::Command};
+
+use serde::{Deserialize, Serialize};
+
+/// 不走代理的地址
+const BYPASS: &[&str] = &["localhost", "127.0.0.0/8", "::1"];
+
+/// 记录的 GNOME 代理设置 (schema, key)
+const GNOME_KEYS: &[(&str, &str)] = &[
+    ("org.gnome.system.proxy", "mode"),
+    ("org.gnome.system.proxy", "autoconfig-url"),
+    ("org.gnome.system.proxy", "ignore-hosts"),
+    ("org.gnome.system.proxy.http", "host"),
+    ("org.gnome.system.proxy.http", "port"),
+    ("org.gnome.system.proxy.https", "host"),
+    ("org.gnome.system.proxy.https", "port"),
+];
+
+/// 记录的 KDE kioslaverc 代理设置
+const KDE_KEYS: &[&str] = &["ProxyType", "httpProxy", "httpsProxy", "NoProxyFor"];
+const KDE_GROUP: &str = "Proxy Settings";
+/// kreadconfig 对不存在的键返回空字符串，用这个默认值区分
+const KDE_UNSET: &str = "\u{1}unset";
+
+/// Runs external commands, so the desktop specific tools can be faked in tests.
+pub trait CommandRunner {
+    /// Runs `program` and returns its stdout, failing if it doesn't exit successfully.
+    fn run(&self, program: &str, args: &[&str]) -> io::Result<String>;
+}
+
+pub struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+    fn run(&self, program: &str, args: &[&str]) -> io::Result<String> {
+        let output = Command::new(program).args(args).output()?;
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+        } else {
+            Err(io::Error::other(format!(
+                "{program} {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )))
+        }
+    }
+}
+
+/// System proxy settings captured before the proxy was set, to restore them as they were.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SystemProxy {
+    /// GNOME 设置的原始 GVariant 值
+    gnome: Vec<(String, String, String)>,
+    /// KDE 设置，`None` 表示原来没有这个键
+    kde: Vec<(String, Option<String>)>,
+    /// 环境变量文件原来的内容，`None` 表示原来没有这个文件
+    environment: Option<String>,
+}
+
+/// Sets the proxy for GNOME through gsettings, for KDE in kioslaverc and for new login
+/// sessions in an environment.d file.
+pub struct LinuxProxy<R> {
+    runner: R,
+    environment_file: Option<PathBuf>,
+}
+
+impl LinuxProxy<SystemRunner> {
+    pub fn new() -> Self {
+        Self::with_runner(
+            SystemRunner,
+            dirs_next::config_dir()
+                .map(|dir| dir.join("environment.d").join("proxelar-proxy.conf")),
+        )
+    }
+}
+
+impl<R: CommandRunner> LinuxProxy<R> {
+    pub fn with_runner(runner: R, environment_file: Option<PathBuf>) -> Self {
+        Self {
+            runner,
+            environment_file,
+        }
+    }
+
+    pub fn capture(&self) -> Result<SystemProxy, Box<dyn Error>> {
+        let mut original = SystemProxy::default();
+
+        if self.has_gnome() {
+            for (schema, key) in GNOME_KEYS {
+                let value = self.runner.run("gsettings", &["get", schema, key])?;
+                original
+                    .gnome
+                    .push((schema.to_string(), key.to_string(), value.trim().to_owned()));
+            }
+        }
+
+        if let Some(kreadconfig) = self.kde_tool("kreadconfig") {
+            for key in KDE_KEYS {
+                let value = self.runner.run(
+                    kreadconfig,
+                    &[
+                        "--file",
+                        "kioslaverc",
+                        "--group",
+                        KDE_GROUP,
+                        "--key",
+                        key,
+                        "--default",
+                        KDE_UNSET,
+                    ],
+                )?;
+                let value = value.trim_end_matches('\n');
+                original.kde.push((
+                    key.to_string(),
+                    (value != KDE_UNSET).then(|| value.to_owned()),
+                ));
+            }
+        }
+
+        if let Some(path) = &self.environment_file {
+            original.environment = match fs::read_to_string(path) {
+                Ok(contents) => Some(contents),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(e) => return Err(e.into()),
+            };
+        }
+
+        Ok(original)
+    }
+
+    /// Points HTTP and HTTPS at `proxy`, given as `host:port`.
+    pub fn set(&self, proxy: &str) -> Result<(), Box<dyn Error>> {
+        let (host, port) = proxy.rsplit_once(':').ok_or("Invalid proxy format")?;
+        port.parse::<u16>()?;
+
+        if self.has_gnome() {
+            let ignore_hosts = format!(
+                "[{}]",
+                BYPASS
+                    .iter()
+                    .map(|host| format!("'{host}'"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            for schema in [
+                "org.gnome.system.proxy.http",
+                "org.gnome.system.proxy.https",
+            ] {
+                self.gsettings(schema, "host", &format!("'{host}'"))?;
+                self.gsettings(schema, "port", port)?;
+            }
+            self.gsettings("org.gnome.system.proxy", "ignore-hosts", &ignore_hosts)?;
+            self.gsettings("org.gnome.system.proxy", "mode", "'manual'")?;
+        }
+
+        if self.kde_tool("kwriteconfig").is_some() {
+            let url = format!("http://{host} {port}");
+            self.kde_write("httpProxy", Some(&url))?;
+            self.kde_write("httpsProxy", Some(&url))?;
+            self.kde_write("NoProxyFor", Some(&BYPASS.join(",")))?;
+            self.kde_write("ProxyType", Some("1"))?;
+            self.kde_reload();
+        }
+
+        if let Some(path) = &self.environment_file {
+            let url = format!("http://{host}:{port}");
+            let no_proxy = BYPASS.join(",");
+            let mut contents = String::new();
+            for (name, value) in [
+                ("http_proxy", &url),
+                ("https_proxy", &url),
+                ("no_proxy", &no_proxy),
+            ] {
+                contents.push_str(&format!("{name}={value}\n"));
+                contents.push_str(&format!("{}={value}\n", name.to_uppercase()));
+            }
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            fs::write(path, contents)?;
+        }
+
+        Ok(())
+    }
+
+    /// Turns the proxy off.
+    pub fn clear(&self) -> Result<(), Box<dyn Error>> {
+        if self.has_gnome() {
+            self.gsettings("org.gnome.system.proxy", "mode", "'none'")?;
+        }
+
+        if self.kde_tool("kwriteconfig").is_some() {
+            self.kde_write("ProxyType", Some("0"))?;
+            self.kde_reload();
+        }
+
+        if let Some(path) = &self.environment_file {
+            remove_file(path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Puts back the settings `original` was captured from.
+    pub fn restore(&self, original: &SystemProxy) -> Result<(), Box<dyn Error>> {
+        // mode 最后恢复，避免中途用到一半恢复的设置
+        for (schema, key, value) in original.gnome.iter().rev() {
+            self.gsettings(schema, key, value)?;
+        }
+
+        if !original.kde.is_empty() {
+            for (key, value) in original.kde.iter().rev() {
+                self.kde_write(key, value.as_deref())?;
+            }
+            self.kde_reload();
+        }
+
+        if let Some(path) = &self.environment_file {
+            match &original.environment {
+                Some(contents) => fs::write(path, contents)?,
+                None => remove_file(path)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn has_gnome(&self) -> bool {
+        self.runner
+            .run("gsettings", &["get", "org.gnome.system.proxy", "mode"])
+            .is_ok()
+    }
+
+    /// KDE 6 or KDE 5 version of `tool`, if installed.
+    fn kde_tool(&self, tool: &str) -> Option<&'static str> {
+        let candidates: &[&'static str] = match tool {
+            "kreadconfig" => &["kreadconfig6", "kreadconfig5"],
+            _ => &["kwriteconfig6", "kwriteconfig5"],
+        };
+        candidates
+            .iter()
+            .copied()
+            .find(|program| self.runner.run(program, &["--help"]).is_ok())
+    }
+
+    fn gsettings(&self, schema: &str, key: &str, value: &str) -> io::Result<()> {
+        self.runner
+            .run("gsettings", &["set", schema, key, value])
+            .map(drop)
+    }
+
+    fn kde_write(&self, key: &str, value: Option<&str>) -> Result<(), Box<dyn Error>> {
+        let kwriteconfig = self
+            .kde_tool("kwriteconfig")
+            .ok_or("kwriteconfig not found")?;
+        let mut args = vec!["--file", "kioslaverc", "--group", KDE_GROUP, "--key", key];
+        match value {
+            Some(value) => args.push(value),
+            None => args.push("--delete"),
+        }
+        self.runner.run(kwriteconfig, &args)?;
+        Ok(())
+    }
+
+    /// 通知 KDE 应用重新读取代理设置
+    fn kde_reload(&self) {
+        let _ = self.runner.run(
+            "dbus-send",
+            &[
+                "--type=signal",
+                "/KIO/Scheduler",
+                "org.kde.KIO.Scheduler.reparseSlaveConfiguration",
+                "string:",
+            ],
+        );
+    }
+}
+
+fn remove_file(path: &PathBuf) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, collections::HashMap};
+
+    /// 模拟 gsettings 和 kreadconfig/kwriteconfig，把设置保存在内存里
+    #[derive(Default)]
+    struct FakeDesktop {
+        gnome: bool,
+        kde: bool,
+        settings: RefCell<HashMap<String, String>>,
+    }
+
+    impl CommandRunner for FakeDesktop {
+        fn run(&self, program: &str, args: &[&str]) -> io::Result<String> {
+            let mut settings = self.settings.borrow_mut();
+            match (program, args) {
+                ("gsettings", ["get", schema, key]) if self.gnome => Ok(format!(
+                    "{}\n",
+                    settings
+                        .get(&format!("{schema}.{key}"))
+                        .map_or("''", String::as_str)
+                )),
+                ("gsettings", ["set", schema, key, value]) if self.gnome => {
+                    settings.insert(format!("{schema}.{key}"), value.to_string());
+                    Ok(String::new())
+                }
+                ("kreadconfig6" | "kwriteconfig6", ["--help"]) if self.kde => Ok(String::new()),
+                ("kreadconfig6", [.., "--key", key, "--default", default]) if self.kde => {
+                    Ok(format!(
+                        "{}\n",
+                        settings
+                            .get(&format!("kde.{key}"))
+                            .map_or(*default, String::as_str)
+                    ))
+                }
+                ("kwriteconfig6", [.., "--key", key, "--delete"]) if self.kde => {
+                    settings.remove(&format!("kde.{key}"));
+                    Ok(String::new())
+                }
+                ("kwriteconfig6", [.., "--key", key, value]) if self.kde => {
+                    settings.insert(format!("kde.{key}"), value.to_string());
+                    Ok(String::new())
+                }
+                ("dbus-send", _) => Ok(String::new()),
+                _ => Err(io::ErrorKind::NotFound.into()),
+            }
+        }
+    }
+
+    fn get(desktop: &FakeDesktop, key: &str) -> Option<String> {
+        desktop.settings.borrow().get(key).cloned()
+    }
+
+    #[test]
+    fn sets_and_restores_gnome() {
+        let desktop = FakeDesktop {
+            gnome: true,
+            ..Default::default()
+        };
+        desktop.settings.borrow_mut().extend([
+            (
+                "org.gnome.system.proxy.mode".to_owned(),
+                "'auto'".to_owned(),
+            ),
+            (
+                "org.gnome.system.proxy.ignore-hosts".to_owned(),
+                "['corp.example']".to_owned(),
+            ),
+        ]);
+        let before = desktop.settings.borrow().clone();
+        let proxy = LinuxProxy::with_runner(desktop, None);
+
+        let original = proxy.capture().unwrap();
+        proxy.set("127.0.0.1:8100").unwrap();
+
+        let desktop = &proxy.runner;
+        assert_eq!(
+            get(desktop, "org.gnome.system.proxy.mode").as_deref(),
+            Some("'manual'")
+        );
+        assert_eq!(
+            get(desktop, "org.gnome.system.proxy.https.host").as_deref(),
+            Some("'127.0.0.1'")
+        );
+        assert_eq!(
+            get(desktop, "org.gnome.system.proxy.https.port").as_deref(),
+            Some("8100")
+        );
+        assert_eq!(
+            get(desktop, "org.gnome.system.proxy.ignore-hosts").as_deref(),
+            Some("['localhost', '127.0.0.0/8', '::1']")
+        );
+
+        proxy.restore(&original).unwrap();
+        let after = desktop.settings.borrow().clone();
+        for (key, value) in before {
+            assert_eq!(after.get(&key), Some(&value));
+        }
+    }
+
+    #[test]
+    fn sets_and_restores_kde() {
+        let desktop = FakeDesktop {
+            kde: true,
+            ..Default::default()
+        };
+        desktop
+            .settings
+            .borrow_mut()
+            .insert("kde.ProxyType".to_owned(), "0".to_owned());
+        let proxy = LinuxProxy::with_runner(desktop, None);
+
+        let original = proxy.capture().unwrap();
+        proxy.set("127.0.0.1:8100").unwrap();
+
+        let desktop = &proxy.runner;
+        assert_eq!(get(desktop, "kde.ProxyType").as_deref(), Some("1"));
+        assert_eq!(
+            get(desktop, "kde.httpsProxy").as_deref(),
+            Some("http://127.0.0.1 8100")
+        );
+
+        proxy.restore(&original).unwrap();
+        assert_eq!(get(desktop, "kde.ProxyType").as_deref(), Some("0"));
+        // 原来没有的键被删除
+        assert_eq!(get(desktop, "kde.httpProxy"), None);
+        assert_eq!(get(desktop, "kde.NoProxyFor"), None);
+    }
+
+    #[test]
+    fn sets_and_restores_environment_file() {
+        let dir = std::env::temp_dir().join(format!("proxelar-env-{}", std::process::id()));
+        let path = dir.join("environment.d").join("proxelar-proxy.conf");
+        let proxy = LinuxProxy::with_runner(FakeDesktop::default(), Some(path.clone()));
+
+        let original = proxy.capture().unwrap();
+        proxy.set("127.0.0.1:8100").unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(contents.contains("https_proxy=http://127.0.0.1:8100\n"));
+        assert!(contents.contains("NO_PROXY=localhost,127.0.0.0/8,::1\n"));
+
+        proxy.restore(&original).unwrap();
+        assert!(!path.exists());
+
+        fs::write(&path, "http_proxy=http://corp:3128\n").unwrap();
+        let original = proxy.capture().unwrap();
+        proxy.set("127.0.0.1:8100").unwrap();
+        proxy.restore(&original).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "http_proxy=http://corp:3128\n"
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_invalid_proxy() {
+        let proxy = LinuxProxy::with_runner(FakeDesktop::default(), None);
+        assert!(proxy.set("127.0.0.1").is_err());
+        assert!(proxy.set("127.0.0.1:port").is_err());
+    }
+}
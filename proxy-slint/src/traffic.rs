@@ -0,0 +1,490 @@
+use std::{
+    fmt::Write,
+    sync::{mpsc::Receiver, Arc, Mutex},
+};
+
+use proxyapi::proxy_handler::{
+    BodySize, ClientInfo, ErrorKind, Flow, FlowEvent, FlowState, Phase, ServerInfo, Timings,
+};
+use slint::{Color, ComponentHandle, Model, ModelRc, SharedString, VecModel};
+
+use crate::{MainWindow, RequestDetails, RequestRecord, TimelineSegment};
+
+/// 方法筛选选项，和 Yew 界面的 RequestTable 一致
+const METHODS: [&str; 11] = [
+    "ALL", "POST", "GET", "PUT", "DELETE", "PATCH", "HEAD", "OPTIONS", "CONNECT", "TRACE", "OTHERS",
+];
+
+/// 状态筛选选项，失败的请求按错误类型筛选
+fn statuses() -> Vec<SharedString> {
+    ["ALL", "Complete", "Pending", "Failed"]
+        .into_iter()
+        .map(SharedString::from)
+        .chain(ErrorKind::ALL.iter().map(|kind| kind.as_str().into()))
+        .collect()
+}
+
+/// 详情里最多显示的正文长度
+const MAX_BODY_PREVIEW: usize = 64 * 1024;
+
+struct Exchange {
+    id: i32,
+    flow: Flow,
+}
+
+/// Captured exchanges behind the request list, with the current filter.
+#[derive(Default)]
+struct Traffic {
+    exchanges: Vec<Exchange>,
+    next_id: i32,
+    method: String,
+    status: String,
+    host: String,
+    selected: Option<i32>,
+    /// 时间线的起点，第一个请求收到的时间
+    origin: Option<i64>,
+}
+
+impl Traffic {
+    fn matches(&self, exchange: &Exchange) -> bool {
+        let method = exchange.flow.request().method().as_str();
+        let method_matches = match self.method.as_str() {
+            "" | "ALL" => true,
+            "OTHERS" => !METHODS.contains(&method),
+            filter => filter == method,
+        };
+        let flow = &exchange.flow;
+        let status_matches = match self.status.as_str() {
+            "" | "ALL" => true,
+            "Complete" => flow.state() == FlowState::Complete,
+            "Pending" => flow.state().is_pending(),
+            "Failed" => flow.state() == FlowState::Failed,
+            kind => flow
+                .error()
+                .is_some_and(|error| error.kind().as_str() == kind),
+        };
+        let host = flow.request().uri().host().unwrap_or_default();
+
+        method_matches && status_matches && host.contains(self.host.trim())
+    }
+
+    fn rows(&self) -> Vec<RequestRecord> {
+        self.exchanges
+            .iter()
+            .filter(|exchange| self.matches(exchange))
+            .map(|exchange| self.record(exchange))
+            .collect()
+    }
+
+    fn record(&self, exchange: &Exchange) -> RequestRecord {
+        record(exchange, self.origin.unwrap_or_default())
+    }
+
+    /// 时间线的长度（毫秒），所有请求共用，瀑布图才能对齐
+    fn timeline_span(&self) -> f32 {
+        let origin = self.origin.unwrap_or_default();
+        self.exchanges
+            .iter()
+            .map(|exchange| millis(end(&exchange.flow) - origin))
+            .fold(1.0, f32::max)
+    }
+}
+
+/// Keeps the request list of `window` up to date with the flow events received on `rx`.
+pub fn setup(window: &MainWindow, rx: Receiver<FlowEvent>) {
+    let traffic = Arc::new(Mutex::new(Traffic::default()));
+
+    window.set_methods(ModelRc::new(VecModel::from(
+        METHODS
+            .iter()
+            .map(|method| SharedString::from(*method))
+            .collect::<Vec<_>>(),
+    )));
+    window.set_statuses(ModelRc::new(VecModel::from(statuses())));
+    window.set_requests(ModelRc::new(VecModel::<RequestRecord>::default()));
+
+    // 在后台线程接收请求事件，再交给事件循环更新界面
+    let window_weak = window.as_weak();
+    let receiver_traffic = Arc::clone(&traffic);
+    std::thread::spawn(move || {
+        for event in rx.iter() {
+            let traffic = Arc::clone(&receiver_traffic);
+            let window = window_weak.clone();
+            let pushed = slint::invoke_from_event_loop(move || {
+                let mut traffic = traffic.lock().unwrap();
+                let Some(window) = window.upgrade() else {
+                    return;
+                };
+
+                let flow = event.flow();
+                if let Some(index) = traffic
+                    .exchanges
+                    .iter()
+                    .position(|exchange| exchange.flow.id() == flow)
+                {
+                    traffic.exchanges[index].flow.apply(event);
+                    let exchange = &traffic.exchanges[index];
+                    let matches = traffic.matches(exchange);
+                    with_rows(&window, |rows| {
+                        match rows.iter().position(|row| row.id == exchange.id) {
+                            Some(row) if matches => {
+                                rows.set_row_data(row, traffic.record(exchange))
+                            }
+                            None if !matches => {}
+                            // 状态变化后可能不再符合筛选条件，或者刚刚符合
+                            _ => rows.set_vec(traffic.rows()),
+                        }
+                    });
+                    if traffic.selected == Some(exchange.id) {
+                        window.set_details(details(exchange));
+                    }
+                    window.set_timeline_span(traffic.timeline_span());
+                    return;
+                }
+
+                // 清空后还在进行中的请求不再显示
+                let Some(flow) = Flow::from_event(event) else {
+                    return;
+                };
+                let exchange = Exchange {
+                    id: traffic.next_id,
+                    flow,
+                };
+                traffic.next_id += 1;
+                traffic.origin.get_or_insert(exchange.flow.request().time());
+
+                if traffic.matches(&exchange) {
+                    with_rows(&window, |rows| rows.push(traffic.record(&exchange)));
+                }
+                traffic.exchanges.push(exchange);
+                window.set_timeline_span(traffic.timeline_span());
+            });
+            if pushed.is_err() {
+                break;
+            }
+        }
+    });
+
+    let window_weak = window.as_weak();
+    let filter_traffic = Arc::clone(&traffic);
+    window.on_filter_requests(move |method, status, host| {
+        let mut traffic = filter_traffic.lock().unwrap();
+        traffic.method = method.to_string();
+        traffic.status = status.to_string();
+        traffic.host = host.to_string();
+
+        if let Some(window) = window_weak.upgrade() {
+            with_rows(&window, |rows| rows.set_vec(traffic.rows()));
+
+            // 选中的请求被筛掉时关闭详情
+            let selected_visible = traffic.selected.is_some_and(|id| {
+                traffic
+                    .exchanges
+                    .iter()
+                    .any(|exchange| exchange.id == id && traffic.matches(exchange))
+            });
+            if !selected_visible {
+                traffic.selected = None;
+                window.set_selected_id(-1);
+            }
+        }
+    });
+
+    let window_weak = window.as_weak();
+    let clear_traffic = Arc::clone(&traffic);
+    window.on_clear_requests(move || {
+        let mut traffic = clear_traffic.lock().unwrap();
+        traffic.exchanges.clear();
+        traffic.selected = None;
+        traffic.origin = None;
+
+        if let Some(window) = window_weak.upgrade() {
+            with_rows(&window, |rows| rows.set_vec(Vec::new()));
+            window.set_selected_id(-1);
+            window.set_timeline_span(1.0);
+        }
+    });
+
+    let window_weak = window.as_weak();
+    let select_traffic = Arc::clone(&traffic);
+    window.on_select_request(move |id| {
+        let mut traffic = select_traffic.lock().unwrap();
+        let Some(window) = window_weak.upgrade() else {
+            return;
+        };
+
+        match traffic.exchanges.iter().find(|exchange| exchange.id == id) {
+            Some(exchange) => {
+                window.set_details(details(exchange));
+                window.set_selected_id(id);
+                traffic.selected = Some(id);
+            }
+            None => {
+                window.set_selected_id(-1);
+                traffic.selected = None;
+            }
+        }
+    });
+
+    let window_weak = window.as_weak();
+    window.on_delete_request(move |id| {
+        let mut traffic = traffic.lock().unwrap();
+        traffic.exchanges.retain(|exchange| exchange.id != id);
+
+        if let Some(window) = window_weak.upgrade() {
+            with_rows(&window, |rows| {
+                if let Some(row) = rows.iter().position(|row| row.id == id) {
+                    rows.remove(row);
+                }
+            });
+            if traffic.selected == Some(id) {
+                traffic.selected = None;
+                window.set_selected_id(-1);
+            }
+        }
+    });
+}
+
+fn with_rows(window: &MainWindow, f: impl FnOnce(&VecModel<RequestRecord>)) {
+    let rows = window.get_requests();
+    if let Some(rows) = rows.as_any().downcast_ref::<VecModel<RequestRecord>>() {
+        f(rows);
+    }
+}
+
+/// 请求在时间线上结束的时间
+fn end(flow: &Flow) -> i64 {
+    let start = flow.request().time();
+    let end = match flow.response() {
+        Some(response) => match response.timings() {
+            Some(timings) => timings.start() + timings.total(),
+            None => response.time(),
+        },
+        None => start,
+    };
+    end.max(start)
+}
+
+fn millis(nanos: i64) -> f32 {
+    nanos as f32 / 1e6
+}
+
+fn phase_color(phase: Phase) -> Color {
+    match phase {
+        Phase::Dns => Color::from_rgb_u8(0x1a, 0xbc, 0x9c),
+        Phase::Connect => Color::from_rgb_u8(0xe6, 0x7e, 0x22),
+        Phase::Tls => Color::from_rgb_u8(0x9b, 0x59, 0xb6),
+        Phase::Send => Color::from_rgb_u8(0x34, 0x98, 0xdb),
+        Phase::Wait => Color::from_rgb_u8(0x2e, 0xcc, 0x71),
+        Phase::Download => Color::from_rgb_u8(0x29, 0x80, 0xb9),
+    }
+}
+
+/// 瀑布图的各段；没有计时的请求只画一段，失败的是红色，进行中的是灰色
+fn timeline(flow: &Flow, origin: i64) -> Vec<TimelineSegment> {
+    if let Some(timings) = flow.response().and_then(|response| response.timings()) {
+        return timings
+            .phases()
+            .into_iter()
+            .map(|(phase, offset, duration)| TimelineSegment {
+                start: millis(timings.start() + offset - origin).max(0.0),
+                length: millis(duration),
+                color: phase_color(phase),
+            })
+            .collect();
+    }
+
+    let start = flow.request().time();
+    let color = match flow.state() {
+        FlowState::Failed => Color::from_rgb_u8(0xc8, 0x32, 0x32),
+        _ => Color::from_rgb_u8(0x88, 0x88, 0x88),
+    };
+    vec![TimelineSegment {
+        start: millis(start - origin).max(0.0),
+        length: millis(end(flow) - start),
+        color,
+    }]
+}
+
+fn record(exchange: &Exchange, origin: i64) -> RequestRecord {
+    let Exchange { id, flow } = exchange;
+    let request = flow.request();
+    let response = flow.response();
+
+    let status = match (flow.state(), response) {
+        (_, Some(response)) => response.status().as_str().to_owned(),
+        (_, None) => "…".to_owned(),
+    };
+    let size = match (flow.state(), response) {
+        (FlowState::Complete, Some(response)) => format_size(response.body().len()),
+        _ => "-".to_owned(),
+    };
+    let duration = match (flow.state(), response) {
+        (FlowState::Complete, Some(response)) => match response.timings() {
+            Some(timings) => format!("{} ms", timings.total() / 1_000_000),
+            None => format!("{} ms", (response.time() - request.time()) / 1_000_000),
+        },
+        _ => "-".to_owned(),
+    };
+
+    RequestRecord {
+        id: *id,
+        method: request.method().as_str().into(),
+        url: request.uri().to_string().into(),
+        status: status.into(),
+        pending: flow.state().is_pending(),
+        failed: flow.state() == FlowState::Failed,
+        size: size.into(),
+        duration: duration.into(),
+        timeline: ModelRc::new(VecModel::from(timeline(flow, origin))),
+    }
+}
+
+fn details(exchange: &Exchange) -> RequestDetails {
+    let flow = &exchange.flow;
+    let request = flow.request();
+
+    let mut req = client_summary(request.client(), request.body_size());
+    let _ = writeln!(
+        req,
+        "{} {} {:?}",
+        request.method(),
+        request.uri(),
+        request.version()
+    );
+    for (name, value) in request.headers() {
+        let _ = writeln!(
+            req,
+            "{}: {}",
+            name,
+            String::from_utf8_lossy(value.as_bytes())
+        );
+    }
+    push_body(&mut req, request.body(), request.truncated());
+
+    let Some(response) = flow.response() else {
+        let state = match flow.state() {
+            FlowState::Received => "Not forwarded yet",
+            _ => "Waiting for response…",
+        };
+        return RequestDetails {
+            request: req.into(),
+            response: state.into(),
+        };
+    };
+
+    let mut res = server_summary(response.server(), response.timings(), response.body_size());
+    let _ = writeln!(res, "{:?} {}", response.version(), response.status());
+    if let Some(error) = response.error() {
+        let _ = writeln!(res, "Error: {error}");
+    }
+    for (name, value) in response.headers() {
+        let _ = writeln!(
+            res,
+            "{}: {}",
+            name,
+            String::from_utf8_lossy(value.as_bytes())
+        );
+    }
+    if flow.state() == FlowState::Receiving {
+        res.push_str("\nReceiving body…");
+    }
+    push_body(&mut res, response.body(), response.truncated());
+
+    RequestDetails {
+        request: req.into(),
+        response: res.into(),
+    }
+}
+
+/// 客户端连接信息，旧版本的记录没有这些字段
+fn client_summary(client: &ClientInfo, size: Option<&BodySize>) -> String {
+    let mut text = String::new();
+    if let Some(addr) = client.addr() {
+        let _ = write!(text, "Client: {addr}");
+        if let Some(id) = client.connection_id() {
+            let _ = write!(text, " (connection #{id})");
+        }
+        text.push('\n');
+    }
+    if let Some(mode) = client.mode() {
+        let _ = writeln!(text, "Mode: {mode}");
+    }
+    if let Some(sni) = client.sni() {
+        let _ = writeln!(text, "SNI: {sni}");
+    }
+    if let Some(tls) = client.tls() {
+        let _ = writeln!(text, "TLS: {tls}");
+    }
+    push_size(&mut text, size);
+    text
+}
+
+fn server_summary(
+    server: &ServerInfo,
+    timings: Option<&Timings>,
+    size: Option<&BodySize>,
+) -> String {
+    let mut text = String::new();
+    if let Some(addr) = server.addr() {
+        let _ = writeln!(text, "Server: {addr}");
+    }
+    if let Some(tls) = server.tls() {
+        let _ = writeln!(text, "TLS: {tls}");
+    }
+    if let Some(timings) = timings {
+        let phases = timings
+            .phases()
+            .into_iter()
+            .map(|(phase, _, duration)| format!("{phase} {:.2} ms", millis(duration)))
+            .collect::<Vec<_>>();
+        let _ = writeln!(text, "Timing: {}", phases.join(", "));
+    }
+    push_size(&mut text, size);
+    text
+}
+
+/// 正文在线路上的大小和解码后的大小
+fn push_size(text: &mut String, size: Option<&BodySize>) {
+    if let Some(size) = size {
+        let _ = write!(text, "Size: {}", format_size(size.wire() as usize));
+        match size.decoded() {
+            Some(decoded) if decoded != size.wire() => {
+                let _ = writeln!(text, " ({} decoded)", format_size(decoded as usize));
+            }
+            _ => text.push('\n'),
+        }
+    }
+    if !text.is_empty() {
+        text.push('\n');
+    }
+}
+
+fn push_body(text: &mut String, body: &[u8], truncated: bool) {
+    if body.is_empty() {
+        return;
+    }
+
+    text.push('\n');
+    text.push_str(&String::from_utf8_lossy(
+        &body[..body.len().min(MAX_BODY_PREVIEW)],
+    ));
+    if body.len() > MAX_BODY_PREVIEW {
+        let _ = write!(
+            text,
+            "\n… {} bytes not shown",
+            body.len() - MAX_BODY_PREVIEW
+        );
+    }
+    if truncated {
+        let _ = write!(text, "\n(body truncated to {} bytes)", body.len());
+    }
+}
+
+fn format_size(len: usize) -> String {
+    match len {
+        len if len < 1024 => format!("{len} B"),
+        len if len < 1024 * 1024 => format!("{:.1} KB", len as f64 / 1024.0),
+        len => format!("{:.1} MB", len as f64 / (1024.0 * 1024.0)),
+    }
+}
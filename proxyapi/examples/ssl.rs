@@ -1,6 +1,9 @@
 use std::net::SocketAddr;
 
-use proxyapi::proxy::Proxy;
+use proxyapi::{
+    ca::{CaStore, Ssl},
+    NoopHandler, ProxyBuilder,
+};
 
 async fn shutdown_signal() {
     tokio::signal::ctrl_c()
@@ -10,10 +13,23 @@ async fn shutdown_signal() {
 
 #[tokio::main]
 async fn main() {
-    if let Err(e) = Proxy::new(SocketAddr::new([127, 0, 0, 1].into(), 8080), None)
-        .start(shutdown_signal())
-        .await
-    {
+    let ca = match Ssl::from_store(&CaStore::default()) {
+        Ok(ca) => ca,
+        Err(e) => {
+            eprintln!("Failed to load the root CA: {e}");
+            return;
+        }
+    };
+
+    let proxy = ProxyBuilder::new()
+        .with_addr(SocketAddr::new([127, 0, 0, 1].into(), 8080))
+        .with_rustls_client()
+        .with_ca(ca)
+        .with_http_handler(NoopHandler)
+        .with_websocket_handler(NoopHandler)
+        .build();
+
+    if let Err(e) = proxy.start(shutdown_signal()).await {
         eprintln!("{e}");
     }
 }
@@ -0,0 +1,87 @@
+use bytes::{Bytes, BytesMut};
+use hyper::{body::HttpBody, Body, HeaderMap};
+use proxyapi_models::BodySize;
+
+use crate::decoder;
+
+/// Part of a body kept while it streamed through the proxy.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Captured {
+    pub(crate) body: Bytes,
+    /// Whether the body was longer than the capture limit, or didn't stream to its end.
+    pub(crate) truncated: bool,
+    /// Bytes that streamed through, captured or not.
+    pub(crate) size: u64,
+}
+
+impl Captured {
+    /// Size of the body sent with `headers`, decoded only if it was captured whole.
+    pub(crate) fn body_size(&self, headers: &HeaderMap) -> BodySize {
+        let decoded = match self.size {
+            0 => Some(0),
+            _ if self.truncated => None,
+            _ => decoder::decoded_len(headers, &self.body).ok(),
+        };
+        BodySize::new(self.size, decoded)
+    }
+}
+
+/// Forwards `body` chunk by chunk while keeping its first `limit` bytes.
+///
+/// `on_end` is called with the captured bytes once the body ended, failed, or the side
+/// reading the returned body went away.
+pub(crate) fn tee<F>(mut body: Body, limit: usize, on_end: F) -> Body
+where
+    F: FnOnce(Captured) + Send + 'static,
+{
+    if body.is_end_stream() {
+        on_end(Captured::default());
+        return body;
+    }
+
+    let (mut sender, tee) = Body::channel();
+
+    tokio::spawn(async move {
+        let mut captured = BytesMut::new();
+        let mut truncated = false;
+        let mut size = 0;
+
+        loop {
+            match body.data().await {
+                Some(Ok(chunk)) => {
+                    size += chunk.len() as u64;
+                    let room = limit.saturating_sub(captured.len());
+                    if chunk.len() > room {
+                        truncated = true;
+                    }
+                    captured.extend_from_slice(&chunk[..chunk.len().min(room)]);
+
+                    if sender.send_data(chunk).await.is_err() {
+                        truncated = true;
+                        break;
+                    }
+                }
+                Some(Err(e)) => {
+                    eprintln!("Failed to read body: {e}");
+                    sender.abort();
+                    truncated = true;
+                    break;
+                }
+                None => {
+                    if let Ok(Some(trailers)) = body.trailers().await {
+                        let _ = sender.send_trailers(trailers).await;
+                    }
+                    break;
+                }
+            }
+        }
+
+        on_end(Captured {
+            body: captured.freeze(),
+            truncated,
+            size,
+        });
+    });
+
+    tee
+}
@@ -0,0 +1,79 @@
+use std::{sync::Arc, time::SystemTime};
+
+use http::uri::Authority;
+use openssl::x509::X509;
+use tokio::time::timeout;
+use tokio_rustls::{
+    rustls::{
+        self,
+        client::{ServerCertVerified, ServerCertVerifier},
+        Certificate, ClientConfig, ServerName,
+    },
+    TlsConnector,
+};
+
+use crate::upstream::UpstreamConnector;
+
+const CONNECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Client config accepting any certificate, it is only used to look at them.
+pub(super) fn client_config() -> Arc<ClientConfig> {
+    Arc::new(
+        ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(Arc::new(AcceptAny))
+            .with_no_client_auth(),
+    )
+}
+
+/// Connects to `authority` through `upstream` and returns the certificate it presents.
+pub(super) async fn upstream_cert(
+    config: Arc<ClientConfig>,
+    upstream: &UpstreamConnector,
+    authority: &Authority,
+) -> Option<X509> {
+    let host = authority
+        .host()
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    let port = authority.port_u16().unwrap_or(443);
+    let server_name = ServerName::try_from(host).ok()?;
+
+    let handshake = async {
+        let stream = upstream.connect(host, port).await?;
+        TlsConnector::from(config)
+            .connect(server_name, stream)
+            .await
+    };
+
+    let stream = match timeout(CONNECT_TIMEOUT, handshake).await {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(e)) => {
+            eprintln!("Failed to read the certificate of {authority}: {e}");
+            return None;
+        }
+        Err(_) => {
+            eprintln!("Timed out reading the certificate of {authority}");
+            return None;
+        }
+    };
+
+    let cert = stream.get_ref().1.peer_certificates()?.first()?;
+    X509::from_der(&cert.0).ok()
+}
+
+struct AcceptAny;
+
+impl ServerCertVerifier for AcceptAny {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
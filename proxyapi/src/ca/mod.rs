@@ -1,5 +1,11 @@
 use std::{
-    sync::Arc,
+    fs,
+    net::IpAddr,
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
     time::{Duration, SystemTime},
 };
 
@@ -9,84 +15,326 @@ use moka::future::Cache;
 use openssl::{
     asn1::{Asn1Integer, Asn1Time},
     bn::BigNum,
+    ec::{EcGroup, EcKey},
     error::ErrorStack,
     hash::MessageDigest,
+    nid::Nid,
+    pkcs12::Pkcs12,
     pkey::{PKey, Private},
     rand,
-    x509::{extension::SubjectAlternativeName, X509Builder, X509NameBuilder, X509},
+    x509::{
+        extension::SubjectAlternativeName, X509Builder, X509NameBuilder, X509VerifyResult, X509,
+    },
 };
 use tokio_rustls::rustls::{self, ServerConfig};
 
+use crate::{error::Error, upstream::UpstreamConnector};
+
+mod mimic;
+mod public_suffix;
+mod store;
+#[cfg(target_os = "linux")]
+pub mod trust;
+
+pub use store::CaStore;
+
 const TTL_SECS: i64 = 365 * 24 * 60 * 60;
 const CACHE_TTL: u64 = TTL_SECS as u64 / 2;
 const NOT_BEFORE_OFFSET: i64 = 60;
+const MAX_CN_LEN: usize = 64;
+/// Leaf certificates share a few keys instead of generating one each.
+const LEAF_KEY_POOL_SIZE: usize = 8;
 
 #[async_trait]
 pub trait CertificateAuthority: Send + Sync + 'static {
-    async fn gen_server_config(&self, authority: &Authority) -> Arc<ServerConfig>;
+    /// TLS configuration presenting a certificate for `authority` to intercepted clients.
+    async fn gen_server_config(&self, authority: &Authority) -> Result<Arc<ServerConfig>, Error>;
+
+    /// DER encoded certificate clients have to trust, offered for download on the onboarding
+    /// page at `http://proxelar.local/`.
+    ///
+    /// That is the root of the chain sent with generated certificates, which is not the signing
+    /// certificate when the CA is an intermediate.
+    fn ca_cert_der(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Routes the connections the authority makes to upstream servers, e.g. to read their
+    /// certificates, through `upstream`.
+    ///
+    /// [`ProxyBuilder::with_ca`](crate::ProxyBuilder::with_ca) passes the proxy's own upstream
+    /// configuration.
+    fn with_upstream(self, _upstream: UpstreamConnector) -> Self
+    where
+        Self: Sized,
+    {
+        self
+    }
 }
 
 #[derive(Clone)]
 pub struct Ssl {
     pkey: PKey<Private>,
-    private_key: rustls::PrivateKey,
     ca_cert: X509,
+    chain: Vec<rustls::Certificate>,
+    leaf_keys: Arc<Vec<(PKey<Private>, rustls::PrivateKey)>>,
+    next_leaf_key: Arc<AtomicUsize>,
+    wildcards: bool,
+    mimic: Option<Arc<rustls::ClientConfig>>,
+    upstream: UpstreamConnector,
     hash: MessageDigest,
-    cache: Cache<Authority, Arc<ServerConfig>>,
+    cache: Cache<String, Arc<ServerConfig>>,
 }
 
-impl Default for Ssl {
-    fn default() -> Self {
-        let private_key_bytes: &[u8] = include_bytes!("proxelar.key");
-        let ca_cert_bytes: &[u8] = include_bytes!("proxelar.cer");
+impl Ssl {
+    /// Creates a CA from its PEM encoded certificate and private key.
+    ///
+    /// Intermediate certificates following the CA certificate in `cert_pem` are sent along with
+    /// every generated certificate.
+    pub fn new(cert_pem: &[u8], key_pem: &[u8]) -> Result<Self, Error> {
+        let mut certs = X509::stack_from_pem(cert_pem).map_err(invalid)?.into_iter();
+        let ca_cert = certs
+            .next()
+            .ok_or_else(|| Error::InvalidCertificate("no certificate found in PEM".to_owned()))?;
+        let pkey = PKey::private_key_from_pem(key_pem).map_err(invalid)?;
 
-        let pkey =
-            PKey::private_key_from_pem(private_key_bytes).expect("Failed to parse private key");
+        Self::from_parts(pkey, ca_cert, certs.collect())
+    }
 
-        let private_key = rustls::PrivateKey(
-            pkey.private_key_to_der()
-                .expect("Failed to encode private key"),
-        );
+    /// Creates a CA from the PEM files at `cert` and `key`, see [`Ssl::new`].
+    pub fn from_pem_files(cert: impl AsRef<Path>, key: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::new(&fs::read(cert)?, &fs::read(key)?)
+    }
+
+    /// Creates a CA from its DER encoded certificate and private key, in PKCS#8 or the key
+    /// type's traditional format.
+    pub fn from_der(cert_der: &[u8], key_der: &[u8]) -> Result<Self, Error> {
+        let ca_cert = X509::from_der(cert_der).map_err(invalid)?;
+        let pkey = PKey::private_key_from_der(key_der)
+            .or_else(|_| PKey::private_key_from_pkcs8(key_der))
+            .map_err(invalid)?;
+
+        Self::from_parts(pkey, ca_cert, Vec::new())
+    }
 
-        let ca_cert = X509::from_pem(ca_cert_bytes).expect("Failed to parse CA certificate");
+    /// Creates a CA from the DER files at `cert` and `key`, see [`Ssl::from_der`].
+    pub fn from_der_files(cert: impl AsRef<Path>, key: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::from_der(&fs::read(cert)?, &fs::read(key)?)
+    }
+
+    /// Creates a CA from a PKCS#12 archive protected by `password`.
+    ///
+    /// The other certificates in the archive are sent along with every generated certificate.
+    pub fn from_pkcs12(der: &[u8], password: &str) -> Result<Self, Error> {
+        let parsed = Pkcs12::from_der(der)
+            .and_then(|pkcs12| pkcs12.parse2(password))
+            .map_err(invalid)?;
+
+        let (pkey, ca_cert) = match (parsed.pkey, parsed.cert) {
+            (Some(pkey), Some(cert)) => (pkey, cert),
+            _ => {
+                return Err(Error::InvalidCertificate(
+                    "PKCS#12 archive has no certificate and private key".to_owned(),
+                ))
+            }
+        };
+        let chain = parsed
+            .ca
+            .map_or_else(Vec::new, |ca| ca.into_iter().collect());
+
+        Self::from_parts(pkey, ca_cert, chain)
+    }
+
+    /// Creates a CA from the PKCS#12 file at `path`, see [`Ssl::from_pkcs12`].
+    pub fn from_pkcs12_file(path: impl AsRef<Path>, password: &str) -> Result<Self, Error> {
+        Self::from_pkcs12(&fs::read(path)?, password)
+    }
+
+    /// Sends the intermediate certificates in `pem` along with every generated certificate.
+    ///
+    /// They are sent in order after the CA certificate, each one should certify the previous.
+    pub fn with_chain_pem(mut self, pem: &[u8]) -> Result<Self, Error> {
+        for cert in X509::stack_from_pem(pem).map_err(invalid)? {
+            self.chain
+                .push(rustls::Certificate(cert.to_der().map_err(invalid)?));
+        }
+        Ok(self)
+    }
+
+    /// Sends the DER encoded intermediate certificate along with every generated certificate,
+    /// see [`Ssl::with_chain_pem`].
+    pub fn with_chain_der(mut self, der: &[u8]) -> Result<Self, Error> {
+        X509::from_der(der).map_err(invalid)?;
+        self.chain.push(rustls::Certificate(der.to_vec()));
+        Ok(self)
+    }
+
+    /// Issues wildcard certificates for the parent domain of intercepted hosts, so e.g.
+    /// `a.example.com` and `b.example.com` share a certificate for `*.example.com`.
+    ///
+    /// Hosts right under a public suffix, e.g. `example.com`, `example.co.uk` or
+    /// `user.github.io`, and IP addresses still get their own certificate.
+    pub fn with_wildcards(mut self, wildcards: bool) -> Self {
+        self.wildcards = wildcards;
+        self
+    }
 
-        Self {
+    /// Copies the subject, SANs and validity of the real certificate into generated ones.
+    ///
+    /// Before a host's certificate is generated the proxy connects to it, through the parent
+    /// proxy the upstream configuration routes it to if any, and reads the certificate it
+    /// presents, falling back to a plain certificate if that fails. Wildcards are not used with
+    /// this option.
+    pub fn with_upstream_mimicry(mut self, mimic: bool) -> Self {
+        self.mimic = mimic.then(mimic::client_config);
+        self
+    }
+
+    fn from_parts(
+        pkey: PKey<Private>,
+        ca_cert: X509,
+        intermediates: Vec<X509>,
+    ) -> Result<Self, Error> {
+        if !ca_cert.public_key().map_err(invalid)?.public_eq(&pkey) {
+            return Err(Error::InvalidCertificate(
+                "private key does not match the CA certificate".to_owned(),
+            ));
+        }
+
+        // Clients only trust the root, so an intermediate CA has to be sent too.
+        let mut chain = Vec::new();
+        if ca_cert.issued(&ca_cert) != X509VerifyResult::OK {
+            chain.push(rustls::Certificate(ca_cert.to_der().map_err(invalid)?));
+        }
+        for cert in intermediates {
+            chain.push(rustls::Certificate(cert.to_der().map_err(invalid)?));
+        }
+
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).map_err(invalid)?;
+        let leaf_keys = (0..LEAF_KEY_POOL_SIZE)
+            .map(|_| {
+                let pkey = PKey::from_ec_key(EcKey::generate(&group)?)?;
+                let private_key = rustls::PrivateKey(pkey.private_key_to_pkcs8()?);
+                Ok((pkey, private_key))
+            })
+            .collect::<Result<_, ErrorStack>>()
+            .map_err(invalid)?;
+
+        Ok(Self {
             pkey,
-            private_key,
             ca_cert,
+            chain,
+            leaf_keys: Arc::new(leaf_keys),
+            next_leaf_key: Arc::new(AtomicUsize::new(0)),
+            wildcards: false,
+            mimic: None,
+            upstream: UpstreamConnector::new(Arc::default()),
             hash: MessageDigest::sha256(),
             cache: Cache::builder()
                 .max_capacity(1_000)
                 .time_to_live(Duration::from_secs(CACHE_TTL))
                 .build(),
-        }
+        })
     }
-}
 
-impl Ssl {
-    fn gen_cert(&self, authority: &Authority) -> Result<rustls::Certificate, ErrorStack> {
-        let mut name_builder = X509NameBuilder::new()?;
-        name_builder.append_entry_by_text("CN", authority.host())?;
-        let name = name_builder.build();
+    /// Loads the CA persisted in `store`, generating it if there is none yet.
+    pub fn from_store(store: &CaStore) -> Result<Self, Error> {
+        let (cert, key) = store.load_or_generate()?;
+        Self::new(cert.as_bytes(), key.as_bytes())
+    }
+
+    /// Replaces the CA persisted in `store` with a new one and returns it.
+    ///
+    /// Proxies keep using the CA they were built with, so restart them with the returned one.
+    pub fn regenerate(store: &CaStore) -> Result<Self, Error> {
+        let (cert, key) = store.regenerate()?;
+        Self::new(cert.as_bytes(), key.as_bytes())
+    }
 
+    /// Name the certificate for `host` is issued for, either the host itself or a wildcard.
+    fn cert_name(&self, host: &str) -> String {
+        let host = host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_ascii_lowercase();
+
+        if self.wildcards && self.mimic.is_none() && host.parse::<IpAddr>().is_err() {
+            // Browsers reject wildcards right under a public suffix like `*.co.uk`, and the
+            // certificate would be shared by unrelated sites.
+            if let Some((_, parent)) = host.split_once('.') {
+                if !public_suffix::is_public_suffix(parent) {
+                    return format!("*.{parent}");
+                }
+            }
+        }
+
+        host
+    }
+
+    /// Generates a certificate for `name`, copying the subject, SANs and validity of the
+    /// `upstream` certificate if there is one.
+    fn gen_cert(
+        &self,
+        name: &str,
+        key: &PKey<Private>,
+        upstream: Option<&X509>,
+    ) -> Result<rustls::Certificate, ErrorStack> {
         let mut x509_builder = X509Builder::new()?;
-        x509_builder.set_subject_name(&name)?;
         x509_builder.set_version(2)?;
 
-        let not_before = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .expect("Failed to determine current UNIX time")
-            .as_secs() as i64
-            - NOT_BEFORE_OFFSET;
-        x509_builder.set_not_before(Asn1Time::from_unix(not_before)?.as_ref())?;
-        x509_builder.set_not_after(Asn1Time::from_unix(not_before + TTL_SECS)?.as_ref())?;
+        let mut names = vec![name.to_owned()];
+
+        if let Some(upstream) = upstream {
+            x509_builder.set_subject_name(upstream.subject_name())?;
+            x509_builder.set_not_before(upstream.not_before())?;
+            x509_builder.set_not_after(upstream.not_after())?;
+
+            for alt_name in upstream.subject_alt_names().into_iter().flatten() {
+                let alt_name = match (alt_name.dnsname(), alt_name.ipaddress()) {
+                    (Some(dns), _) => dns.to_owned(),
+                    (_, Some(ip)) => match <[u8; 4]>::try_from(ip) {
+                        Ok(ip) => IpAddr::from(ip).to_string(),
+                        Err(_) => match <[u8; 16]>::try_from(ip) {
+                            Ok(ip) => IpAddr::from(ip).to_string(),
+                            Err(_) => continue,
+                        },
+                    },
+                    _ => continue,
+                };
+                if !names.contains(&alt_name) {
+                    names.push(alt_name);
+                }
+            }
+        } else {
+            let mut name_builder = X509NameBuilder::new()?;
+            // Longer names don't fit in a CN, clients only look at the SAN anyway.
+            if name.len() <= MAX_CN_LEN {
+                name_builder.append_entry_by_text("CN", name)?;
+            }
+            x509_builder.set_subject_name(&name_builder.build())?;
 
-        x509_builder.set_pubkey(&self.pkey)?;
+            let not_before = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .expect("Failed to determine current UNIX time")
+                .as_secs() as i64
+                - NOT_BEFORE_OFFSET;
+            x509_builder.set_not_before(Asn1Time::from_unix(not_before)?.as_ref())?;
+            x509_builder.set_not_after(Asn1Time::from_unix(not_before + TTL_SECS)?.as_ref())?;
+        }
+
+        x509_builder.set_pubkey(key)?;
         x509_builder.set_issuer_name(self.ca_cert.subject_name())?;
 
-        let alternative_name = SubjectAlternativeName::new()
-            .dns(authority.host())
-            .build(&x509_builder.x509v3_context(Some(&self.ca_cert), None))?;
+        let mut alternative_name = SubjectAlternativeName::new();
+        for name in &names {
+            if name.parse::<IpAddr>().is_ok() {
+                alternative_name.ip(name);
+            } else {
+                alternative_name.dns(name);
+            }
+        }
+        let alternative_name =
+            alternative_name.build(&x509_builder.x509v3_context(Some(&self.ca_cert), None))?;
         x509_builder.append_extension(alternative_name)?;
 
         let mut serial_number = [0; 16];
@@ -102,24 +350,40 @@ impl Ssl {
     }
 }
 
+fn invalid(err: ErrorStack) -> Error {
+    Error::InvalidCertificate(err.to_string())
+}
+
 #[async_trait]
 impl CertificateAuthority for Ssl {
-    async fn gen_server_config(&self, authority: &Authority) -> Arc<ServerConfig> {
-        if let Some(server_cfg) = self.cache.get(authority) {
+    async fn gen_server_config(&self, authority: &Authority) -> Result<Arc<ServerConfig>, Error> {
+        let name = self.cert_name(authority.host());
+        if let Some(server_cfg) = self.cache.get(&name) {
             println!("Using cached server config");
-            return server_cfg;
+            return Ok(server_cfg);
         }
         println!("Generating server config");
 
-        let certs = vec![self
-            .gen_cert(authority)
-            .unwrap_or_else(|_| panic!("Failed to generate certificate for {}", authority))];
+        let index = self.next_leaf_key.fetch_add(1, Ordering::Relaxed) % self.leaf_keys.len();
+        let (key, private_key) = &self.leaf_keys[index];
+
+        let upstream = match &self.mimic {
+            Some(config) => {
+                mimic::upstream_cert(Arc::clone(config), &self.upstream, authority).await
+            }
+            None => None,
+        };
+
+        let mut certs = vec![self
+            .gen_cert(&name, key, upstream.as_ref())
+            .map_err(|e| Error::LeafCertificate(name.clone(), e.to_string()))?];
+        certs.extend(self.chain.iter().cloned());
 
         let mut server_cfg = ServerConfig::builder()
             .with_safe_defaults()
             .with_no_client_auth()
-            .with_single_cert(certs, self.private_key.clone())
-            .expect("Failed to build ServerConfig");
+            .with_single_cert(certs, private_key.clone())
+            .map_err(|e| Error::LeafCertificate(name.clone(), e.to_string()))?;
 
         server_cfg.alpn_protocols = vec![
             #[cfg(feature = "http2")]
@@ -129,10 +393,243 @@ impl CertificateAuthority for Ssl {
 
         let server_cfg = Arc::new(server_cfg);
 
-        self.cache
-            .insert(authority.clone(), Arc::clone(&server_cfg))
-            .await;
+        self.cache.insert(name, Arc::clone(&server_cfg)).await;
+
+        Ok(server_cfg)
+    }
+
+    fn ca_cert_der(&self) -> Option<Vec<u8>> {
+        // The CA may be an intermediate, clients have to trust the top of its chain.
+        match self.chain.last() {
+            Some(cert) => Some(cert.0.clone()),
+            None => self.ca_cert.to_der().ok(),
+        }
+    }
+
+    fn with_upstream(self, upstream: UpstreamConnector) -> Self {
+        Self { upstream, ..self }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::upstream::{UpstreamConfig, UpstreamProxy};
+    use tempfile::TempDir;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+    use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+    fn ssl() -> Ssl {
+        let dir = TempDir::new().unwrap();
+        Ssl::from_store(&CaStore::new(dir.path())).unwrap()
+    }
+
+    fn issued(ssl: &Ssl, name: &str) -> X509 {
+        let key = &ssl.leaf_keys[0].0;
+        X509::from_der(&ssl.gen_cert(name, key, None).unwrap().0).unwrap()
+    }
+
+    #[test]
+    fn wildcard_boundaries() {
+        let ssl = ssl().with_wildcards(true);
+        let cases = [
+            ("www.example.com", "*.example.com"),
+            ("a.b.example.com", "*.b.example.com"),
+            ("example.com", "example.com"),
+            ("foo.co.uk", "foo.co.uk"),
+            ("www.foo.co.uk", "*.foo.co.uk"),
+            ("a.github.io", "a.github.io"),
+            ("b.a.github.io", "*.a.github.io"),
+            ("WWW.Example.COM", "*.example.com"),
+            ("intranet.corp", "intranet.corp"),
+            ("127.0.0.1", "127.0.0.1"),
+            ("[::1]", "::1"),
+        ];
+
+        for (host, expected) in cases {
+            assert_eq!(ssl.cert_name(host), expected, "{host}");
+        }
+
+        let ssl = ssl.with_wildcards(false);
+        assert_eq!(ssl.cert_name("www.example.com"), "www.example.com");
+    }
+
+    #[test]
+    fn ip_sans() {
+        let ssl = ssl();
 
-        server_cfg
+        for (name, octets) in [
+            ("192.0.2.1", vec![192, 0, 2, 1]),
+            ("2001:db8::1", {
+                let mut octets = vec![0x20, 0x01, 0x0d, 0xb8];
+                octets.extend([0; 11]);
+                octets.push(1);
+                octets
+            }),
+        ] {
+            let cert = issued(&ssl, name);
+            let sans = cert.subject_alt_names().unwrap();
+
+            assert_eq!(sans.len(), 1, "{name}");
+            assert_eq!(
+                sans.get(0).unwrap().ipaddress(),
+                Some(&octets[..]),
+                "{name}"
+            );
+            assert_eq!(sans.get(0).unwrap().dnsname(), None, "{name}");
+        }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn dns_sans() {
+        let cert = issued(&ssl(), "*.example.com");
+        let sans = cert.subject_alt_names().unwrap();
+
+        assert_eq!(sans.len(), 1);
+        assert_eq!(sans.get(0).unwrap().dnsname(), Some("*.example.com"));
+    }
+
+    #[tokio::test]
+    async fn reuses_cached_configs() {
+        let ssl = ssl().with_wildcards(true);
+        let config = |authority: &'static str| {
+            let ssl = ssl.clone();
+            async move {
+                ssl.gen_server_config(&authority.parse().unwrap())
+                    .await
+                    .unwrap()
+            }
+        };
+
+        let www = config("www.example.com:443").await;
+        assert!(Arc::ptr_eq(&www, &config("www.example.com:443").await));
+        assert!(Arc::ptr_eq(&www, &config("api.example.com:8443").await));
+        assert!(!Arc::ptr_eq(&www, &config("example.com:443").await));
+
+        let foo = config("foo.co.uk:443").await;
+        assert!(!Arc::ptr_eq(&foo, &config("bar.co.uk:443").await));
+        assert!(!Arc::ptr_eq(
+            &config("a.github.io:443").await,
+            &config("b.github.io:443").await
+        ));
+    }
+
+    /// Serves one TLS connection with a certificate for `mimic.test` and `127.0.0.1`.
+    async fn start_upstream() -> (u16, rcgen::Certificate) {
+        let mut params =
+            rcgen::CertificateParams::new(vec!["mimic.test".to_owned(), "127.0.0.1".to_owned()]);
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "Mimic Test");
+        params
+            .distinguished_name
+            .push(rcgen::DnType::OrganizationName, "Proxelar Tests");
+        let cert = rcgen::Certificate::from_params(params).unwrap();
+
+        let config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![rustls::Certificate(cert.serialize_der().unwrap())],
+                rustls::PrivateKey(cert.serialize_private_key_der()),
+            )
+            .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let _ = acceptor.accept(stream).await;
+        });
+
+        (port, cert)
+    }
+
+    /// HTTP parent proxy relaying a single `CONNECT` to `127.0.0.1:port`, returning its target.
+    async fn start_parent(port: u16) -> (String, tokio::task::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+
+        let relaying = tokio::spawn(async move {
+            let (mut client, _) = listener.accept().await.unwrap();
+            let mut head = Vec::new();
+            while !head.ends_with(b"\r\n\r\n") {
+                head.push(client.read_u8().await.unwrap());
+            }
+            client
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                .await
+                .unwrap();
+
+            let mut server = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+            tokio::spawn(async move {
+                let _ = tokio::io::copy_bidirectional(&mut client, &mut server).await;
+            });
+
+            let head = String::from_utf8(head).unwrap();
+            head.split_whitespace().nth(1).unwrap().to_owned()
+        });
+
+        (addr, relaying)
+    }
+
+    /// Certificate presented with `config`.
+    async fn presented(config: Arc<ServerConfig>) -> X509 {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(async move { TlsAcceptor::from(config).accept(server).await });
+
+        let stream = TlsConnector::from(mimic::client_config())
+            .connect("mimic.test".try_into().unwrap(), client)
+            .await
+            .unwrap();
+        let cert = &stream.get_ref().1.peer_certificates().unwrap()[0];
+        X509::from_der(&cert.0).unwrap()
+    }
+
+    #[tokio::test]
+    async fn mimics_upstream_through_parent_proxy() {
+        let (port, upstream) = start_upstream().await;
+        let (parent, target) = start_parent(port).await;
+        let upstream_config = UpstreamConfig::new().with_default(UpstreamProxy::Http {
+            addr: parent,
+            auth: None,
+        });
+
+        // `mimic.test` doesn't resolve, only the parent proxy can reach it.
+        let ssl = ssl()
+            .with_upstream_mimicry(true)
+            .with_upstream(UpstreamConnector::new(Arc::new(upstream_config)));
+        let config = ssl
+            .gen_server_config(&format!("mimic.test:{port}").parse().unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(target.await.unwrap(), format!("mimic.test:{port}"));
+
+        let cert = presented(config).await;
+        let upstream = X509::from_der(&upstream.serialize_der().unwrap()).unwrap();
+        assert_eq!(
+            cert.subject_name().to_der().unwrap(),
+            upstream.subject_name().to_der().unwrap()
+        );
+        assert_eq!(
+            cert.issuer_name().to_der().unwrap(),
+            ssl.ca_cert.subject_name().to_der().unwrap()
+        );
+
+        let sans = cert.subject_alt_names().unwrap();
+        let sans: Vec<_> = sans
+            .iter()
+            .map(|name| match (name.dnsname(), name.ipaddress()) {
+                (Some(dns), _) => dns.to_owned(),
+                (_, Some(ip)) => format!("{ip:?}"),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(sans, ["mimic.test", "[127, 0, 0, 1]"]);
+    }
+}
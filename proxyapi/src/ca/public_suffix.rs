@@ -0,0 +1,96 @@
+use std::{collections::HashSet, sync::OnceLock};
+
+/// Copy of https://publicsuffix.org/list/public_suffix_list.dat, both its ICANN and private
+/// sections.
+const LIST: &str = include_str!("public_suffix_list.dat");
+
+struct Rules {
+    suffixes: HashSet<String>,
+    /// Domains whose every subdomain is a public suffix, from `*.` rules.
+    wildcards: HashSet<String>,
+    /// Exceptions to wildcard rules, from `!` rules.
+    exceptions: HashSet<String>,
+}
+
+fn rules() -> &'static Rules {
+    static RULES: OnceLock<Rules> = OnceLock::new();
+
+    RULES.get_or_init(|| {
+        let mut rules = Rules {
+            suffixes: HashSet::new(),
+            wildcards: HashSet::new(),
+            exceptions: HashSet::new(),
+        };
+
+        for line in LIST.lines() {
+            let Some(rule) = line.split_whitespace().next() else {
+                continue;
+            };
+            if rule.starts_with("//") {
+                continue;
+            }
+
+            // Hosts are matched in their ASCII form, the list has internationalized names.
+            let Ok(rule) = idna::domain_to_ascii(rule) else {
+                continue;
+            };
+
+            if let Some(domain) = rule.strip_prefix("*.") {
+                rules.wildcards.insert(domain.to_owned());
+            } else if let Some(domain) = rule.strip_prefix('!') {
+                rules.exceptions.insert(domain.to_owned());
+            } else {
+                rules.suffixes.insert(rule);
+            }
+        }
+
+        rules
+    })
+}
+
+/// Whether `domain`, lowercase and in its ASCII form, is a public suffix such as `com`,
+/// `co.uk` or `github.io`, under which unrelated parties register names.
+pub(crate) fn is_public_suffix(domain: &str) -> bool {
+    let rules = rules();
+
+    if rules.exceptions.contains(domain) {
+        return false;
+    }
+
+    rules.suffixes.contains(domain)
+        || domain
+            .split_once('.')
+            .is_some_and(|(_, parent)| rules.wildcards.contains(parent))
+        // Unlisted top-level domains are public suffixes too.
+        || !domain.contains('.')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_rules() {
+        let cases = [
+            ("com", true),
+            ("example.com", false),
+            ("uk", true),
+            ("co.uk", true),
+            ("foo.co.uk", false),
+            ("github.io", true),
+            ("a.github.io", false),
+            // *.ck with an exception for www.ck
+            ("anything.ck", true),
+            ("www.ck", false),
+            ("a.anything.ck", false),
+            // Internationalized rule 公司.cn
+            ("xn--55qx5d.cn", true),
+            ("unlisted-tld", true),
+            ("example.unlisted-tld", false),
+        ];
+
+        for (domain, expected) in cases {
+            assert_eq!(is_public_suffix(domain), expected, "{domain}");
+        }
+    }
+}
@@ -0,0 +1,277 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use rcgen::{
+    BasicConstraints, CertificateParams, DistinguishedName, DnType, IsCa, KeyUsagePurpose,
+};
+use time::{Duration, OffsetDateTime};
+
+use crate::error::Error;
+
+const CERT_FILE: &str = "ca.crt";
+const KEY_FILE: &str = "ca.key";
+/// Suffix of the files the CA is written to before they replace the current ones.
+const TMP_SUFFIX: &str = ".tmp";
+const VALIDITY_DAYS: i64 = 10 * 365;
+
+/// Directory holding the root CA, its certificate in `ca.crt` and private key in `ca.key`,
+/// both PEM encoded.
+///
+/// The CA is generated the first time it is loaded, so each user gets their own key. The
+/// directory is only accessible to its owner and the key only readable by them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CaStore {
+    dir: PathBuf,
+}
+
+impl CaStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// The `proxelar` directory in the platform data directory, e.g. `~/.local/share/proxelar`
+    /// on Linux, `~/Library/Application Support/proxelar` on macOS and `%APPDATA%\proxelar` on
+    /// Windows.
+    pub fn default_dir() -> Option<PathBuf> {
+        dirs_next::data_dir().map(|dir| dir.join("proxelar"))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn cert_path(&self) -> PathBuf {
+        self.dir.join(CERT_FILE)
+    }
+
+    pub fn key_path(&self) -> PathBuf {
+        self.dir.join(KEY_FILE)
+    }
+
+    /// Returns the PEM encoded certificate and private key, generating them if they don't exist
+    /// yet.
+    ///
+    /// A CA whose certificate or key alone went missing is an error rather than regenerated,
+    /// users may trust its certificate already.
+    pub fn load_or_generate(&self) -> Result<(String, String), Error> {
+        self.recover()?;
+
+        match (
+            fs::read_to_string(self.cert_path()),
+            fs::read_to_string(self.key_path()),
+        ) {
+            (Ok(cert), Ok(key)) => Ok((cert, key)),
+            (Err(cert), Err(key))
+                if cert.kind() == io::ErrorKind::NotFound
+                    && key.kind() == io::ErrorKind::NotFound =>
+            {
+                self.regenerate()
+            }
+            (Ok(_), Err(e)) if e.kind() == io::ErrorKind::NotFound => {
+                Err(self.incomplete(KEY_FILE))
+            }
+            (Err(e), Ok(_)) if e.kind() == io::ErrorKind::NotFound => {
+                Err(self.incomplete(CERT_FILE))
+            }
+            (Err(e), _) | (_, Err(e)) => Err(e.into()),
+        }
+    }
+
+    /// Replaces the CA with a newly generated one and returns its PEM encoded certificate and
+    /// private key.
+    ///
+    /// Certificates signed by the previous CA stop being trusted once it is removed from the
+    /// trust stores it was installed in.
+    pub fn regenerate(&self) -> Result<(String, String), Error> {
+        let (cert, key) = generate()?;
+
+        // Both are written aside and then renamed over the current files, the certificate last,
+        // so an interruption never leaves a mismatched pair behind, see `recover`.
+        create_private_dir(&self.dir)?;
+        let (cert_tmp, key_tmp) = (tmp_path(&self.cert_path()), tmp_path(&self.key_path()));
+        write_private(&key_tmp, &key)?;
+        write_synced(&cert_tmp, &cert)?;
+        fs::rename(key_tmp, self.key_path())?;
+        fs::rename(cert_tmp, self.cert_path())?;
+
+        Ok((cert, key))
+    }
+
+    /// Finishes or rolls back a `regenerate` that was interrupted.
+    fn recover(&self) -> io::Result<()> {
+        let (cert_tmp, key_tmp) = (tmp_path(&self.cert_path()), tmp_path(&self.key_path()));
+
+        if key_tmp.exists() {
+            // Interrupted before anything was replaced, the current pair is intact.
+            remove_if_exists(&key_tmp)?;
+            remove_if_exists(&cert_tmp)
+        } else if cert_tmp.exists() {
+            // Interrupted between the renames, the new key is in place already.
+            fs::rename(cert_tmp, self.cert_path())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn incomplete(&self, missing: &str) -> Error {
+        Error::InvalidCertificate(format!(
+            "`{missing}` is missing from {}, restore it or regenerate the CA",
+            self.dir.display()
+        ))
+    }
+}
+
+impl Default for CaStore {
+    /// Uses [`CaStore::default_dir`], or a `proxelar` directory in the working directory on
+    /// platforms without a data directory.
+    fn default() -> Self {
+        Self::new(Self::default_dir().unwrap_or_else(|| PathBuf::from("proxelar")))
+    }
+}
+
+fn generate() -> Result<(String, String), Error> {
+    let mut distinguished_name = DistinguishedName::new();
+    distinguished_name.push(DnType::CommonName, "proxelar");
+    distinguished_name.push(DnType::OrganizationName, "Proxelar");
+
+    let now = OffsetDateTime::now_utc();
+
+    let mut params = CertificateParams::default();
+    params.distinguished_name = distinguished_name;
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    params.key_usages = vec![
+        KeyUsagePurpose::KeyCertSign,
+        KeyUsagePurpose::CrlSign,
+        KeyUsagePurpose::DigitalSignature,
+    ];
+    params.not_before = now - Duration::days(1);
+    params.not_after = now + Duration::days(VALIDITY_DAYS);
+
+    let cert = rcgen::Certificate::from_params(params)?;
+
+    Ok((cert.serialize_pem()?, cert.serialize_private_key_pem()))
+}
+
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(TMP_SUFFIX);
+    PathBuf::from(name)
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Writes `contents` to `path` and waits for them to reach the disk.
+fn write_synced(path: &Path, contents: &str) -> io::Result<()> {
+    let mut file = fs::File::create(path)?;
+    io::Write::write_all(&mut file, contents.as_bytes())?;
+    file.sync_all()
+}
+
+#[cfg(unix)]
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)?;
+    fs::set_permissions(dir, fs::Permissions::from_mode(0o700))
+}
+
+#[cfg(not(unix))]
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)
+}
+
+#[cfg(unix)]
+fn write_private(path: &Path, contents: &str) -> io::Result<()> {
+    use std::{
+        io::Write,
+        os::unix::fs::{OpenOptionsExt, PermissionsExt},
+    };
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    // The mode only applies to new files.
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, contents: &str) -> io::Result<()> {
+    write_synced(path, contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn generates_once() {
+        let dir = TempDir::new().unwrap();
+        let store = CaStore::new(dir.path().join("ca"));
+
+        let generated = store.load_or_generate().unwrap();
+        assert_eq!(store.load_or_generate().unwrap(), generated);
+        assert_eq!(fs::read_dir(store.dir()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn rolls_back_interrupted_writes() {
+        let dir = TempDir::new().unwrap();
+        let store = CaStore::new(dir.path());
+        let current = store.load_or_generate().unwrap();
+
+        // Crashed while writing the new pair.
+        fs::write(tmp_path(&store.key_path()), "new key").unwrap();
+        fs::write(tmp_path(&store.cert_path()), "new cert").unwrap();
+
+        assert_eq!(store.load_or_generate().unwrap(), current);
+        assert!(!tmp_path(&store.key_path()).exists());
+        assert!(!tmp_path(&store.cert_path()).exists());
+    }
+
+    #[test]
+    fn completes_interrupted_renames() {
+        let dir = TempDir::new().unwrap();
+        let store = CaStore::new(dir.path().join("current"));
+        store.load_or_generate().unwrap();
+        let (cert, key) = CaStore::new(dir.path().join("new"))
+            .load_or_generate()
+            .unwrap();
+
+        // Crashed after the key was replaced, before the certificate was.
+        fs::write(store.key_path(), &key).unwrap();
+        fs::write(tmp_path(&store.cert_path()), &cert).unwrap();
+
+        assert_eq!(store.load_or_generate().unwrap(), (cert, key));
+        assert!(!tmp_path(&store.cert_path()).exists());
+    }
+
+    #[test]
+    fn keeps_a_half_missing_ca() {
+        let dir = TempDir::new().unwrap();
+        let store = CaStore::new(dir.path());
+        let (cert, _) = store.load_or_generate().unwrap();
+        fs::remove_file(store.key_path()).unwrap();
+
+        assert!(matches!(
+            store.load_or_generate(),
+            Err(Error::InvalidCertificate(_))
+        ));
+        assert_eq!(fs::read_to_string(store.cert_path()).unwrap(), cert);
+    }
+}
@@ -0,0 +1,272 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use openssl::x509::X509;
+
+use crate::error::Error;
+
+/// Name of the certificate in trust stores.
+const NAME: &str = "proxelar";
+
+/// Anchor directories of the distro trust stores, relative to the root, and the tool that
+/// rebuilds the trusted bundle from them.
+const LAYOUTS: &[(&str, &[&str])] = &[
+    // Debian, Ubuntu
+    (
+        "usr/local/share/ca-certificates",
+        &["update-ca-certificates"],
+    ),
+    // Fedora, RHEL
+    (
+        "etc/pki/ca-trust/source/anchors",
+        &["update-ca-trust", "extract"],
+    ),
+    // Arch
+    (
+        "etc/ca-certificates/trust-source/anchors",
+        &["update-ca-trust", "extract"],
+    ),
+    // openSUSE
+    ("etc/pki/trust/anchors", &["update-ca-certificates"]),
+];
+
+/// Installs the CA into the Linux system trust store and the NSS databases used by Firefox
+/// and Chromium.
+///
+/// The system store is the first anchor directory found under the root, `/` by default. Only
+/// for the real root the distro's update tool is run, through `pkexec` when not running as
+/// root. NSS databases are looked up in the home directory and changed with `certutil`, they
+/// are skipped if it isn't installed.
+///
+/// ```no_run
+/// use proxyapi::ca::{trust::TrustStore, CaStore};
+///
+/// let cert = std::fs::read(CaStore::default().cert_path()).unwrap();
+/// let store = TrustStore::new();
+/// if !store.is_trusted(&cert) {
+///     store.install(&cert).unwrap();
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TrustStore {
+    root: PathBuf,
+    home: Option<PathBuf>,
+}
+
+impl TrustStore {
+    pub fn new() -> Self {
+        Self {
+            root: PathBuf::from("/"),
+            home: dirs_next::home_dir(),
+        }
+    }
+
+    /// Uses the trust store under `root` instead of `/`, e.g. a chroot or a test directory.
+    pub fn with_root(self, root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            ..self
+        }
+    }
+
+    /// Looks for NSS databases in `home` instead of the user's home directory.
+    pub fn with_home(self, home: impl Into<PathBuf>) -> Self {
+        Self {
+            home: Some(home.into()),
+            ..self
+        }
+    }
+
+    /// Path the certificate is installed at in the system trust store, if there is one.
+    pub fn anchor_path(&self) -> Option<PathBuf> {
+        self.layout().map(|(anchor, _)| anchor)
+    }
+
+    /// Trusts the PEM encoded certificate system-wide and in the NSS databases.
+    ///
+    /// The system store is left alone if it already trusts the certificate.
+    pub fn install(&self, cert_pem: &[u8]) -> Result<(), Error> {
+        let (anchor, update) = self.layout().ok_or(Error::NoTrustStore)?;
+
+        if !self.is_anchored(cert_pem) {
+            if self.is_real_root() {
+                // `tee` writes the anchor with the privileges the update tool runs with.
+                let script = format!("tee \"$0\" > /dev/null && {}", update.join(" "));
+                privileged(
+                    &["sh", "-c", &script, &anchor.to_string_lossy()],
+                    Some(cert_pem),
+                )?;
+            } else {
+                fs::write(&anchor, cert_pem)?;
+            }
+        }
+
+        for db in self.nss_databases() {
+            let db = format!("sql:{}", db.display());
+            certutil(&["-A", "-n", NAME, "-t", "C,,", "-d", &db], Some(cert_pem))?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes the certificate from the system trust store and the NSS databases.
+    pub fn uninstall(&self) -> Result<(), Error> {
+        let (anchor, update) = self.layout().ok_or(Error::NoTrustStore)?;
+
+        if self.is_real_root() {
+            if anchor.exists() {
+                let script = format!("rm -f \"$0\" && {}", update.join(" "));
+                privileged(&["sh", "-c", &script, &anchor.to_string_lossy()], None)?;
+            }
+        } else {
+            match fs::remove_file(&anchor) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+
+        for db in self.nss_databases() {
+            let db = format!("sql:{}", db.display());
+            if certutil(&["-L", "-n", NAME, "-d", &db], None).is_ok() {
+                certutil(&["-D", "-n", NAME, "-d", &db], None)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether the PEM encoded certificate is installed in the system trust store and in every
+    /// NSS database.
+    ///
+    /// NSS databases are not checked if `certutil` isn't installed, as they are skipped when
+    /// installing too.
+    pub fn is_trusted(&self, cert_pem: &[u8]) -> bool {
+        self.is_anchored(cert_pem)
+            && self
+                .nss_databases()
+                .iter()
+                .all(|db| nss_holds(db, cert_pem))
+    }
+
+    fn is_anchored(&self, cert_pem: &[u8]) -> bool {
+        self.anchor_path()
+            .and_then(|anchor| fs::read(anchor).ok())
+            .is_some_and(|installed| installed.trim_ascii() == cert_pem.trim_ascii())
+    }
+
+    /// NSS databases of Chromium and of each Firefox profile.
+    pub fn nss_databases(&self) -> Vec<PathBuf> {
+        let Some(home) = &self.home else {
+            return Vec::new();
+        };
+
+        let mut databases = vec![home.join(".pki/nssdb")];
+        for profiles in [
+            home.join(".mozilla/firefox"),
+            home.join("snap/firefox/common/.mozilla/firefox"),
+        ] {
+            if let Ok(entries) = fs::read_dir(profiles) {
+                databases.extend(entries.flatten().map(|entry| entry.path()));
+            }
+        }
+
+        databases.retain(|db| db.join("cert9.db").is_file());
+        databases
+    }
+
+    /// Anchor path of the certificate and update tool of the system trust store.
+    fn layout(&self) -> Option<(PathBuf, &'static [&'static str])> {
+        LAYOUTS
+            .iter()
+            .map(|(dir, update)| (self.root.join(dir), *update))
+            .find(|(dir, _)| dir.is_dir())
+            .map(|(dir, update)| (dir.join(format!("{NAME}.crt")), update))
+    }
+
+    fn is_real_root(&self) -> bool {
+        self.root == Path::new("/")
+    }
+}
+
+impl Default for TrustStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Runs `args` as root, through `pkexec` unless already running as root.
+fn privileged(args: &[&str], stdin: Option<&[u8]>) -> Result<(), Error> {
+    // SAFETY: geteuid has no preconditions and can't fail.
+    if unsafe { libc::geteuid() } == 0 {
+        run(args[0], &args[1..], stdin)
+    } else {
+        run("pkexec", args, stdin)
+    }
+}
+
+fn certutil(args: &[&str], stdin: Option<&[u8]>) -> Result<(), Error> {
+    match run("certutil", args, stdin) {
+        Err(Error::Io(e)) if e.kind() == io::ErrorKind::NotFound => {
+            eprintln!("certutil not found, skipping NSS databases");
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+/// Whether the NSS database at `db` holds the PEM encoded certificate under [`NAME`].
+fn nss_holds(db: &Path, cert_pem: &[u8]) -> bool {
+    let output = Command::new("certutil")
+        .args([
+            "-L",
+            "-n",
+            NAME,
+            "-a",
+            "-d",
+            &format!("sql:{}", db.display()),
+        ])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output();
+
+    match output {
+        // The certificate may have been replaced since it was added under the same name.
+        Ok(output) => {
+            let der = |pem: &[u8]| X509::from_pem(pem).and_then(|cert| cert.to_der()).ok();
+            output.status.success() && der(&output.stdout).is_some_and(|d| Some(d) == der(cert_pem))
+        }
+        Err(e) => e.kind() == io::ErrorKind::NotFound,
+    }
+}
+
+fn run(program: &str, args: &[&str], stdin: Option<&[u8]>) -> Result<(), Error> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    if let (Some(input), Some(mut pipe)) = (stdin, child.stdin.take()) {
+        pipe.write_all(input)?;
+    }
+
+    let output = child.wait_with_output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(Error::CommandFailed(format!(
+            "`{program}` {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+    }
+}
@@ -0,0 +1,34 @@
+use std::io::{self, Read};
+
+use flate2::read::{MultiGzDecoder, ZlibDecoder};
+use hyper::{header::CONTENT_ENCODING, HeaderMap};
+
+use crate::error::Error;
+
+/// Length of `body` once the codings listed in its `Content-Encoding` are undone.
+pub(crate) fn decoded_len(headers: &HeaderMap, body: &[u8]) -> Result<u64, Error> {
+    let mut codings = Vec::new();
+    for value in headers.get_all(CONTENT_ENCODING) {
+        let value = value.to_str().map_err(|_| Error::Decode)?;
+        codings.extend(
+            value
+                .split(',')
+                .map(|coding| coding.trim().to_ascii_lowercase())
+                .filter(|coding| !coding.is_empty()),
+        );
+    }
+
+    // Codings are listed in the order they were applied.
+    let mut reader: Box<dyn Read + '_> = Box::new(body);
+    for coding in codings.iter().rev() {
+        reader = match coding.as_str() {
+            "gzip" | "x-gzip" => Box::new(MultiGzDecoder::new(reader)),
+            "deflate" => Box::new(ZlibDecoder::new(reader)),
+            "br" => Box::new(brotli::Decompressor::new(reader, 4096)),
+            "identity" => reader,
+            _ => return Err(Error::Decode),
+        };
+    }
+
+    io::copy(&mut reader, &mut io::sink()).map_err(|_| Error::Decode)
+}
@@ -1,11 +1,252 @@
+use hyper::{header, Body, Response, StatusCode};
+use proxyapi_models::{ErrorKind, FlowError};
+use std::io;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("network error")]
     Network(#[from] hyper::Error),
+    #[error("io error")]
+    Io(#[from] std::io::Error),
+    #[error("invalid upstream proxy `{0}`")]
+    InvalidUpstream(String),
+    #[error("invalid host pattern `{0}`")]
+    InvalidHostPattern(String),
+    #[error("invalid certificate: {0}")]
+    InvalidCertificate(String),
+    #[error("failed to generate certificate")]
+    CertificateGeneration(#[from] rcgen::Error),
+    #[error("failed to generate certificate for `{0}`: {1}")]
+    LeafCertificate(String, String),
+    #[error("no supported trust store found")]
+    NoTrustStore,
+    #[error("command failed: {0}")]
+    CommandFailed(String),
     #[error("unable to decode body")]
     Decode,
     #[error("unknown error")]
     Unknown,
 }
+
+/// A host name that couldn't be resolved, either an upstream server's or a parent proxy's.
+#[derive(Debug, Error)]
+#[error("failed to resolve `{host}`")]
+pub(crate) struct DnsError {
+    pub(crate) host: String,
+    #[source]
+    pub(crate) source: io::Error,
+}
+
+/// Describes `err` along with its causes, e.g. the certificate problem behind a failed TLS
+/// handshake.
+pub(crate) fn describe(err: &(dyn std::error::Error + 'static)) -> String {
+    let mut description = err.to_string();
+    let mut source = err.source();
+
+    while let Some(err) = source {
+        let cause = err.to_string();
+        if !description.contains(&cause) {
+            description.push_str(": ");
+            description.push_str(&cause);
+        }
+        source = err.source();
+    }
+
+    description
+}
+
+/// Classifies why a request couldn't be forwarded upstream.
+pub(crate) fn upstream_error(err: &hyper::Error) -> FlowError {
+    FlowError::new(kind(err), describe(err))
+}
+
+fn kind(err: &hyper::Error) -> ErrorKind {
+    if err.is_timeout() {
+        return ErrorKind::Timeout;
+    }
+
+    if let Some(kind) = cause_kind(err) {
+        kind
+    } else if err.is_parse() || err.is_incomplete_message() || err.is_closed() || err.is_canceled()
+    {
+        ErrorKind::UpstreamProtocol
+    } else {
+        ErrorKind::Other
+    }
+}
+
+/// Kind of the first cause of `err` that tells what went wrong.
+fn cause_kind(err: &(dyn std::error::Error + 'static)) -> Option<ErrorKind> {
+    let mut source = Some(err);
+    while let Some(cause) = source {
+        if cause.is::<DnsError>() {
+            return Some(ErrorKind::Dns);
+        }
+
+        if cause.is::<rustls::Error>() {
+            return Some(ErrorKind::TlsHandshake);
+        }
+
+        if let Some(e) = cause.downcast_ref::<hyper::Error>() {
+            // The client's body stream failed while it was being forwarded.
+            if e.is_body_write_aborted() {
+                return Some(ErrorKind::ClientAbort);
+            }
+        }
+
+        if let Some(e) = cause.downcast_ref::<io::Error>() {
+            // The source of an I/O error is the source of the error it wraps, not that error.
+            if let Some(kind) = e.get_ref().and_then(|inner| cause_kind(inner)) {
+                return Some(kind);
+            }
+            match e.kind() {
+                io::ErrorKind::ConnectionRefused => return Some(ErrorKind::ConnectionRefused),
+                io::ErrorKind::TimedOut => return Some(ErrorKind::Timeout),
+                _ => {}
+            }
+        }
+
+        source = cause.source();
+    }
+
+    None
+}
+
+/// Classifies why a tunnel failed, as `fallback` when its causes don't tell.
+pub(crate) fn tunnel_error(
+    err: &(dyn std::error::Error + 'static),
+    fallback: ErrorKind,
+) -> FlowError {
+    FlowError::new(cause_kind(err).unwrap_or(fallback), describe(err))
+}
+
+/// Response sent to the client when its request couldn't be forwarded, a 504 when upstream
+/// timed out and a 502 otherwise, telling what went wrong.
+pub(crate) fn error_response(error: &FlowError) -> Response<Body> {
+    let status = match error.kind() {
+        ErrorKind::Timeout => StatusCode::GATEWAY_TIMEOUT,
+        _ => StatusCode::BAD_GATEWAY,
+    };
+
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(Body::from(diagnostic(error)))
+        .expect("Failed to build response")
+}
+
+/// Response sent to the client when its request is malformed and can't be forwarded.
+pub(crate) fn bad_request(message: String) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::BAD_REQUEST)
+        .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(Body::from(message + "\n"))
+        .expect("Failed to build response")
+}
+
+/// Body of the response sent by [`error_response`].
+pub(crate) fn diagnostic(error: &FlowError) -> String {
+    format!("{}\n\n{}\n", error.kind(), error.message())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::upstream::{UpstreamConfig, UpstreamConnector};
+    use std::sync::Arc;
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+
+    /// Error of a request with `body` sent on a connection answered with `reply`.
+    async fn exchange(reply: &'static [u8], body: Body) -> hyper::Error {
+        let (client, mut server) = duplex(1024);
+        tokio::spawn(async move {
+            let mut request = [0; 1024];
+            let _ = server.read(&mut request).await;
+            let _ = server.write_all(reply).await;
+            // Keeps the connection open until the client gave up on it.
+            let _ = server.read(&mut request).await;
+        });
+
+        let (mut sender, connection) = hyper::client::conn::handshake(client).await.unwrap();
+        tokio::spawn(connection);
+        let request = hyper::Request::post("/").body(body).unwrap();
+        sender.send_request(request).await.unwrap_err()
+    }
+
+    /// Error of a request to `uri` sent with the upstream connector.
+    async fn request(uri: String) -> hyper::Error {
+        hyper::Client::builder()
+            .build::<_, Body>(UpstreamConnector::new(Arc::default()))
+            .get(uri.parse().unwrap())
+            .await
+            .unwrap_err()
+    }
+
+    fn io_error(kind: io::ErrorKind) -> io::Error {
+        io::Error::new(kind, "failed")
+    }
+
+    #[tokio::test]
+    async fn dns() {
+        let err = request("http://proxelar.invalid/".to_owned()).await;
+        assert_eq!(kind(&err), ErrorKind::Dns, "{err:?}");
+
+        // Reaching a parent proxy by name.
+        let err = UpstreamConfig::new()
+            .with_default("http://proxy.invalid:8080".parse().unwrap())
+            .connect("example.com", 80)
+            .await
+            .unwrap_err();
+        assert_eq!(cause_kind(&err), Some(ErrorKind::Dns));
+    }
+
+    #[tokio::test]
+    async fn connection_refused() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let err = request(format!("http://127.0.0.1:{port}/")).await;
+        assert_eq!(kind(&err), ErrorKind::ConnectionRefused, "{err:?}");
+    }
+
+    #[test]
+    fn timeout() {
+        let err = io_error(io::ErrorKind::TimedOut);
+        assert_eq!(cause_kind(&err), Some(ErrorKind::Timeout));
+    }
+
+    #[test]
+    fn tls_handshake() {
+        let err = rustls::Error::InvalidCertificateSignature;
+        assert_eq!(cause_kind(&err), Some(ErrorKind::TlsHandshake));
+
+        // As reported by tokio-rustls.
+        let err = io::Error::new(io::ErrorKind::InvalidData, err);
+        assert_eq!(cause_kind(&err), Some(ErrorKind::TlsHandshake));
+    }
+
+    #[tokio::test]
+    async fn upstream_protocol() {
+        let err = exchange(b"nonsense\r\n\r\n", Body::empty()).await;
+        assert_eq!(kind(&err), ErrorKind::UpstreamProtocol, "{err:?}");
+    }
+
+    #[tokio::test]
+    async fn client_abort() {
+        let (sender, body) = Body::channel();
+        sender.abort();
+
+        let err = exchange(b"", body).await;
+        assert_eq!(kind(&err), ErrorKind::ClientAbort, "{err:?}");
+    }
+
+    #[test]
+    fn other() {
+        let err = io_error(io::ErrorKind::PermissionDenied);
+        assert_eq!(cause_kind(&err), None);
+    }
+}
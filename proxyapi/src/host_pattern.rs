@@ -0,0 +1,200 @@
+use std::{net::IpAddr, str::FromStr};
+
+use regex::Regex;
+
+use crate::error::Error;
+
+/// Pattern matched against the host a client wants to reach.
+///
+/// Hosts are compared case-insensitively and without the brackets around IPv6 addresses.
+/// Patterns are parsed from:
+///
+/// - `example.com`, matching that host only,
+/// - `*.example.com`, matching its subdomains,
+/// - `/^api\d+\.example\.com$/`, matching hosts the regex matches,
+/// - `10.0.0.0/8`, `::1` or any other IP address or CIDR block, matching IP addresses in it.
+///   Host names are not resolved to be matched against these.
+#[derive(Clone, Debug)]
+pub enum HostPattern {
+    /// Matches exactly this host.
+    Exact(String),
+    /// Matches any subdomain of this domain, written as `*.example.com`.
+    Wildcard(String),
+    /// Matches hosts the regex matches, written between slashes.
+    Regex(Regex),
+    /// Matches IP addresses sharing the first `prefix_len` bits with this address.
+    Cidr(IpAddr, u8),
+}
+
+impl HostPattern {
+    pub fn matches(&self, host: &str) -> bool {
+        let host = normalize_host(host);
+
+        match self {
+            Self::Exact(exact) => host == *exact,
+            Self::Wildcard(domain) => host
+                .strip_suffix(domain.as_str())
+                .is_some_and(|subdomain| subdomain.len() > 1 && subdomain.ends_with('.')),
+            Self::Regex(regex) => regex.is_match(&host),
+            Self::Cidr(network, prefix_len) => host
+                .parse::<IpAddr>()
+                .is_ok_and(|ip| in_network(ip, *network, *prefix_len)),
+        }
+    }
+}
+
+impl PartialEq for HostPattern {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Exact(a), Self::Exact(b)) | (Self::Wildcard(a), Self::Wildcard(b)) => a == b,
+            (Self::Regex(a), Self::Regex(b)) => a.as_str() == b.as_str(),
+            (Self::Cidr(a, a_len), Self::Cidr(b, b_len)) => a == b && a_len == b_len,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for HostPattern {}
+
+impl FromStr for HostPattern {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidHostPattern(s.to_owned());
+
+        if let Some(regex) = s
+            .strip_prefix('/')
+            .and_then(|s| s.strip_suffix('/'))
+            .filter(|regex| !regex.is_empty())
+        {
+            return Regex::new(regex).map(Self::Regex).map_err(|_| invalid());
+        }
+
+        if let Ok(ip) = normalize_host(s).parse::<IpAddr>() {
+            return Ok(Self::Cidr(ip, max_prefix_len(ip)));
+        }
+
+        if let Some((ip, prefix_len)) = s.split_once('/') {
+            let ip = ip.parse::<IpAddr>().map_err(|_| invalid())?;
+            let prefix_len = prefix_len
+                .parse::<u8>()
+                .ok()
+                .filter(|len| *len <= max_prefix_len(ip))
+                .ok_or_else(invalid)?;
+
+            return Ok(Self::Cidr(ip, prefix_len));
+        }
+
+        Ok(match s.strip_prefix("*.") {
+            Some(domain) => Self::Wildcard(normalize_host(domain)),
+            None => Self::Exact(normalize_host(s)),
+        })
+    }
+}
+
+fn max_prefix_len(ip: IpAddr) -> u8 {
+    match ip {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+fn in_network(ip: IpAddr, network: IpAddr, prefix_len: u8) -> bool {
+    let (ip, network, bits) = match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) => {
+            (u32::from(ip) as u128, u32::from(network) as u128, 32)
+        }
+        (IpAddr::V6(ip), IpAddr::V6(network)) => (u128::from(ip), u128::from(network), 128),
+        _ => return false,
+    };
+
+    let shift = bits - u32::from(prefix_len);
+    shift >= bits || ip >> shift == network >> shift
+}
+
+pub(crate) fn normalize_host(host: &str) -> String {
+    host.trim_start_matches('[')
+        .trim_end_matches(']')
+        .trim_end_matches('.')
+        .to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> HostPattern {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn exact() {
+        let pattern = parse("Example.com");
+        assert_eq!(pattern, HostPattern::Exact("example.com".to_owned()));
+
+        assert!(pattern.matches("example.com"));
+        assert!(pattern.matches("EXAMPLE.com."));
+        assert!(!pattern.matches("www.example.com"));
+        assert!(!pattern.matches("example.com.evil"));
+    }
+
+    #[test]
+    fn wildcard_does_not_match_apex() {
+        let pattern = parse("*.example.com");
+        assert_eq!(pattern, HostPattern::Wildcard("example.com".to_owned()));
+
+        assert!(pattern.matches("www.example.com"));
+        assert!(pattern.matches("a.b.Example.com"));
+        assert!(!pattern.matches("example.com"));
+        assert!(!pattern.matches(".example.com"));
+        assert!(!pattern.matches("badexample.com"));
+    }
+
+    #[test]
+    fn regex() {
+        let pattern = parse(r"/^api\d+\.example\.com$/");
+        assert!(matches!(pattern, HostPattern::Regex(_)));
+
+        assert!(pattern.matches("api1.example.com"));
+        assert!(pattern.matches("API42.example.com"));
+        assert!(!pattern.matches("api.example.com"));
+
+        assert!("/(/".parse::<HostPattern>().is_err());
+        assert!("//".parse::<HostPattern>().is_err());
+    }
+
+    #[test]
+    fn cidr_v4() {
+        let pattern = parse("10.0.0.0/8");
+        assert!(pattern.matches("10.1.2.3"));
+        assert!(!pattern.matches("11.0.0.1"));
+        assert!(!pattern.matches("::ffff:10.0.0.1"));
+        assert!(!pattern.matches("ten.example.com"));
+
+        let single = parse("192.0.2.1");
+        assert_eq!(single, HostPattern::Cidr("192.0.2.1".parse().unwrap(), 32));
+        assert!(single.matches("192.0.2.1"));
+        assert!(!single.matches("192.0.2.2"));
+
+        assert!(parse("0.0.0.0/0").matches("203.0.113.9"));
+        assert!("10.0.0.0/33".parse::<HostPattern>().is_err());
+        assert!("10.0.0.0/x".parse::<HostPattern>().is_err());
+    }
+
+    #[test]
+    fn cidr_v6() {
+        let pattern = parse("2001:db8::/32");
+        assert!(pattern.matches("2001:db8::1"));
+        assert!(pattern.matches("[2001:DB8:ffff::1]"));
+        assert!(!pattern.matches("2001:db9::1"));
+        assert!(!pattern.matches("10.0.0.1"));
+
+        let loopback = parse("[::1]");
+        assert_eq!(loopback, HostPattern::Cidr("::1".parse().unwrap(), 128));
+        assert!(loopback.matches("[::1]"));
+        assert!(!loopback.matches("::2"));
+
+        assert!(parse("::/0").matches("fe80::1"));
+        assert!("::/129".parse::<HostPattern>().is_err());
+    }
+}
@@ -1,12 +1,31 @@
+mod body;
+mod counted;
+mod decoder;
 mod error;
+pub mod host_pattern;
+mod noop;
+mod onboarding;
 pub mod proxy;
 pub mod proxy_handler;
+pub mod reverse;
 mod rewind;
+mod sni;
+mod socks5;
+mod timing;
+pub mod tls;
+#[cfg(target_os = "linux")]
+mod transparent;
+pub mod upstream;
 
 pub mod ca;
 
 use hyper::{Body, Request, Response, Uri};
-use std::net::SocketAddr;
+use std::{
+    fmt,
+    hash::{Hash, Hasher},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
 use tokio_tungstenite::tungstenite::Message;
 
 pub use async_trait;
@@ -15,10 +34,8 @@ pub use openssl;
 pub use tokio_rustls;
 pub use tokio_tungstenite;
 
-//decoder
-// pub use decoder;
-// pub use error;
-// pub use noop;
+pub use error::Error;
+pub use noop::*;
 pub use proxy::*;
 pub use proxy_handler::*;
 
@@ -43,6 +60,77 @@ impl From<Response<Body>> for RequestResponse {
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct HttpContext {
     pub remote_addr: SocketAddr,
+    /// Id of the exchange, unique for the lifetime of the process.
+    pub flow_id: u64,
+    /// Id of the client connection, shared by the exchanges sent over it.
+    pub connection_id: u64,
+    pub mode: InterceptionMode,
+    /// Server name the client sent when the proxy terminated its TLS connection.
+    pub sni: Option<String>,
+    /// Parameters of the client's TLS connection, if the proxy terminated one.
+    pub client_tls: Option<TlsInfo>,
+    /// State the handler keeps between its calls for this exchange.
+    pub exchange: ExchangeState,
+}
+
+impl HttpContext {
+    /// Client side of the exchange, as recorded in [`ProxiedRequest`].
+    pub fn client_info(&self) -> ClientInfo {
+        let client = ClientInfo::new(self.remote_addr, self.connection_id, self.mode);
+        match &self.client_tls {
+            Some(tls) => client.with_tls(self.sni.clone(), tls.clone()),
+            None => client,
+        }
+    }
+}
+
+/// Values a handler keeps for one exchange, e.g. to pair its response with its request.
+///
+/// Handlers are cloned for each connection, so state kept in them isn't tied to an exchange.
+/// Every call about an exchange gets a context pointing to the same state, which is dropped
+/// along with the exchange. Values are looked up by type.
+#[derive(Clone, Default)]
+pub struct ExchangeState(Arc<Mutex<http::Extensions>>);
+
+impl ExchangeState {
+    /// Stores `value`, returning the value of the same type stored before.
+    pub fn insert<T: Send + Sync + 'static>(&self, value: T) -> Option<T> {
+        self.0.lock().unwrap().insert(value)
+    }
+
+    pub fn get<T: Clone + Send + Sync + 'static>(&self) -> Option<T> {
+        self.0.lock().unwrap().get().cloned()
+    }
+
+    /// Calls `f` with the stored value of type `T`, if there is one.
+    pub fn update<T: Send + Sync + 'static, R>(&self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        self.0.lock().unwrap().get_mut().map(f)
+    }
+
+    pub fn remove<T: Send + Sync + 'static>(&self) -> Option<T> {
+        self.0.lock().unwrap().remove()
+    }
+}
+
+impl fmt::Debug for ExchangeState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExchangeState").finish_non_exhaustive()
+    }
+}
+
+/// States are equal when they are the state of the same exchange.
+impl PartialEq for ExchangeState {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for ExchangeState {}
+
+impl Hash for ExchangeState {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Arc::as_ptr(&self.0).hash(state);
+    }
 }
 
 pub enum WebSocketContext {
@@ -59,10 +147,31 @@ pub trait HttpHandler: Clone + Send + Sync + 'static {
     async fn handle_response(&mut self, _ctx: &HttpContext, res: Response<Body>) -> Response<Body> {
         res
     }
+
+    /// Called right before the request is sent upstream.
+    async fn handle_forward(&mut self, _ctx: &HttpContext, _req: &Request<Body>) {}
+
+    /// Called when the request couldn't be forwarded upstream or upstream didn't answer in
+    /// time, returns the response sent to the client instead.
+    async fn handle_error(&mut self, _ctx: &HttpContext, error: &FlowError) -> Response<Body> {
+        error::error_response(error)
+    }
+
+    /// Called once a connection relayed without interception is closed.
+    async fn handle_tunnel(&mut self, _ctx: &HttpContext, _tunnel: &proxy_handler::Tunnel) {}
 }
 
 #[async_trait::async_trait]
 pub trait WebSocketHandler: Clone + Send + Sync + 'static {
+    /// Called once both sides completed the handshake, before any message is relayed.
+    async fn handle_handshake(
+        &mut self,
+        _ctx: &HttpContext,
+        _req: &Request<()>,
+        _res: &Response<Option<Vec<u8>>>,
+    ) {
+    }
+
     async fn handle_message(
         &mut self,
         _ctx: &WebSocketContext,
@@ -70,4 +179,10 @@ pub trait WebSocketHandler: Clone + Send + Sync + 'static {
     ) -> Option<Message> {
         Some(message)
     }
+
+    /// Called once per session, when the direction described by `ctx` ended it.
+    ///
+    /// `code` is the code of the close frame, or 1006 (abnormal closure) when the connection
+    /// failed or ended without one.
+    async fn handle_close(&mut self, _ctx: &WebSocketContext, _code: u16, _reason: &str) {}
 }
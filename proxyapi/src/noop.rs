@@ -0,0 +1,9 @@
+use crate::{HttpHandler, WebSocketHandler};
+
+/// A handler that forwards every request, response and message untouched.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoopHandler;
+
+impl HttpHandler for NoopHandler {}
+
+impl WebSocketHandler for NoopHandler {}
@@ -0,0 +1,111 @@
+use hyper::{header, Body, Method, Request, Response, StatusCode};
+use openssl::{pkcs12::Pkcs12, x509::X509};
+
+/// Host answered by the proxy itself with a page to download its CA certificate.
+pub(crate) const HOST: &str = "proxelar.local";
+
+const PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>Proxelar certificate</title>
+<style>
+body { font-family: sans-serif; max-width: 720px; margin: 2em auto; padding: 0 1em; line-height: 1.5; }
+a.button { display: inline-block; margin: 0 .5em .5em 0; padding: .5em 1em; border: 1px solid; border-radius: 5px; text-decoration: none; }
+</style>
+</head>
+<body>
+<h1>Proxelar certificate</h1>
+<p>Proxelar decrypts HTTPS traffic with certificates signed by its own certificate authority.
+Install and trust it on this device to inspect HTTPS without certificate errors.</p>
+<p>
+<a class="button" href="/cert/pem">PEM (.pem)</a>
+<a class="button" href="/cert/der">DER (.cer)</a>
+<a class="button" href="/cert/p12">PKCS#12 (.p12)</a>
+</p>
+<h2>Windows</h2>
+<p>Download the <code>.cer</code> file, open it and choose <em>Install Certificate</em>. Select
+<em>Local Machine</em>, then place it in <em>Trusted Root Certification Authorities</em>.</p>
+<h2>macOS</h2>
+<p>Download the <code>.pem</code> file and open it to add it to the <em>System</em> keychain. In
+Keychain Access, open the <em>proxelar</em> certificate and set <em>When using this certificate</em>
+to <em>Always Trust</em>.</p>
+<h2>Linux</h2>
+<p>Download the <code>.pem</code> file and copy it to <code>/usr/local/share/ca-certificates/proxelar.crt</code>
+then run <code>sudo update-ca-certificates</code> (Debian, Ubuntu). On Fedora and Arch copy it to
+<code>/etc/pki/ca-trust/source/anchors/</code> and run <code>sudo update-ca-trust</code>.</p>
+<h2>iOS</h2>
+<p>Open this page in Safari and download the <code>.pem</code> file, then install the profile in
+<em>Settings &gt; General &gt; VPN &amp; Device Management</em>. Enable full trust for it in
+<em>Settings &gt; General &gt; About &gt; Certificate Trust Settings</em>.</p>
+<h2>Android</h2>
+<p>Download the <code>.cer</code> file and install it in <em>Settings &gt; Security &gt; Encryption
+&amp; credentials &gt; Install a certificate &gt; CA certificate</em>. Apps only trust user
+certificates if they opt in.</p>
+<h2>Firefox</h2>
+<p>Firefox has its own certificate store. Download the <code>.pem</code> file and import it in
+<em>Settings &gt; Privacy &amp; Security &gt; Certificates &gt; View Certificates &gt;
+Authorities</em>, trusting it to identify websites.</p>
+</body>
+</html>
+"#;
+
+/// Whether `req` is for the onboarding host, rather than a server to forward it to.
+pub(crate) fn is_onboarding<T>(req: &Request<T>) -> bool {
+    req.method() != Method::CONNECT
+        && req
+            .uri()
+            .host()
+            .is_some_and(|host| host.eq_ignore_ascii_case(HOST))
+}
+
+/// Serves the onboarding page and the DER encoded `ca_cert` in the formats it links to.
+pub(crate) fn respond<T>(req: &Request<T>, ca_cert: Option<Vec<u8>>) -> Response<Body> {
+    let download = |content_type: &str, filename: &str, body: Vec<u8>| {
+        Response::builder()
+            .header(header::CONTENT_TYPE, content_type)
+            .header(
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{filename}\""),
+            )
+            .body(Body::from(body))
+    };
+
+    let res = match (req.uri().path(), ca_cert) {
+        ("/", _) => Response::builder()
+            .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+            .body(Body::from(PAGE)),
+        ("/cert/pem", Some(der)) => match X509::from_der(&der).and_then(|cert| cert.to_pem()) {
+            Ok(pem) => download("application/x-pem-file", "proxelar-ca.pem", pem),
+            Err(e) => return internal_error(e),
+        },
+        ("/cert/der", Some(der)) => download("application/x-x509-ca-cert", "proxelar-ca.cer", der),
+        ("/cert/p12", Some(der)) => match pkcs12(&der) {
+            Ok(p12) => download("application/x-pkcs12", "proxelar-ca.p12", p12),
+            Err(e) => return internal_error(e),
+        },
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::from("Not found")),
+    };
+
+    res.expect("Failed to build response")
+}
+
+/// PKCS#12 archive holding just the certificate, protected by an empty password.
+fn pkcs12(der: &[u8]) -> Result<Vec<u8>, openssl::error::ErrorStack> {
+    let cert = X509::from_der(der)?;
+    Pkcs12::builder()
+        .name("proxelar")
+        .cert(&cert)
+        .build2("")?
+        .to_der()
+}
+
+fn internal_error(err: openssl::error::ErrorStack) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::INTERNAL_SERVER_ERROR)
+        .body(Body::from(err.to_string()))
+        .expect("Failed to build response")
+}
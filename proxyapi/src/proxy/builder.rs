@@ -0,0 +1,308 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use hyper::client::{connect::Connect, Client};
+use tokio::sync::broadcast;
+use tokio_tungstenite::Connector;
+
+use crate::{
+    ca::CertificateAuthority,
+    host_pattern::HostPattern,
+    tls::{ClientConfigs, TlsOptions, UpstreamTlsConnector},
+    upstream::{UpstreamConfig, UpstreamConnector},
+    HttpHandler, NoopHandler, WebSocketHandler,
+};
+
+use super::{Proxy, ProxyMode};
+
+/// Default of [`ProxyBuilder::with_connect_timeout`].
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default of [`ProxyBuilder::with_response_timeout`].
+pub const DEFAULT_RESPONSE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Builder for [`Proxy`].
+///
+/// The builder walks through its required settings in a fixed order (address, client,
+/// certificate authority) before the optional handlers can be set and the proxy built.
+///
+/// ```no_run
+/// use proxyapi::{
+///     ca::{CaStore, Ssl},
+///     NoopHandler, ProxyBuilder,
+/// };
+///
+/// # fn main() -> Result<(), proxyapi::Error> {
+/// let proxy = ProxyBuilder::new()
+///     .with_addr(([127, 0, 0, 1], 8080).into())
+///     .with_rustls_client()
+///     .with_ca(Ssl::from_store(&CaStore::default())?)
+///     .with_http_handler(NoopHandler)
+///     .build();
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct ProxyBuilder<T>(T);
+
+/// Builder state that needs the listening address.
+#[derive(Debug)]
+pub struct WantsAddr(());
+
+/// Builder state that needs the client used to reach upstream servers.
+#[derive(Debug)]
+pub struct WantsClient {
+    addr: SocketAddr,
+    upstream: UpstreamConfig,
+    tls: TlsOptions,
+    connect_timeout: Duration,
+}
+
+/// Builder state that needs the certificate authority used for MITM'd connections.
+#[derive(Debug)]
+pub struct WantsCa<C> {
+    addr: SocketAddr,
+    client: Client<C>,
+    upstream: UpstreamConfig,
+    websocket_tls: ClientConfigs,
+}
+
+/// Builder state in which the handlers can be set and the proxy built.
+pub struct WantsHandlers<C, CA, H, W> {
+    addr: SocketAddr,
+    mode: ProxyMode,
+    client: Client<C>,
+    upstream: UpstreamConfig,
+    ca: CA,
+    passthrough: Vec<HostPattern>,
+    http_handler: H,
+    websocket_handler: W,
+    websocket_connector: Option<Connector>,
+    websocket_tls: ClientConfigs,
+    response_timeout: Duration,
+}
+
+impl ProxyBuilder<WantsAddr> {
+    pub fn new() -> Self {
+        Self(WantsAddr(()))
+    }
+
+    /// Sets the address the proxy listens on.
+    pub fn with_addr(self, addr: SocketAddr) -> ProxyBuilder<WantsClient> {
+        ProxyBuilder(WantsClient {
+            addr,
+            upstream: UpstreamConfig::default(),
+            tls: TlsOptions::default(),
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+        })
+    }
+}
+
+impl Default for ProxyBuilder<WantsAddr> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProxyBuilder<WantsClient> {
+    /// Routes upstream connections through parent proxies.
+    ///
+    /// Raw tunnels and WebSocket connections always follow `upstream`. HTTP requests only do
+    /// with [`with_rustls_client`](Self::with_rustls_client), a custom client or connector is
+    /// in charge of its own connections.
+    pub fn with_upstream(self, upstream: UpstreamConfig) -> Self {
+        ProxyBuilder(WantsClient { upstream, ..self.0 })
+    }
+
+    /// Gives up on upstream connections that take longer than `timeout` to open,
+    /// [`DEFAULT_CONNECT_TIMEOUT`] by default.
+    ///
+    /// Like the upstream configuration, it only applies to the connections of a custom client
+    /// or connector if they are opened with an [`UpstreamConnector`].
+    pub fn with_connect_timeout(self, connect_timeout: Duration) -> Self {
+        ProxyBuilder(WantsClient {
+            connect_timeout,
+            ..self.0
+        })
+    }
+
+    /// Sets how [`with_rustls_client`](Self::with_rustls_client) verifies upstream servers and
+    /// which client certificates it presents to them.
+    pub fn with_upstream_tls(self, tls: TlsOptions) -> Self {
+        ProxyBuilder(WantsClient { tls, ..self.0 })
+    }
+
+    /// Uses a rustls client trusting the webpki roots, the same one used by [`Proxy::new`].
+    ///
+    /// With the `http2` feature the client offers `h2` via ALPN and uses HTTP/2 with upstream
+    /// servers that accept it.
+    pub fn with_rustls_client(self) -> ProxyBuilder<WantsCa<UpstreamTlsConnector>> {
+        let connector = UpstreamConnector::new(Arc::new(self.0.upstream()));
+        let https = UpstreamTlsConnector::new(connector, &self.0.tls);
+
+        self.with_http_connector(https)
+    }
+
+    /// Uses a client built around the given connector.
+    pub fn with_http_connector<C>(self, connector: C) -> ProxyBuilder<WantsCa<C>>
+    where
+        C: Connect + Clone + Send + Sync + 'static,
+    {
+        let client = Client::builder()
+            .http1_preserve_header_case(true)
+            .http1_title_case_headers(true)
+            .build(connector);
+
+        self.with_client(client)
+    }
+
+    /// Uses a fully configured client.
+    pub fn with_client<C>(self, client: Client<C>) -> ProxyBuilder<WantsCa<C>>
+    where
+        C: Connect + Clone + Send + Sync + 'static,
+    {
+        ProxyBuilder(WantsCa {
+            addr: self.0.addr,
+            client,
+            upstream: self.0.upstream(),
+            websocket_tls: ClientConfigs::new(&self.0.tls, &[b"http/1.1"]),
+        })
+    }
+}
+
+impl WantsClient {
+    fn upstream(&self) -> UpstreamConfig {
+        self.upstream
+            .clone()
+            .with_connect_timeout(self.connect_timeout)
+    }
+}
+
+impl<C> ProxyBuilder<WantsCa<C>> {
+    /// Sets the certificate authority used to sign certificates for intercepted hosts.
+    ///
+    /// The connections it makes to upstream servers follow the proxy's upstream configuration,
+    /// see [`CertificateAuthority::with_upstream`].
+    pub fn with_ca<CA: CertificateAuthority>(
+        self,
+        ca: CA,
+    ) -> ProxyBuilder<WantsHandlers<C, CA, NoopHandler, NoopHandler>> {
+        let ca = ca.with_upstream(UpstreamConnector::new(Arc::new(self.0.upstream.clone())));
+
+        ProxyBuilder(WantsHandlers {
+            addr: self.0.addr,
+            client: self.0.client,
+            upstream: self.0.upstream,
+            mode: ProxyMode::default(),
+            ca,
+            passthrough: Vec::new(),
+            http_handler: NoopHandler,
+            websocket_handler: NoopHandler,
+            websocket_connector: None,
+            websocket_tls: self.0.websocket_tls,
+            response_timeout: DEFAULT_RESPONSE_TIMEOUT,
+        })
+    }
+}
+
+impl<C, CA, H, W> ProxyBuilder<WantsHandlers<C, CA, H, W>>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+    CA: CertificateAuthority,
+    H: HttpHandler,
+    W: WebSocketHandler,
+{
+    /// Sets the handler called for every HTTP request and response.
+    pub fn with_http_handler<H2: HttpHandler>(
+        self,
+        http_handler: H2,
+    ) -> ProxyBuilder<WantsHandlers<C, CA, H2, W>> {
+        ProxyBuilder(WantsHandlers {
+            addr: self.0.addr,
+            client: self.0.client,
+            upstream: self.0.upstream,
+            mode: self.0.mode,
+            ca: self.0.ca,
+            passthrough: self.0.passthrough,
+            http_handler,
+            websocket_handler: self.0.websocket_handler,
+            websocket_connector: self.0.websocket_connector,
+            websocket_tls: self.0.websocket_tls,
+            response_timeout: self.0.response_timeout,
+        })
+    }
+
+    /// Sets the handler called for every WebSocket message.
+    pub fn with_websocket_handler<W2: WebSocketHandler>(
+        self,
+        websocket_handler: W2,
+    ) -> ProxyBuilder<WantsHandlers<C, CA, H, W2>> {
+        ProxyBuilder(WantsHandlers {
+            addr: self.0.addr,
+            client: self.0.client,
+            upstream: self.0.upstream,
+            mode: self.0.mode,
+            ca: self.0.ca,
+            passthrough: self.0.passthrough,
+            http_handler: self.0.http_handler,
+            websocket_handler,
+            websocket_connector: self.0.websocket_connector,
+            websocket_tls: self.0.websocket_tls,
+            response_timeout: self.0.response_timeout,
+        })
+    }
+
+    /// Sets the protocol clients use to talk to the proxy, [`ProxyMode::Http`] by default.
+    pub fn with_mode(self, mode: ProxyMode) -> Self {
+        ProxyBuilder(WantsHandlers { mode, ..self.0 })
+    }
+
+    /// Relays connections to hosts matching `pattern` without intercepting them.
+    ///
+    /// Use it for certificate-pinned apps and hosts that must not be decrypted. Tunnels to a
+    /// matching host are relayed whatever protocol they carry, TLS connections are also matched
+    /// on the server name in their ClientHello. Relayed connections are reported to
+    /// [`HttpHandler::handle_tunnel`].
+    pub fn with_passthrough(mut self, pattern: HostPattern) -> Self {
+        self.0.passthrough.push(pattern);
+        self
+    }
+
+    /// Answers requests whose response head didn't arrive within `timeout` of being forwarded
+    /// with a 504, [`DEFAULT_RESPONSE_TIMEOUT`] by default.
+    pub fn with_response_timeout(self, response_timeout: Duration) -> Self {
+        ProxyBuilder(WantsHandlers {
+            response_timeout,
+            ..self.0
+        })
+    }
+
+    /// Sets the connector used to reach upstream WebSocket servers.
+    ///
+    /// Without one, `wss` upstreams are verified and authenticated to with the
+    /// [`TlsOptions`] of the client, see [`with_upstream_tls`](ProxyBuilder::with_upstream_tls).
+    pub fn with_websocket_connector(self, connector: Connector) -> Self {
+        ProxyBuilder(WantsHandlers {
+            websocket_connector: Some(connector),
+            ..self.0
+        })
+    }
+
+    pub fn build(self) -> Proxy<C, CA, H, W> {
+        let (shutdown, _) = broadcast::channel(1);
+
+        Proxy {
+            addr: self.0.addr,
+            mode: self.0.mode,
+            client: self.0.client,
+            upstream: Arc::new(self.0.upstream),
+            ca: Arc::new(self.0.ca),
+            passthrough: Arc::new(self.0.passthrough),
+            http_handler: self.0.http_handler,
+            websocket_handler: self.0.websocket_handler,
+            websocket_connector: self.0.websocket_connector,
+            websocket_tls: self.0.websocket_tls,
+            response_timeout: self.0.response_timeout,
+            shutdown,
+        }
+    }
+}
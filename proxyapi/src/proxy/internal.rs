@@ -1,144 +1,201 @@
 // This code was derived from the hudsucker repository:
 // https://github.com/omjadas/hudsucker
 
-use crate::{ca::CertificateAuthority, rewind::Rewind, HttpContext, HttpHandler, RequestResponse};
+use crate::{
+    ca::CertificateAuthority,
+    counted::Counted,
+    error,
+    host_pattern::HostPattern,
+    onboarding,
+    reverse::ReverseConfig,
+    rewind::Rewind,
+    sni, socks5,
+    tls::{self, ClientConfigs},
+    upstream::UpstreamConfig,
+    ExchangeState, HttpContext, HttpHandler, RequestResponse, WebSocketContext, WebSocketHandler,
+};
+use futures::{future, Sink, SinkExt, Stream, StreamExt};
 use http::uri::{Authority, Scheme};
 use hyper::{
     client::connect::Connect, header::Entry, server::conn::Http, service::service_fn,
-    upgrade::Upgraded, Body, Client, Method, Request, Response, Uri,
+    upgrade::Upgraded, Body, Client, Method, Request, Response, StatusCode, Uri,
+};
+use proxyapi_models::{ErrorKind, FlowError, InterceptionMode, TlsInfo, Tunnel};
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
 };
-use std::{net::SocketAddr, sync::Arc};
 use tokio::{
     io::{AsyncRead, AsyncReadExt, AsyncWrite},
     net::TcpStream,
+    task::JoinHandle,
+};
+use tokio_rustls::{rustls::ServerName, TlsAcceptor, TlsConnector};
+use tokio_tungstenite::{
+    tungstenite::{
+        self,
+        error::ProtocolError,
+        protocol::{frame::coding::CloseCode, CloseFrame},
+        Message,
+    },
+    Connector,
 };
-use tokio_rustls::TlsAcceptor;
-use tokio_tungstenite::{tungstenite, Connector};
 
-pub struct InternalProxy<C, CA, H> {
+#[cfg(target_os = "linux")]
+use crate::transparent;
+
+static NEXT_FLOW_ID: AtomicU64 = AtomicU64::new(0);
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Id for a new client connection, unique for the lifetime of the process.
+pub(crate) fn next_connection_id() -> u64 {
+    NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+pub struct InternalProxy<C, CA, H, W> {
     pub ca: Arc<CA>,
+    pub passthrough: Arc<Vec<HostPattern>>,
     pub client: Client<C>,
+    pub upstream: Arc<UpstreamConfig>,
+    pub reverse: Option<Arc<ReverseConfig>>,
     pub http_handler: H,
+    pub websocket_handler: W,
     pub websocket_connector: Option<Connector>,
+    pub websocket_tls: ClientConfigs,
+    pub response_timeout: Duration,
     pub remote_addr: SocketAddr,
+    pub connection_id: u64,
+    pub mode: InterceptionMode,
+    pub sni: Option<String>,
+    pub client_tls: Option<TlsInfo>,
 }
 
-impl<C, CA, H> Clone for InternalProxy<C, CA, H>
+impl<C, CA, H, W> Clone for InternalProxy<C, CA, H, W>
 where
     C: Clone,
     H: Clone,
+    W: Clone,
 {
     fn clone(&self) -> Self {
         InternalProxy {
             ca: Arc::clone(&self.ca),
+            passthrough: Arc::clone(&self.passthrough),
             client: self.client.clone(),
+            upstream: Arc::clone(&self.upstream),
+            reverse: self.reverse.clone(),
             http_handler: self.http_handler.clone(),
+            websocket_handler: self.websocket_handler.clone(),
             websocket_connector: self.websocket_connector.clone(),
+            websocket_tls: self.websocket_tls.clone(),
+            response_timeout: self.response_timeout,
             remote_addr: self.remote_addr,
+            connection_id: self.connection_id,
+            mode: self.mode,
+            sni: self.sni.clone(),
+            client_tls: self.client_tls.clone(),
         }
     }
 }
 
-impl<C, CA, H> InternalProxy<C, CA, H>
+impl<C, CA, H, W> InternalProxy<C, CA, H, W>
 where
     C: Connect + Clone + Send + Sync + 'static,
     CA: CertificateAuthority,
     H: HttpHandler,
+    W: WebSocketHandler,
 {
+    /// Context of a new exchange on the client connection.
+    fn context(&self) -> HttpContext {
+        HttpContext {
+            remote_addr: self.remote_addr,
+            flow_id: NEXT_FLOW_ID.fetch_add(1, Ordering::Relaxed),
+            connection_id: self.connection_id,
+            mode: self.mode,
+            sni: self.sni.clone(),
+            client_tls: self.client_tls.clone(),
+            exchange: ExchangeState::default(),
+        }
+    }
+
     pub(crate) async fn proxy(
         mut self,
         req: Request<Body>,
     ) -> Result<Response<Body>, hyper::Error> {
-        let ctx = HttpContext {
-            remote_addr: self.remote_addr,
-        };
+        if onboarding::is_onboarding(&req) {
+            return Ok(onboarding::respond(&req, self.ca.ca_cert_der()));
+        }
+
+        let ctx = self.context();
 
-        let req = match self.http_handler.handle_request(&ctx, req).await {
+        let mut req = match self.http_handler.handle_request(&ctx, req).await {
             RequestResponse::Request(req) => req,
             RequestResponse::Response(res) => return Ok(res),
         };
 
+        if let Some(reverse) = &self.reverse {
+            if req.method() == Method::CONNECT || !reverse.rewrite(&mut req) {
+                let host = req.uri().host().unwrap_or_default();
+                let res = Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body(Body::from(format!("No virtual host for `{host}`")))
+                    .expect("Failed to build response");
+
+                return Ok(self.http_handler.handle_response(&ctx, res).await);
+            }
+        }
+
+        // Requests in tunnels get their target from the Host header, which may be missing.
+        let Some(authority) = req.uri().authority().cloned() else {
+            eprintln!(
+                "Request from {} has no target host: {}",
+                self.remote_addr,
+                req.uri()
+            );
+            let res = error::bad_request(format!("No host to forward `{}` to", req.uri()));
+            return Ok(self.http_handler.handle_response(&ctx, res).await);
+        };
+
         if req.method() == Method::CONNECT {
-            self.process_connect(req)
+            self.process_connect(req, authority)
         } else if hyper_tungstenite::is_upgrade_request(&req) {
-            Ok(self.upgrade_websocket(req))
+            match self.clone().upgrade_websocket(req) {
+                Ok(res) => Ok(res),
+                Err(e) => {
+                    eprintln!("Invalid WebSocket upgrade from {}: {e}", self.remote_addr);
+                    let res = error::bad_request(format!("Invalid WebSocket upgrade: {e}"));
+                    Ok(self.http_handler.handle_response(&ctx, res).await)
+                }
+            }
         } else {
-            let res = self.client.request(normalize_request(req)).await?;
+            let req = normalize_request(req);
+            self.http_handler.handle_forward(&ctx, &req).await;
+
+            let error =
+                match tokio::time::timeout(self.response_timeout, self.client.request(req)).await {
+                    Ok(Ok(res)) => return Ok(self.http_handler.handle_response(&ctx, res).await),
+                    Ok(Err(e)) => error::upstream_error(&e),
+                    Err(_) => FlowError::new(
+                        ErrorKind::Timeout,
+                        format!("no response within {:?}", self.response_timeout),
+                    ),
+                };
 
-            Ok(self.http_handler.handle_response(&ctx, res).await)
+            Ok(self.http_handler.handle_error(&ctx, &error).await)
         }
     }
 
-    fn process_connect(self, mut req: Request<Body>) -> Result<Response<Body>, hyper::Error> {
+    fn process_connect(
+        self,
+        mut req: Request<Body>,
+        authority: Authority,
+    ) -> Result<Response<Body>, hyper::Error> {
         let fut = async move {
             match hyper::upgrade::on(&mut req).await {
-                Ok(mut upgraded) => {
-                    let mut buffer = [0; 4];
-                    let bytes_read = match upgraded.read(&mut buffer).await {
-                        Ok(bytes_read) => bytes_read,
-                        Err(e) => {
-                            eprintln!("Failed to read from upgraded connection: {e}");
-                            return;
-                        }
-                    };
-
-                    let mut upgraded = Rewind::new_buffered(
-                        upgraded,
-                        bytes::Bytes::copy_from_slice(buffer[..bytes_read].as_ref()),
-                    );
-
-                    if buffer == *b"GET " {
-                        if let Err(e) = self.serve_stream(upgraded, Scheme::HTTP).await {
-                            eprintln!("Websocket connect error: {e}");
-                        }
-                    } else if buffer[..2] == *b"\x16\x03" {
-                        let authority = req
-                            .uri()
-                            .authority()
-                            .expect("Uri doesn't contain authority");
-
-                        let server_config = self.ca.gen_server_config(authority).await;
-
-                        let stream = match TlsAcceptor::from(server_config).accept(upgraded).await {
-                            Ok(stream) => stream,
-                            Err(e) => {
-                                eprintln!("Failed to establish TLS Connection:{e}");
-                                return;
-                            }
-                        };
-
-                        if let Err(e) = self.serve_stream(stream, Scheme::HTTPS).await {
-                            if !e.to_string().starts_with("error shutting down connection") {
-                                eprintln!("HTTPS connect error: {e}");
-                            }
-                        }
-                    } else {
-                        eprintln!(
-                            "Unknown protocol, read '{:02X?}' from upgraded connection",
-                            &buffer[..bytes_read]
-                        );
-
-                        let authority = req
-                            .uri()
-                            .authority()
-                            .expect("Uri doesn't contain authority")
-                            .as_ref();
-
-                        let mut server = match TcpStream::connect(authority).await {
-                            Ok(server) => server,
-                            Err(e) => {
-                                eprintln! {"failed to connect to {authority}: {e}"};
-                                return;
-                            }
-                        };
-
-                        if let Err(e) =
-                            tokio::io::copy_bidirectional(&mut upgraded, &mut server).await
-                        {
-                            eprintln!("Failed to tunnel unknown protocol to {}: {}", authority, e);
-                        }
-                    }
-                }
+                Ok(upgraded) => self.serve_tunnel(upgraded, authority).await,
                 Err(e) => eprintln!("Upgrade error {e}"),
             };
         };
@@ -147,7 +204,259 @@ where
         Ok(Response::new(Body::empty()))
     }
 
-    fn upgrade_websocket(self, req: Request<Body>) -> Response<Body> {
+    /// Performs the SOCKS5 handshake on `stream` and serves the requested connection like a
+    /// `CONNECT` tunnel.
+    pub(crate) async fn serve_socks5(self, mut stream: TcpStream) {
+        let (host, port) = match socks5::accept(&mut stream).await {
+            Ok(target) => target,
+            Err(e) => {
+                eprintln!("SOCKS5 handshake with {} failed: {e}", self.remote_addr);
+                return;
+            }
+        };
+
+        let authority = if host.contains(':') {
+            format!("[{host}]:{port}")
+        } else {
+            format!("{host}:{port}")
+        };
+
+        match Authority::try_from(authority.as_str()) {
+            Ok(authority) => self.serve_tunnel(stream, authority).await,
+            Err(e) => eprintln!("Invalid SOCKS5 destination {authority}: {e}"),
+        }
+    }
+
+    /// Serves a connection redirected to the proxy by the firewall like a `CONNECT` tunnel to
+    /// its original destination.
+    #[cfg(target_os = "linux")]
+    pub(crate) async fn serve_transparent(self, stream: TcpStream, listen_port: u16) {
+        let authority = match transparent::original_dst(&stream) {
+            // Relaying a connection made straight to the proxy would just loop back to it.
+            Ok(dst) if dst.port() == listen_port && stream.local_addr().ok() == Some(dst) => {
+                eprintln!(
+                    "Ignoring connection from {} that wasn't redirected",
+                    self.remote_addr
+                );
+                return;
+            }
+            Ok(dst) => Authority::try_from(dst.to_string().as_str()),
+            Err(e) => {
+                eprintln!(
+                    "Failed to get original destination of {}: {e}",
+                    self.remote_addr
+                );
+                return;
+            }
+        };
+
+        match authority {
+            Ok(authority) => self.serve_tunnel(stream, authority).await,
+            Err(e) => eprintln!("Invalid original destination: {e}"),
+        }
+    }
+
+    /// Serves a connection to the reverse proxy, terminating TLS for the host its client asks
+    /// for.
+    pub(crate) async fn serve_reverse(mut self, stream: TcpStream) {
+        self.mode = InterceptionMode::Reverse;
+        let (stream, protocol) = match sniff(stream).await {
+            Ok(sniffed) => sniffed,
+            Err(e) => {
+                eprintln!("Failed to read from {}: {e}", self.remote_addr);
+                return;
+            }
+        };
+
+        match protocol {
+            Protocol::Tls {
+                server_name: Some(server_name),
+            } => match Authority::try_from(server_name.as_str()) {
+                Ok(authority) => self.serve_tls(stream, &authority).await,
+                Err(e) => eprintln!("Invalid server name {server_name}: {e}"),
+            },
+            Protocol::Tls { server_name: None } => {
+                eprintln!("TLS client {} didn't send a server name", self.remote_addr);
+            }
+            Protocol::Http => {
+                if let Err(e) = self.serve_stream(stream, Scheme::HTTP).await {
+                    eprintln!("HTTP connect error: {e}");
+                }
+            }
+            Protocol::Unknown => {
+                eprintln!("Unknown protocol spoken by {}", self.remote_addr);
+            }
+        }
+    }
+
+    /// Sniffs the protocol spoken in a tunnel to `authority`: TLS is intercepted, plain HTTP is
+    /// served as is and anything else is relayed untouched.
+    async fn serve_tunnel<I>(mut self, stream: I, authority: Authority)
+    where
+        I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        self.mode = InterceptionMode::Tunnel;
+
+        // Whatever they carry, tunnels to passthrough hosts are never looked into.
+        if self.is_passthrough(authority.host()) {
+            let host = authority.host().to_owned();
+            self.relay(stream, &authority, host).await;
+            return;
+        }
+
+        let (stream, protocol) = match sniff(stream).await {
+            Ok(sniffed) => sniffed,
+            Err(e) => {
+                let error = FlowError::new(ErrorKind::ClientAbort, error::describe(&e));
+                self.tunnel_failed(&authority, authority.host().to_owned(), error)
+                    .await;
+                return;
+            }
+        };
+
+        match protocol {
+            // Clients may CONNECT to an address and only name the host in the ClientHello.
+            Protocol::Tls {
+                server_name: Some(server_name),
+            } if self.is_passthrough(&server_name) => {
+                self.relay(stream, &authority, server_name).await;
+            }
+            Protocol::Tls { server_name } => {
+                // The certificate has to match the name the client checks, which isn't
+                // necessarily the host it asked the proxy for, or known at all for redirected
+                // connections.
+                let authority = server_name
+                    .and_then(|name| {
+                        let port = authority.port_u16().unwrap_or(443);
+                        Authority::try_from(format!("{name}:{port}").as_str()).ok()
+                    })
+                    .unwrap_or(authority);
+
+                self.serve_tls(stream, &authority).await;
+            }
+            Protocol::Http => {
+                if let Err(e) = self.serve_stream(stream, Scheme::HTTP).await {
+                    eprintln!("Websocket connect error: {e}");
+                }
+            }
+            // Recorded as a tunnel once relayed.
+            Protocol::Unknown => {
+                let host = authority.host().to_owned();
+                self.relay(stream, &authority, host).await;
+            }
+        }
+    }
+
+    fn is_passthrough(&self, host: &str) -> bool {
+        self.passthrough.iter().any(|pattern| pattern.matches(host))
+    }
+
+    /// Relays `stream` to `authority` untouched and reports the tunnel to the handler as `host`.
+    async fn relay<I>(mut self, stream: I, authority: &Authority, host: String)
+    where
+        I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let port = authority.port_u16().unwrap_or(443);
+
+        let mut server = match self.upstream.connect(authority.host(), port).await {
+            Ok(server) => server,
+            Err(e) => {
+                let error = error::tunnel_error(&e, ErrorKind::Other);
+                self.tunnel_failed(authority, host, error).await;
+                return;
+            }
+        };
+
+        let start = chrono::Local::now();
+        let mut stream = Counted::new(stream);
+
+        let relayed = tokio::io::copy_bidirectional(&mut stream, &mut server).await;
+
+        let duration = chrono::Local::now() - start;
+        let mut tunnel = Tunnel::new(
+            host,
+            port,
+            stream.read(),
+            stream.written(),
+            start.timestamp_nanos_opt().unwrap_or_default(),
+            duration.num_nanoseconds().unwrap_or(i64::MAX),
+        );
+        if let Err(e) = relayed {
+            eprintln!("Failed to tunnel to {}: {}", authority, e);
+            tunnel = tunnel.with_error(error::tunnel_error(&e, ErrorKind::Other));
+        }
+
+        let ctx = self.context();
+        self.http_handler.handle_tunnel(&ctx, &tunnel).await;
+    }
+
+    /// Reports a tunnel to `authority`, named `host`, that failed before anything was relayed.
+    async fn tunnel_failed(mut self, authority: &Authority, host: String, error: FlowError) {
+        eprintln!("Tunnel to {authority} failed: {error}");
+
+        let tunnel = Tunnel::new(
+            host,
+            authority.port_u16().unwrap_or(443),
+            0,
+            0,
+            chrono::Local::now()
+                .timestamp_nanos_opt()
+                .unwrap_or_default(),
+            0,
+        )
+        .with_error(error);
+
+        let ctx = self.context();
+        self.http_handler.handle_tunnel(&ctx, &tunnel).await;
+    }
+
+    /// Terminates TLS with a certificate for `authority` and serves the HTTPS requests inside.
+    async fn serve_tls<I>(mut self, stream: I, authority: &Authority)
+    where
+        I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        // The client was already told the tunnel is open, all that's left is closing it.
+        let server_config = match self.ca.gen_server_config(authority).await {
+            Ok(server_config) => server_config,
+            Err(e) => {
+                let error = error::tunnel_error(&e, ErrorKind::Other);
+                self.tunnel_failed(authority, authority.host().to_owned(), error)
+                    .await;
+                return;
+            }
+        };
+
+        let stream = match TlsAcceptor::from(server_config).accept(stream).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                // A rustls error here is about the client, not upstream.
+                let error = FlowError::new(ErrorKind::ClientTls, error::describe(&e));
+                self.tunnel_failed(authority, authority.host().to_owned(), error)
+                    .await;
+                return;
+            }
+        };
+
+        let (_, session) = stream.get_ref();
+        if self.mode != InterceptionMode::Reverse {
+            self.mode = InterceptionMode::Mitm;
+        }
+        self.sni = session.sni_hostname().map(str::to_owned);
+        self.client_tls = tls::tls_info(session);
+
+        if let Err(e) = self.serve_stream(stream, Scheme::HTTPS).await {
+            if !e.to_string().starts_with("error shutting down connection") {
+                eprintln!("HTTPS connect error: {e}");
+            }
+        }
+    }
+
+    /// Answers the client's upgrade request and relays the WebSocket once upgraded, failing if
+    /// the request is malformed.
+    fn upgrade_websocket(
+        self,
+        req: Request<Body>,
+    ) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
         let mut req = {
             let (mut parts, _) = req.into_parts();
 
@@ -155,19 +464,18 @@ where
                 let mut parts = parts.uri.into_parts();
 
                 parts.scheme = if parts.scheme.unwrap_or(Scheme::HTTP) == Scheme::HTTP {
-                    Some("ws".try_into().expect("Failed to convert scheme"))
+                    Some("ws".parse()?)
                 } else {
-                    Some("wss".try_into().expect("Failed to convert scheme"))
+                    Some("wss".parse()?)
                 };
 
-                Uri::from_parts(parts).expect("Failed to build URI")
+                Uri::from_parts(parts)?
             };
 
             Request::from_parts(parts, ())
         };
 
-        let (res, websocket) =
-            hyper_tungstenite::upgrade(&mut req, None).expect("Request missing headers");
+        let (res, websocket) = hyper_tungstenite::upgrade(&mut req, None)?;
 
         let fut = async move {
             match websocket.await {
@@ -183,14 +491,115 @@ where
         };
 
         tokio::spawn(fut);
-        res
+        Ok(res)
     }
 
     async fn handle_websocket(
         self,
-        _server_socket: hyper_tungstenite::WebSocketStream<Upgraded>,
-        _req: Request<()>,
+        client_socket: hyper_tungstenite::WebSocketStream<Upgraded>,
+        mut req: Request<()>,
     ) -> Result<(), tungstenite::Error> {
+        let uri = req.uri().clone();
+
+        // tungstenite can't decode compressed frames, so don't let upstream negotiate them.
+        req.headers_mut()
+            .remove(hyper::header::SEC_WEBSOCKET_EXTENSIONS);
+
+        let mut handshake = Request::new(());
+        *handshake.method_mut() = req.method().clone();
+        *handshake.uri_mut() = uri.clone();
+        *handshake.version_mut() = req.version();
+        *handshake.headers_mut() = req.headers().clone();
+
+        let connecting = async {
+            let mut req = req;
+            let host = uri.host().unwrap_or_default();
+            let secure = uri.scheme_str() == Some("wss");
+            let port = uri.port_u16().unwrap_or(if secure { 443 } else { 80 });
+            let stream: Box<dyn Io> = Box::new(self.upstream.connect(host, port).await?);
+
+            // tungstenite's connector speaks another rustls version than the proxy's own, so
+            // without an explicit one TLS is set up here with the client's options.
+            let (stream, connector) = match &self.websocket_connector {
+                Some(connector) => (stream, connector.clone()),
+                None if secure => {
+                    let host = host.trim_start_matches('[').trim_end_matches(']');
+                    let server_name = ServerName::try_from(host).map_err(|_| {
+                        std::io::Error::new(std::io::ErrorKind::InvalidInput, "invalid dnsname")
+                    })?;
+                    let stream = TlsConnector::from(self.websocket_tls.get(host))
+                        .connect(server_name, stream)
+                        .await?;
+                    // The connection is already secured, tungstenite only sees a plain one.
+                    let mut parts = uri.clone().into_parts();
+                    parts.scheme = Some("ws".parse().expect("ws is a valid scheme"));
+                    *req.uri_mut() = Uri::from_parts(parts).expect("Only the scheme changed");
+
+                    (Box::new(stream) as Box<dyn Io>, Connector::Plain)
+                }
+                None => (stream, Connector::Plain),
+            };
+
+            tokio_tungstenite::client_async_tls_with_config(req, stream, None, Some(connector))
+                .await
+        };
+
+        let (server_socket, server_res) = match connecting.await {
+            Ok(server) => server,
+            Err(e) => {
+                let mut client_socket = client_socket;
+                let _ = client_socket
+                    .close(Some(CloseFrame {
+                        code: CloseCode::Again,
+                        reason: "Failed to connect to upstream server".into(),
+                    }))
+                    .await;
+                return Err(e);
+            }
+        };
+
+        let ctx = self.context();
+
+        let mut websocket_handler = self.websocket_handler;
+        websocket_handler
+            .handle_handshake(&ctx, &handshake, &server_res)
+            .await;
+
+        let (server_sink, server_stream) = server_socket.split();
+        let (client_sink, client_stream) = client_socket.split();
+
+        let server_to_client = spawn_message_forwarder(
+            server_stream,
+            client_sink,
+            websocket_handler.clone(),
+            WebSocketContext::ServerToClient {
+                src: uri.clone(),
+                dst: self.remote_addr,
+            },
+        );
+
+        let client_to_server = spawn_message_forwarder(
+            client_stream,
+            server_sink,
+            websocket_handler.clone(),
+            WebSocketContext::ClientToServer {
+                src: self.remote_addr,
+                dst: uri,
+            },
+        );
+
+        // The session is over as soon as either side closed or failed, the other direction only
+        // flushes the close reply.
+        tokio::spawn(async move {
+            let (ended, _) = future::select(server_to_client, client_to_server)
+                .await
+                .factor_first();
+
+            if let Ok((ctx, code, reason)) = ended {
+                websocket_handler.handle_close(&ctx, code, &reason).await;
+            }
+        });
+
         Ok(())
     }
 
@@ -198,25 +607,24 @@ where
     where
         I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
     {
-        let service = service_fn(|mut req| {
+        let service = service_fn(|mut req: Request<Body>| {
             if req.version() == hyper::Version::HTTP_10 || req.version() == hyper::Version::HTTP_11
             {
-                let (mut parts, body) = req.into_parts();
-
-                let authority = parts
-                    .headers
+                // Without a valid Host the URI is left without authority, the request is then
+                // answered with a 400.
+                let authority = req
+                    .headers()
                     .get(hyper::header::HOST)
-                    .expect("Host is a required header")
-                    .as_bytes();
-                parts.uri = {
-                    let mut parts = parts.uri.into_parts();
-                    parts.scheme = Some(scheme.clone());
-                    parts.authority =
-                        Some(Authority::try_from(authority).expect("Failed to parse authority"));
-                    Uri::from_parts(parts).expect("Failed to build URI")
-                };
+                    .and_then(|host| Authority::try_from(host.as_bytes()).ok());
 
-                req = Request::from_parts(parts, body);
+                if let Some(authority) = authority {
+                    let mut parts = req.uri().clone().into_parts();
+                    parts.scheme = Some(scheme.clone());
+                    parts.authority = Some(authority);
+                    if let Ok(uri) = Uri::from_parts(parts) {
+                        *req.uri_mut() = uri;
+                    }
+                }
             };
 
             self.clone().proxy(req)
@@ -229,6 +637,146 @@ where
     }
 }
 
+/// First four bytes of the request line of plain HTTP requests.
+const HTTP_METHODS: &[&[u8]] = &[
+    b"GET ", b"HEAD", b"POST", b"PUT ", b"DELE", b"OPTI", b"PATC", b"TRAC",
+];
+
+/// Protocol spoken by a client, as told by the first bytes it sends.
+enum Protocol {
+    Tls { server_name: Option<String> },
+    Http,
+    Unknown,
+}
+
+/// Reads enough of `stream` to tell which protocol its client speaks, handing back the stream
+/// with the bytes read put back.
+async fn sniff<I>(mut stream: I) -> std::io::Result<(Rewind<I>, Protocol)>
+where
+    I: AsyncRead + Unpin,
+{
+    let mut buffer = [0; 4];
+    let bytes_read = stream.read(&mut buffer).await?;
+    let mut read = buffer[..bytes_read].to_vec();
+
+    let protocol = if buffer[..2] == *b"\x16\x03" {
+        read_record(&mut stream, &mut read).await?;
+        Protocol::Tls {
+            server_name: sni::server_name(&read),
+        }
+    } else if HTTP_METHODS.contains(&&buffer[..bytes_read]) {
+        Protocol::Http
+    } else {
+        Protocol::Unknown
+    };
+
+    Ok((Rewind::new_buffered(stream, read.into()), protocol))
+}
+
+/// Reads the rest of the TLS record whose first bytes are in `buf`.
+async fn read_record<I>(stream: &mut I, buf: &mut Vec<u8>) -> std::io::Result<()>
+where
+    I: AsyncRead + Unpin,
+{
+    let mut chunk = [0; 1024];
+
+    loop {
+        let len = sni::record_len(buf).unwrap_or(5).min(sni::MAX_RECORD_LEN);
+        if buf.len() >= len {
+            return Ok(());
+        }
+
+        let wanted = (len - buf.len()).min(chunk.len());
+        let bytes_read = stream.read(&mut chunk[..wanted]).await?;
+        if bytes_read == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        buf.extend_from_slice(&chunk[..bytes_read]);
+    }
+}
+
+/// Relays messages in one direction until it ends, yielding the close code and reason it ended
+/// with.
+fn spawn_message_forwarder<W>(
+    mut stream: impl Stream<Item = Result<Message, tungstenite::Error>> + Unpin + Send + 'static,
+    mut sink: impl Sink<Message, Error = tungstenite::Error> + Unpin + Send + 'static,
+    mut handler: W,
+    ctx: WebSocketContext,
+) -> JoinHandle<(WebSocketContext, u16, String)>
+where
+    W: WebSocketHandler,
+{
+    let fut = async move {
+        let mut closed = None;
+
+        while let Some(message) = stream.next().await {
+            let message = match message {
+                Ok(message) => message,
+                Err(tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed) => {
+                    break
+                }
+                Err(e) => {
+                    eprintln!("Websocket message error: {e}");
+                    let _ = sink
+                        .send(Message::Close(Some(CloseFrame {
+                            code: CloseCode::Error,
+                            reason: "Peer connection failed".into(),
+                        })))
+                        .await;
+                    closed.get_or_insert((CloseCode::Abnormal.into(), e.to_string()));
+                    break;
+                }
+            };
+
+            if let Message::Close(frame) = &message {
+                closed.get_or_insert(match frame {
+                    Some(frame) => (frame.code.into(), frame.reason.to_string()),
+                    None => (CloseCode::Status.into(), String::new()),
+                });
+            }
+
+            let Some(message) = handler.handle_message(&ctx, message).await else {
+                continue;
+            };
+
+            // Each side already answered its own pings, relaying the pongs would duplicate them.
+            if message.is_pong() {
+                continue;
+            }
+
+            // Keep reading after a close frame, the next read flushes the close reply.
+            match sink.send(message).await {
+                Ok(()) => {}
+                Err(
+                    tungstenite::Error::ConnectionClosed
+                    | tungstenite::Error::AlreadyClosed
+                    | tungstenite::Error::Protocol(ProtocolError::SendAfterClosing),
+                ) => break,
+                Err(e) => {
+                    eprintln!("Websocket send error: {e}");
+                    closed.get_or_insert((CloseCode::Abnormal.into(), e.to_string()));
+                    break;
+                }
+            }
+        }
+
+        let (code, reason) = closed.unwrap_or_else(|| {
+            (
+                CloseCode::Abnormal.into(),
+                "Connection closed without a close frame".to_owned(),
+            )
+        });
+        (ctx, code, reason)
+    };
+
+    tokio::spawn(fut)
+}
+
+/// Upstream WebSocket connection, over TLS or not.
+trait Io: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Io for T {}
+
 fn normalize_request<T>(mut req: Request<T>) -> Request<T> {
     req.headers_mut().remove(hyper::header::HOST);
 
@@ -237,6 +785,9 @@ fn normalize_request<T>(mut req: Request<T>) -> Request<T> {
         cookies.insert(joined_cookies.try_into().expect("Failed to join cookies"));
     }
 
+    // hyper only negotiates the upstream protocol via ALPN for HTTP/1.1 requests, an HTTP/2
+    // request would force HTTP/2 even with servers that don't speak it. The version actually
+    // used upstream is the one of the response.
     *req.version_mut() = hyper::Version::HTTP_11;
     req
 }
@@ -1,94 +1,304 @@
+pub mod builder;
 mod internal;
-//pub mod builder;
 
 use std::{
     convert::Infallible,
     future::Future,
     net::SocketAddr,
     sync::{mpsc::SyncSender, Arc},
+    time::Duration,
 };
 
 use internal::InternalProxy;
 
-use crate::{ca::Ssl, error::Error, proxy_handler};
+use crate::{
+    ca::{CaStore, CertificateAuthority, Ssl},
+    error::Error,
+    host_pattern::HostPattern,
+    proxy_handler,
+    reverse::ReverseConfig,
+    tls::{ClientConfigs, UpstreamTlsConnector},
+    upstream::UpstreamConfig,
+    HttpHandler, NoopHandler, WebSocketHandler,
+};
 
-//use builder::{AddrListenerServer, WantsAddr};
+pub use builder::ProxyBuilder;
 
 use hyper::{
+    client::connect::Connect,
     server::conn::AddrStream,
     service::{make_service_fn, service_fn},
     Client, Server,
 };
 
-use hyper_rustls::HttpsConnectorBuilder;
+use proxyapi_models::InterceptionMode;
+use tokio::{net::TcpListener, sync::broadcast};
+use tokio_tungstenite::Connector;
 
-use tokio::sync::broadcast;
+/// Protocol spoken by clients connecting to the proxy.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum ProxyMode {
+    /// HTTP proxy, HTTPS and other protocols are reached with `CONNECT`.
+    #[default]
+    Http,
+    /// SOCKS5 proxy accepting unauthenticated `CONNECT` commands.
+    Socks5,
+    /// Transparent proxy for connections redirected to it by iptables `REDIRECT` or `TPROXY`
+    /// rules.
+    ///
+    /// The proxy's own upstream connections must be exempt from the rules, e.g. by matching on
+    /// `-m owner ! --uid-owner` when redirecting locally generated traffic.
+    #[cfg(target_os = "linux")]
+    Transparent,
+    /// Reverse proxy routing requests to the virtual hosts' upstreams.
+    ///
+    /// HTTPS is terminated with certificates for the server name clients send.
+    Reverse(Arc<ReverseConfig>),
+}
 
-pub struct Proxy {
+pub struct Proxy<
+    C = UpstreamTlsConnector,
+    CA = Ssl,
+    H = proxy_handler::ProxyHandler,
+    W = NoopHandler,
+> {
     addr: SocketAddr,
-    tx: Option<SyncSender<proxy_handler::ProxyHandler>>,
+    mode: ProxyMode,
+    client: Client<C>,
+    upstream: Arc<UpstreamConfig>,
+    ca: Arc<CA>,
+    passthrough: Arc<Vec<HostPattern>>,
+    http_handler: H,
+    websocket_handler: W,
+    websocket_connector: Option<Connector>,
+    websocket_tls: ClientConfigs,
+    response_timeout: Duration,
     shutdown: broadcast::Sender<()>,
 }
 
 impl Proxy {
-    pub fn new(addr: SocketAddr, tx: Option<SyncSender<proxy_handler::ProxyHandler>>) -> Self {
-        let (shutdown, _) = broadcast::channel(1);
-        Self { addr, tx, shutdown }
+    /// Creates a proxy with the default client and the CA of the default [`CaStore`] that sends
+    /// every captured exchange to `tx`.
+    ///
+    /// Fails if the CA can't be loaded, or generated on first use. Use [`ProxyBuilder`] to plug
+    /// in other handlers, a custom CA or client.
+    pub fn new(
+        addr: SocketAddr,
+        tx: Option<SyncSender<proxy_handler::ProxyHandler>>,
+    ) -> Result<Self, Error> {
+        let http_handler = tx.map(proxy_handler::ProxyHandler::new).unwrap_or_default();
+
+        Ok(ProxyBuilder::new()
+            .with_addr(addr)
+            .with_rustls_client()
+            .with_ca(Ssl::from_store(&CaStore::default())?)
+            .with_http_handler(http_handler)
+            .build())
     }
+}
 
+impl<C, CA, H, W> Proxy<C, CA, H, W>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+    CA: CertificateAuthority,
+    H: HttpHandler,
+    W: WebSocketHandler,
+{
     pub async fn start<F: Future<Output = ()>>(&self, signal: F) -> Result<(), Error> {
-        let addr = self.addr;
-        let tx = self.tx.clone();
         let mut shutdown_rx = self.shutdown.subscribe();
+        let shutdown = async move {
+            tokio::select! {
+                _ = signal => {}
+                _ = shutdown_rx.recv() => {}
+            }
+        };
 
-        let https = HttpsConnectorBuilder::new()
-            .with_webpki_roots()
-            .https_or_http()
-            .enable_http1()
-            .build();
+        match self.mode {
+            ProxyMode::Http => self.serve_http(shutdown).await,
+            ProxyMode::Socks5 => self.serve_socks5(shutdown).await,
+            #[cfg(target_os = "linux")]
+            ProxyMode::Transparent => self.serve_transparent(shutdown).await,
+            ProxyMode::Reverse(_) => self.serve_reverse(shutdown).await,
+        }
+    }
 
-        let client = Client::builder()
-            .http1_preserve_header_case(true)
-            .http1_title_case_headers(true)
-            .build(https);
+    pub fn shutdown(&self) {
+        let _ = self.shutdown.send(());
+    }
 
-        let server_builder = Server::try_bind(&addr)?
+    fn internal_proxy(&self, remote_addr: SocketAddr) -> InternalProxy<C, CA, H, W> {
+        InternalProxy {
+            ca: Arc::clone(&self.ca),
+            passthrough: Arc::clone(&self.passthrough),
+            client: self.client.clone(),
+            upstream: Arc::clone(&self.upstream),
+            reverse: match &self.mode {
+                ProxyMode::Reverse(reverse) => Some(Arc::clone(reverse)),
+                _ => None,
+            },
+            http_handler: self.http_handler.clone(),
+            websocket_handler: self.websocket_handler.clone(),
+            remote_addr,
+            connection_id: internal::next_connection_id(),
+            mode: InterceptionMode::Forward,
+            sni: None,
+            client_tls: None,
+            websocket_connector: self.websocket_connector.clone(),
+            websocket_tls: self.websocket_tls.clone(),
+            response_timeout: self.response_timeout,
+        }
+    }
+
+    async fn serve_http(&self, shutdown: impl Future<Output = ()>) -> Result<(), Error> {
+        let proxy = self.internal_proxy(self.addr);
+
+        let server_builder = Server::try_bind(&self.addr)?
             .http1_preserve_header_case(true)
             .http1_title_case_headers(true);
 
-        let ssl = Arc::new(Ssl::default());
-
         let make_service = make_service_fn(move |conn: &AddrStream| {
-            let client = client.clone();
-            let ca = Arc::clone(&ssl);
-            let tx = tx.clone();
-            let http_handler = proxy_handler::ProxyHandler::new(tx.unwrap());
-            let websocket_connector = None;
-            let remote_addr = conn.remote_addr();
-            async move {
-                Ok::<_, Infallible>(service_fn(move |req| {
-                    InternalProxy {
-                        ca: Arc::clone(&ca),
-                        client: client.clone(),
-                        http_handler: http_handler.clone(),
-                        remote_addr,
-                        websocket_connector: websocket_connector.clone(),
-                    }
-                    .proxy(req)
-                }))
-            }
+            let proxy = InternalProxy {
+                remote_addr: conn.remote_addr(),
+                connection_id: internal::next_connection_id(),
+                ..proxy.clone()
+            };
+            async move { Ok::<_, Infallible>(service_fn(move |req| proxy.clone().proxy(req))) }
         });
 
         server_builder
             .serve(make_service)
-            .with_graceful_shutdown(async move {
-                let _ = shutdown_rx.recv().await;
-            })
+            .with_graceful_shutdown(shutdown)
             .await
             .map_err(Into::into)
     }
 
-    pub fn shutdown(&self) {
-        let _ = self.shutdown.send(());
+    async fn serve_socks5(&self, shutdown: impl Future<Output = ()>) -> Result<(), Error> {
+        let listener = TcpListener::bind(self.addr).await?;
+        tokio::pin!(shutdown);
+
+        loop {
+            tokio::select! {
+                _ = &mut shutdown => return Ok(()),
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, remote_addr)) => {
+                        tokio::spawn(self.internal_proxy(remote_addr).serve_socks5(stream));
+                    }
+                    Err(e) => eprintln!("Failed to accept SOCKS5 connection: {e}"),
+                },
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn serve_transparent(&self, shutdown: impl Future<Output = ()>) -> Result<(), Error> {
+        let listener = crate::transparent::bind(self.addr)?;
+        let listen_port = listener.local_addr()?.port();
+        tokio::pin!(shutdown);
+
+        loop {
+            tokio::select! {
+                _ = &mut shutdown => return Ok(()),
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, remote_addr)) => {
+                        let proxy = self.internal_proxy(remote_addr);
+                        tokio::spawn(proxy.serve_transparent(stream, listen_port));
+                    }
+                    Err(e) => eprintln!("Failed to accept redirected connection: {e}"),
+                },
+            }
+        }
+    }
+
+    async fn serve_reverse(&self, shutdown: impl Future<Output = ()>) -> Result<(), Error> {
+        let listener = TcpListener::bind(self.addr).await?;
+        tokio::pin!(shutdown);
+
+        loop {
+            tokio::select! {
+                _ = &mut shutdown => return Ok(()),
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, remote_addr)) => {
+                        tokio::spawn(self.internal_proxy(remote_addr).serve_reverse(stream));
+                    }
+                    Err(e) => eprintln!("Failed to accept connection: {e}"),
+                },
+            }
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use crate::{FlowEvent, ProxyHandler};
+    use std::sync::mpsc;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    /// A connection that wasn't redirected is served for its local address, as TPROXY hands
+    /// them over.
+    #[tokio::test(flavor = "multi_thread")]
+    async fn intercepts_transparent_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let dst = listener.local_addr().unwrap();
+        let mut client = TcpStream::connect(dst).await.unwrap();
+        let (stream, remote_addr) = listener.accept().await.unwrap();
+
+        // The backend takes over the address the client connected to.
+        drop(listener);
+        let backend = TcpListener::bind(dst).await.unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = backend.accept().await.unwrap();
+            let mut head = Vec::new();
+            while !head.ends_with(b"\r\n\r\n") {
+                head.push(stream.read_u8().await.unwrap());
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")
+                .await
+                .unwrap();
+        });
+
+        let ca = tempfile::TempDir::new().unwrap();
+        let (cert, key) = CaStore::new(ca.path()).load_or_generate().unwrap();
+        let (tx, flows) = mpsc::sync_channel(100);
+        let proxy = ProxyBuilder::new()
+            .with_addr("127.0.0.1:0".parse().unwrap())
+            .with_rustls_client()
+            .with_ca(Ssl::new(cert.as_bytes(), key.as_bytes()).unwrap())
+            .with_http_handler(ProxyHandler::default().with_flow_sender(tx))
+            .with_mode(ProxyMode::Transparent)
+            .build();
+        // Any port but the one the connection was made to, which would be a loop.
+        tokio::spawn(
+            proxy
+                .internal_proxy(remote_addr)
+                .serve_transparent(stream, dst.port().wrapping_add(1)),
+        );
+
+        client
+            .write_all(
+                format!("GET / HTTP/1.1\r\nHost: {dst}\r\nConnection: close\r\n\r\n").as_bytes(),
+            )
+            .await
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert!(response.ends_with("ok"), "{response}");
+
+        let (request, response) = loop {
+            match flows.recv_timeout(Duration::from_secs(5)).unwrap() {
+                FlowEvent::ResponseComplete {
+                    request, response, ..
+                } => break (request, response),
+                _ => continue,
+            }
+        };
+        assert_eq!(request.uri().to_string(), format!("http://{dst}/"));
+        assert_eq!(request.client().mode(), Some(InterceptionMode::Tunnel));
+        assert_eq!(response.body().as_ref(), b"ok");
     }
 }
@@ -2,26 +2,168 @@
 // https://github.com/omjadas/hudsucker
 
 use async_trait::async_trait;
-use http::{Request, Response};
-use hyper::{body::to_bytes, Body};
-pub use proxyapi_models::{ProxiedRequest, ProxiedResponse};
-use std::sync::mpsc::SyncSender;
+use http::{HeaderMap, Method, Request, Response, StatusCode, Version};
+use hyper::{client::connect::HttpInfo, Body};
+pub use proxyapi_models::{
+    BodySize, ClientInfo, ErrorKind, Flow, FlowError, FlowEvent, FlowState, InterceptionMode,
+    Phase, ProxiedRequest, ProxiedResponse, ServerInfo, Timings, TlsInfo, Tunnel,
+    WebSocketDirection, WebSocketEvent, WebSocketFrame, WebSocketMessage, WebSocketSession,
+};
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    mpsc::SyncSender,
+    Arc, Mutex,
+};
+use tokio_tungstenite::tungstenite::Message;
 
-use crate::{HttpContext, HttpHandler, RequestResponse};
+use crate::{
+    body::{self, Captured},
+    error,
+    timing::{self, ConnectionTimings},
+    HttpContext, HttpHandler, RequestResponse, WebSocketContext, WebSocketHandler,
+};
 
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Bytes of each request and response body kept by default, see
+/// [`ProxyHandler::with_capture_limit`].
+pub const DEFAULT_CAPTURE_LIMIT: usize = 10 * 1024 * 1024;
+
+/// Records every exchange and sends it to the receiving end of `tx`.
+///
+/// The default handler has no receiver and drops the recorded exchanges.
+/// The lifecycle events of each exchange, WebSocket sessions and tunnels are only recorded once a
+/// sender is set with [`ProxyHandler::with_flow_sender`], [`ProxyHandler::with_websocket_sender`]
+/// and [`ProxyHandler::with_tunnel_sender`].
+///
+/// The handler runs on the proxy's workers and never waits on a receiver: when a channel is full
+/// the event is dropped and logged, so size the channels for the bursts the receiver lags behind.
 #[derive(Clone, Debug)]
 pub struct ProxyHandler {
-    tx: SyncSender<ProxyHandler>,
+    tx: Option<SyncSender<ProxyHandler>>,
     req: Option<ProxiedRequest>,
     res: Option<ProxiedResponse>,
+    capture_limit: usize,
+    websocket_tx: Option<SyncSender<WebSocketEvent>>,
+    session: Option<u64>,
+    tunnel_tx: Option<SyncSender<Tunnel>>,
+    flow_tx: Option<SyncSender<FlowEvent>>,
 }
 
-impl ProxyHandler {
-    pub fn new(tx: SyncSender<ProxyHandler>) -> Self {
+/// What is recorded of an exchange between the handler calls, kept in its
+/// [`ExchangeState`](crate::ExchangeState).
+#[derive(Clone)]
+struct Recording {
+    /// Whether the exchange is a flow, `CONNECT` and WebSocket upgrade requests aren't.
+    is_flow: bool,
+    request: ProxiedRequest,
+    request_body: Arc<Mutex<Option<Captured>>>,
+    /// When the request was sent upstream.
+    forwarded: Option<i64>,
+    /// Set for flows until their outcome is sent.
+    outcome: Option<Arc<Outcome>>,
+}
+
+/// Guard reporting a flow as aborted by the client when the exchange ends without a response or
+/// error being sent, e.g. because the client went away while upstream was still answering.
+struct Outcome {
+    handler: ProxyHandler,
+    flow: u64,
+    request: ProxiedRequest,
+    sent: AtomicBool,
+}
+
+impl Outcome {
+    fn sent(&self) {
+        self.sent.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Drop for Outcome {
+    fn drop(&mut self) {
+        if self.sent.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let error = FlowError::new(
+            ErrorKind::ClientAbort,
+            "The client went away before the response arrived".to_owned(),
+        );
+        // nginx's "Client Closed Request", the client never got a response.
+        let response = ProxiedResponse::new(
+            StatusCode::from_u16(499).expect("499 is a valid status code"),
+            Version::HTTP_11,
+            HeaderMap::new(),
+            error::diagnostic(&error).into(),
+            timing::now(),
+        )
+        .with_error(error);
+
+        self.handler.send_flow_event(FlowEvent::Error {
+            flow: self.flow,
+            response: Box::new(response.clone()),
+        });
+        self.handler.output(Some(self.request.clone()), response);
+    }
+}
+
+impl Default for ProxyHandler {
+    fn default() -> Self {
         Self {
-            tx,
+            tx: None,
             req: None,
             res: None,
+            capture_limit: DEFAULT_CAPTURE_LIMIT,
+            websocket_tx: None,
+            session: None,
+            tunnel_tx: None,
+            flow_tx: None,
+        }
+    }
+}
+
+impl ProxyHandler {
+    pub fn new(tx: SyncSender<ProxyHandler>) -> Self {
+        Self {
+            tx: Some(tx),
+            ..Default::default()
+        }
+    }
+
+    /// Keeps at most `limit` bytes of each body, the flow is marked as truncated beyond that.
+    ///
+    /// Bodies are streamed to the other side as they arrive whatever their size, the limit only
+    /// bounds how much of them is recorded.
+    pub fn with_capture_limit(self, limit: usize) -> Self {
+        Self {
+            capture_limit: limit,
+            ..self
+        }
+    }
+
+    pub fn with_websocket_sender(self, websocket_tx: SyncSender<WebSocketEvent>) -> Self {
+        Self {
+            websocket_tx: Some(websocket_tx),
+            ..self
+        }
+    }
+
+    pub fn with_tunnel_sender(self, tunnel_tx: SyncSender<Tunnel>) -> Self {
+        Self {
+            tunnel_tx: Some(tunnel_tx),
+            ..self
+        }
+    }
+
+    /// Sends the events of each exchange as they happen, including the ones that never get a
+    /// response.
+    ///
+    /// `CONNECT` and WebSocket upgrade requests have no flow, they are recorded as tunnels and
+    /// WebSocket sessions.
+    pub fn with_flow_sender(self, flow_tx: SyncSender<FlowEvent>) -> Self {
+        Self {
+            flow_tx: Some(flow_tx),
+            ..self
         }
     }
 
@@ -31,26 +173,67 @@ impl ProxyHandler {
 
     pub fn set_req(&mut self, req: ProxiedRequest) -> Self {
         Self {
-            tx: self.clone().tx,
             req: Some(req),
             res: None,
+            ..self.clone()
         }
     }
 
     pub fn set_res(&mut self, res: ProxiedResponse) -> Self {
         Self {
-            tx: self.clone().tx,
-            req: self.clone().req,
             res: Some(res),
+            ..self.clone()
         }
     }
 
     pub fn send_output(self) {
-        if let Err(e) = self.tx.send(self.clone()) {
+        let Some(tx) = &self.tx else {
+            return;
+        };
+
+        if let Err(e) = tx.try_send(self.clone()) {
             eprintln!("Error on sending Response to main thread: {}", e);
         }
     }
 
+    fn send_websocket_event(&self, event: WebSocketEvent) {
+        let Some(tx) = &self.websocket_tx else {
+            return;
+        };
+
+        if let Err(e) = tx.try_send(event) {
+            eprintln!("Error on sending WebSocket event to main thread: {}", e);
+        }
+    }
+
+    fn send_flow_event(&self, event: FlowEvent) {
+        let Some(tx) = &self.flow_tx else {
+            return;
+        };
+
+        if let Err(e) = tx.try_send(event) {
+            eprintln!("Error on sending flow event to main thread: {}", e);
+        }
+    }
+
+    /// Sends the exchange to the receiving end of `tx`, see [`ProxyHandler::new`].
+    fn output(&self, request: Option<ProxiedRequest>, response: ProxiedResponse) {
+        if self.tx.is_none() {
+            return;
+        }
+
+        Self {
+            req: request,
+            res: Some(response),
+            ..self.clone()
+        }
+        .send_output();
+    }
+
+    fn is_recording(&self) -> bool {
+        self.tx.is_some() || self.flow_tx.is_some()
+    }
+
     pub fn req(&self) -> &Option<ProxiedRequest> {
         &self.req
     }
@@ -83,11 +266,7 @@ impl ProxyHandler {
 
 #[async_trait]
 impl HttpHandler for ProxyHandler {
-    async fn handle_request(
-        &mut self,
-        _ctx: &HttpContext,
-        mut req: Request<Body>,
-    ) -> RequestResponse {
+    async fn handle_request(&mut self, ctx: &HttpContext, req: Request<Body>) -> RequestResponse {
         println!("\n=== 新请求 ===");
         println!(">>> 方法: {}", req.method());
         println!(">>> 完整 URL: {}", req.uri());
@@ -96,39 +275,61 @@ impl HttpHandler for ProxyHandler {
         for (name, value) in req.headers() {
             println!("    {}: {}", name, value.to_str().unwrap_or("无法解析的值"));
         }
+        println!("===============");
 
-        // 尝试读取和打印请求体
-        let mut body_mut = req.body_mut();
-        let body_bytes = to_bytes(&mut body_mut).await.unwrap_or_default();
-        if !body_bytes.is_empty() {
-            if let Ok(body_str) = String::from_utf8(body_bytes.to_vec()) {
-                println!(">>> 请求体:\n{}", body_str);
-            }
+        if !self.is_recording() {
+            return req.into();
         }
-        println!("===============");
 
-        *body_mut = Body::from(body_bytes.clone());
+        let is_flow =
+            req.method() != Method::CONNECT && !hyper_tungstenite::is_upgrade_request(&req);
+        let (parts, body) = req.into_parts();
 
-        let output_request = ProxiedRequest::new(
-            req.method().clone(),
-            req.uri().clone(),
-            req.version(),
-            req.headers().clone(),
-            body_bytes,
+        let request_body = Arc::new(Mutex::new(None));
+        let body = {
+            let request_body = Arc::clone(&request_body);
+            body::tee(body, self.capture_limit, move |captured| {
+                *request_body.lock().unwrap() = Some(captured);
+            })
+        };
+
+        let request = ProxiedRequest::new(
+            parts.method.clone(),
+            parts.uri.clone(),
+            parts.version,
+            parts.headers.clone(),
+            Default::default(),
             chrono::Local::now()
                 .timestamp_nanos_opt()
                 .unwrap_or_default(),
-        );
-        *self = self.set_req(output_request);
+        )
+        .with_client(ctx.client_info());
+        if is_flow {
+            self.send_flow_event(FlowEvent::RequestReceived {
+                flow: ctx.flow_id,
+                request: Box::new(request.clone()),
+            });
+        }
+        let outcome = is_flow.then(|| {
+            Arc::new(Outcome {
+                handler: self.clone(),
+                flow: ctx.flow_id,
+                request: request.clone(),
+                sent: AtomicBool::new(false),
+            })
+        });
+        ctx.exchange.insert(Recording {
+            is_flow,
+            request,
+            request_body,
+            forwarded: None,
+            outcome,
+        });
 
-        req.into()
+        Request::from_parts(parts, body).into()
     }
 
-    async fn handle_response(
-        &mut self,
-        _ctx: &HttpContext,
-        mut res: Response<Body>,
-    ) -> Response<Body> {
+    async fn handle_response(&mut self, ctx: &HttpContext, res: Response<Body>) -> Response<Body> {
         println!("\n=== 响应详情 ===");
         println!("<<< 状态: {}", res.status());
         println!("<<< 响应头:");
@@ -137,22 +338,372 @@ impl HttpHandler for ProxyHandler {
         }
         println!("===============");
 
-        let mut body_mut = res.body_mut();
-        let body_bytes = to_bytes(&mut body_mut).await.unwrap_or_default();
-        *body_mut = Body::from(body_bytes.clone());
+        if !self.is_recording() {
+            return res;
+        }
+
+        let recording = ctx.exchange.get::<Recording>();
+        let (parts, body) = res.into_parts();
+        let time = chrono::Local::now()
+            .timestamp_nanos_opt()
+            .unwrap_or_default();
+        // Responses made up by the proxy have neither.
+        let server = ServerInfo::new(
+            parts
+                .extensions
+                .get::<HttpInfo>()
+                .map(HttpInfo::remote_addr),
+            parts.extensions.get::<TlsInfo>().cloned(),
+        );
+        let timings = recording
+            .as_ref()
+            .and_then(|recording| recording.forwarded)
+            .map(|start| {
+                timing::exchange_timings(start, time, parts.extensions.get::<ConnectionTimings>())
+            });
+        let is_flow = recording
+            .as_ref()
+            .is_some_and(|recording| recording.is_flow);
+
+        if is_flow {
+            let mut response = ProxiedResponse::new(
+                parts.status,
+                parts.version,
+                parts.headers.clone(),
+                Default::default(),
+                time,
+            )
+            .with_server(server.clone());
+            if let Some(timings) = timings {
+                response = response.with_timings(timings);
+            }
+            self.send_flow_event(FlowEvent::ResponseHeaders {
+                flow: ctx.flow_id,
+                response: Box::new(response),
+            });
+        }
+
+        // The exchange is only sent once the response body streamed through, by which time the
+        // request body has almost always been sent too.
+        let handler = self.clone();
+        let flow = ctx.flow_id;
+        let status = parts.status;
+        let version = parts.version;
+        let headers = parts.headers.clone();
+        let body = body::tee(body, self.capture_limit, move |captured| {
+            if let Some(outcome) = recording
+                .as_ref()
+                .and_then(|recording| recording.outcome.as_ref())
+            {
+                outcome.sent();
+            }
+            let request = recording.map(|recording| {
+                let request_body = recording.request_body.lock().unwrap().take();
+                match request_body {
+                    Some(request_body) => {
+                        let body_size = request_body.body_size(recording.request.headers());
+                        recording
+                            .request
+                            .with_body(request_body.body)
+                            .with_truncated(request_body.truncated)
+                            .with_body_size(body_size)
+                    }
+                    None => recording.request,
+                }
+            });
+
+            let body_size = captured.body_size(&headers);
+            let mut response = ProxiedResponse::new(status, version, headers, captured.body, time)
+                .with_truncated(captured.truncated)
+                .with_server(server)
+                .with_body_size(body_size);
+            if let Some(timings) = timings {
+                response = response.with_timings(timings.with_download(timing::now() - time));
+            }
+
+            if let (true, Some(request)) = (is_flow, &request) {
+                handler.send_flow_event(FlowEvent::ResponseComplete {
+                    flow,
+                    request: Box::new(request.clone()),
+                    response: Box::new(response.clone()),
+                });
+            }
+            handler.output(request, response);
+        });
+
+        Response::from_parts(parts, body)
+    }
 
-        let output_response = ProxiedResponse::new(
+    async fn handle_error(&mut self, ctx: &HttpContext, error: &FlowError) -> Response<Body> {
+        eprintln!("Failed to forward request: {error}");
+
+        let res = error::error_response(error);
+        if !self.is_recording() {
+            return res;
+        }
+
+        let response = ProxiedResponse::new(
             res.status(),
             res.version(),
             res.headers().clone(),
-            body_bytes,
+            error::diagnostic(error).into(),
             chrono::Local::now()
                 .timestamp_nanos_opt()
                 .unwrap_or_default(),
-        );
+        )
+        .with_error(error.clone());
 
-        self.set_res(output_response).send_output();
+        let recording = ctx.exchange.get::<Recording>();
+        if let Some(outcome) = recording
+            .as_ref()
+            .and_then(|recording| recording.outcome.as_ref())
+        {
+            outcome.sent();
+        }
+        if recording
+            .as_ref()
+            .is_some_and(|recording| recording.is_flow)
+        {
+            self.send_flow_event(FlowEvent::Error {
+                flow: ctx.flow_id,
+                response: Box::new(response.clone()),
+            });
+        }
+        self.output(recording.map(|recording| recording.request), response);
 
         res
     }
+
+    async fn handle_forward(&mut self, ctx: &HttpContext, _req: &Request<Body>) {
+        let time = timing::now();
+        let is_flow = ctx
+            .exchange
+            .update(|recording: &mut Recording| {
+                recording.forwarded = Some(time);
+                recording.is_flow
+            })
+            .unwrap_or_default();
+
+        if is_flow {
+            self.send_flow_event(FlowEvent::RequestForwarded {
+                flow: ctx.flow_id,
+                time,
+            });
+        }
+    }
+
+    async fn handle_tunnel(&mut self, _ctx: &HttpContext, tunnel: &Tunnel) {
+        let Some(tx) = &self.tunnel_tx else {
+            return;
+        };
+
+        if let Err(e) = tx.try_send(tunnel.clone()) {
+            eprintln!("Error on sending tunnel to main thread: {}", e);
+        }
+    }
+}
+
+#[async_trait]
+impl WebSocketHandler for ProxyHandler {
+    async fn handle_handshake(
+        &mut self,
+        ctx: &HttpContext,
+        req: &Request<()>,
+        res: &Response<Option<Vec<u8>>>,
+    ) {
+        if self.websocket_tx.is_none() {
+            return;
+        }
+
+        let time = chrono::Local::now()
+            .timestamp_nanos_opt()
+            .unwrap_or_default();
+
+        let request = ProxiedRequest::new(
+            req.method().clone(),
+            req.uri().clone(),
+            req.version(),
+            req.headers().clone(),
+            Default::default(),
+            time,
+        )
+        .with_client(ctx.client_info());
+
+        let response = ProxiedResponse::new(
+            res.status(),
+            res.version(),
+            res.headers().clone(),
+            res.body().clone().unwrap_or_default().into(),
+            time,
+        );
+
+        let id = NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed);
+        self.session = Some(id);
+        self.send_websocket_event(WebSocketEvent::Opened(Box::new(WebSocketSession::new(
+            id, request, response,
+        ))));
+    }
+
+    async fn handle_message(
+        &mut self,
+        ctx: &WebSocketContext,
+        message: Message,
+    ) -> Option<Message> {
+        let Some(session) = self.session else {
+            return Some(message);
+        };
+
+        let direction = match ctx {
+            WebSocketContext::ClientToServer { .. } => WebSocketDirection::ClientToServer,
+            WebSocketContext::ServerToClient { .. } => WebSocketDirection::ServerToClient,
+        };
+
+        let recorded = match &message {
+            Message::Text(text) => WebSocketMessage::Text(text.clone()),
+            Message::Binary(data) => WebSocketMessage::Binary(data.clone().into()),
+            Message::Ping(data) => WebSocketMessage::Ping(data.clone().into()),
+            Message::Pong(data) => WebSocketMessage::Pong(data.clone().into()),
+            Message::Close(frame) => WebSocketMessage::Close(
+                frame
+                    .as_ref()
+                    .map(|frame| (frame.code.into(), frame.reason.to_string())),
+            ),
+            // Raw frames are never yielded when reading a message.
+            Message::Frame(_) => return Some(message),
+        };
+
+        let frame = WebSocketFrame::new(
+            direction,
+            recorded,
+            chrono::Local::now()
+                .timestamp_nanos_opt()
+                .unwrap_or_default(),
+        );
+        self.send_websocket_event(WebSocketEvent::Frame { session, frame });
+
+        Some(message)
+    }
+
+    async fn handle_close(&mut self, _ctx: &WebSocketContext, code: u16, reason: &str) {
+        if let Some(session) = self.session {
+            self.send_websocket_event(WebSocketEvent::Closed {
+                session,
+                code,
+                reason: reason.to_owned(),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ExchangeState;
+    use std::sync::mpsc;
+
+    fn context(flow_id: u64) -> HttpContext {
+        HttpContext {
+            remote_addr: ([127, 0, 0, 1], 50000).into(),
+            flow_id,
+            connection_id: 0,
+            mode: InterceptionMode::Forward,
+            sni: None,
+            client_tls: None,
+            exchange: ExchangeState::default(),
+        }
+    }
+
+    fn request(uri: &str) -> Request<Body> {
+        Request::post(uri).body(Body::from(uri.to_owned())).unwrap()
+    }
+
+    async fn forward(handler: &mut ProxyHandler, ctx: &HttpContext, uri: &str) {
+        let RequestResponse::Request(req) =
+            HttpHandler::handle_request(handler, ctx, request(uri)).await
+        else {
+            panic!("the handler answered the request itself");
+        };
+        hyper::body::to_bytes(req.into_body()).await.unwrap();
+        HttpHandler::handle_forward(handler, ctx, &request(uri)).await;
+    }
+
+    /// The proxy shares one handler between the exchanges of a connection, and their calls
+    /// interleave.
+    #[tokio::test]
+    async fn pairs_interleaved_exchanges() {
+        let (tx, flows) = mpsc::sync_channel(100);
+        let mut handler = ProxyHandler::default().with_flow_sender(tx);
+        let (first, second) = (context(1), context(2));
+
+        forward(&mut handler, &first, "http://first.test/").await;
+        forward(&mut handler, &second, "http://second.test/").await;
+        for (ctx, body) in [(&second, "second"), (&first, "first")] {
+            let res =
+                HttpHandler::handle_response(&mut handler, ctx, Response::new(Body::from(body)))
+                    .await;
+            hyper::body::to_bytes(res.into_body()).await.unwrap();
+        }
+        // Completed exchanges aren't reported as aborted once they end.
+        drop((first, second));
+
+        let events: Vec<_> = flows.try_iter().collect();
+        assert!(!events
+            .iter()
+            .any(|event| matches!(event, FlowEvent::Error { .. })));
+        let completed: Vec<_> = events
+            .into_iter()
+            .filter_map(|event| match event {
+                FlowEvent::ResponseComplete {
+                    flow,
+                    request,
+                    response,
+                } => Some((flow, request, response)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(completed.len(), 2);
+        for (flow, request, response) in completed {
+            let name = if flow == 1 { "first" } else { "second" };
+            let uri = format!("http://{name}.test/");
+            assert_eq!(request.uri(), &uri[..]);
+            assert_eq!(&request.body()[..], uri.as_bytes());
+            assert_eq!(&response.body()[..], name.as_bytes());
+            let timings = response.timings().unwrap();
+            assert!(timings.get(Phase::Wait).is_some_and(|wait| wait >= 0));
+            assert!(timings
+                .get(Phase::Download)
+                .is_some_and(|download| download >= 0));
+        }
+    }
+
+    #[tokio::test]
+    async fn skips_connect_requests() {
+        let (tx, flows) = mpsc::sync_channel(100);
+        let mut handler = ProxyHandler::default().with_flow_sender(tx);
+        let ctx = context(1);
+
+        let req = Request::connect("example.com:443")
+            .body(Body::empty())
+            .unwrap();
+        HttpHandler::handle_request(&mut handler, &ctx, req).await;
+        let res =
+            HttpHandler::handle_response(&mut handler, &ctx, Response::new(Body::empty())).await;
+        hyper::body::to_bytes(res.into_body()).await.unwrap();
+
+        assert!(flows.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn drops_events_when_the_receiver_lags() {
+        // Nothing is ever received, every send finds the channel full.
+        let (tx, _flows) = mpsc::sync_channel(0);
+        let mut handler = ProxyHandler::default().with_flow_sender(tx);
+
+        tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            forward(&mut handler, &context(1), "http://lagging.test/"),
+        )
+        .await
+        .expect("the handler waited on the receiver");
+    }
 }
@@ -0,0 +1,169 @@
+use http::{
+    header::{HeaderValue, HOST},
+    uri::{PathAndQuery, Uri},
+    Request,
+};
+
+use crate::host_pattern::HostPattern;
+
+const X_FORWARDED_HOST: &str = "x-forwarded-host";
+const X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+
+/// Virtual hosts served in [`ProxyMode::Reverse`](crate::ProxyMode::Reverse).
+///
+/// Requests are routed by their `Host` to the upstream of the first matching virtual host. The
+/// upstream is a base URI such as `http://127.0.0.1:3000`, its path (if any) is prepended to the
+/// request's.
+///
+/// ```
+/// use proxyapi::reverse::ReverseConfig;
+///
+/// let config = ReverseConfig::new()
+///     .with_host("api.test".parse().unwrap(), "http://127.0.0.1:3000".parse().unwrap())
+///     .with_host("web.test".parse().unwrap(), "http://127.0.0.1:5173".parse().unwrap());
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReverseConfig {
+    hosts: Vec<(HostPattern, Uri)>,
+}
+
+impl ReverseConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sends requests for hosts matching `pattern` to `upstream`.
+    pub fn with_host(mut self, pattern: HostPattern, upstream: Uri) -> Self {
+        self.hosts.push((pattern, upstream));
+        self
+    }
+
+    /// Returns the upstream serving `host`.
+    pub fn route(&self, host: &str) -> Option<&Uri> {
+        self.hosts
+            .iter()
+            .find(|(pattern, _)| pattern.matches(host))
+            .map(|(_, upstream)| upstream)
+    }
+
+    /// Points `req` at the upstream serving its host, returning `false` if there is none.
+    ///
+    /// The `Host` header is set to the upstream's and the one the client used is passed along in
+    /// `X-Forwarded-Host`.
+    pub(crate) fn rewrite<T>(&self, req: &mut Request<T>) -> bool {
+        let Some(upstream) = req.uri().host().and_then(|host| self.route(host)) else {
+            return false;
+        };
+        let (Some(scheme), Some(authority)) = (upstream.scheme(), upstream.authority()) else {
+            return false;
+        };
+
+        let path_and_query = match req.uri().path_and_query() {
+            Some(pq) => format!("{}{}", upstream.path().trim_end_matches('/'), pq),
+            None => upstream.path().to_owned(),
+        };
+        let Ok(path_and_query) = path_and_query.parse::<PathAndQuery>() else {
+            return false;
+        };
+
+        let mut parts = req.uri().clone().into_parts();
+        parts.scheme = Some(scheme.clone());
+        parts.authority = Some(authority.clone());
+        parts.path_and_query = Some(path_and_query);
+        let Ok(uri) = Uri::from_parts(parts) else {
+            return false;
+        };
+
+        let forwarded_proto = req.uri().scheme_str().unwrap_or("http").to_owned();
+        let headers = req.headers_mut();
+
+        if let Some(host) = headers.remove(HOST) {
+            headers.insert(X_FORWARDED_HOST, host);
+        }
+        if let Ok(proto) = HeaderValue::from_str(&forwarded_proto) {
+            headers.insert(X_FORWARDED_PROTO, proto);
+        }
+        if let Ok(host) = HeaderValue::from_str(authority.as_str()) {
+            headers.insert(HOST, host);
+        }
+
+        *req.uri_mut() = uri;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> ReverseConfig {
+        ReverseConfig::new()
+            .with_host(
+                "api.test".parse().unwrap(),
+                "https://127.0.0.1:3000/v1/".parse().unwrap(),
+            )
+            .with_host(
+                "*.web.test".parse().unwrap(),
+                "http://127.0.0.1:5173".parse().unwrap(),
+            )
+    }
+
+    #[test]
+    fn routes_by_host() {
+        let config = config();
+
+        assert_eq!(
+            config.route("API.test."),
+            Some(&"https://127.0.0.1:3000/v1/".parse().unwrap())
+        );
+        assert_eq!(
+            config.route("app.web.test"),
+            Some(&"http://127.0.0.1:5173".parse().unwrap())
+        );
+        assert_eq!(config.route("other.test"), None);
+    }
+
+    #[test]
+    fn rewrites_requests_to_the_upstream() {
+        let mut req = Request::builder()
+            .uri("http://api.test/users?id=1")
+            .header(HOST, "api.test")
+            .body(())
+            .unwrap();
+
+        assert!(config().rewrite(&mut req));
+        assert_eq!(req.uri(), "https://127.0.0.1:3000/v1/users?id=1");
+        assert_eq!(req.headers()[HOST], "127.0.0.1:3000");
+        assert_eq!(req.headers()[X_FORWARDED_HOST], "api.test");
+        assert_eq!(req.headers()[X_FORWARDED_PROTO], "http");
+    }
+
+    #[test]
+    fn forwards_the_client_scheme() {
+        let mut req = Request::builder()
+            .uri("https://app.web.test:8443/")
+            .header(HOST, "app.web.test:8443")
+            .body(())
+            .unwrap();
+
+        assert!(config().rewrite(&mut req));
+        assert_eq!(req.uri(), "http://127.0.0.1:5173/");
+        assert_eq!(req.headers()[HOST], "127.0.0.1:5173");
+        assert_eq!(req.headers()[X_FORWARDED_HOST], "app.web.test:8443");
+        assert_eq!(req.headers()[X_FORWARDED_PROTO], "https");
+    }
+
+    #[test]
+    fn leaves_unknown_hosts_alone() {
+        let mut req = Request::builder()
+            .uri("http://other.test/")
+            .header(HOST, "other.test")
+            .body(())
+            .unwrap();
+
+        assert!(!config().rewrite(&mut req));
+        assert_eq!(req.uri(), "http://other.test/");
+        assert_eq!(req.headers()[HOST], "other.test");
+        assert!(!req.headers().contains_key(X_FORWARDED_HOST));
+    }
+}
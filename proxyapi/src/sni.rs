@@ -0,0 +1,205 @@
+/// Largest TLS record a ClientHello can be sent in.
+pub(crate) const MAX_RECORD_LEN: usize = 5 + 16384;
+
+const CONTENT_TYPE_HANDSHAKE: u8 = 0x16;
+const HANDSHAKE_CLIENT_HELLO: u8 = 0x01;
+const EXTENSION_SERVER_NAME: u16 = 0x0000;
+const NAME_TYPE_HOST_NAME: u8 = 0x00;
+
+/// Returns the length of the TLS record starting `buf`, once its header has been read.
+pub(crate) fn record_len(buf: &[u8]) -> Option<usize> {
+    if buf.len() < 5 || buf[0] != CONTENT_TYPE_HANDSHAKE {
+        return None;
+    }
+
+    Some(5 + u16::from_be_bytes([buf[3], buf[4]]) as usize)
+}
+
+/// Extracts the server name indication from a TLS record holding a ClientHello.
+pub(crate) fn server_name(record: &[u8]) -> Option<String> {
+    let mut reader = Reader(record);
+
+    if reader.u8()? != CONTENT_TYPE_HANDSHAKE {
+        return None;
+    }
+    reader.skip(2)?;
+    let mut reader = Reader(reader.vec16()?);
+
+    if reader.u8()? != HANDSHAKE_CLIENT_HELLO {
+        return None;
+    }
+    let len = reader.u24()?;
+    let mut hello = Reader(reader.take(len)?);
+
+    // client_version, random, session_id, cipher_suites, compression_methods
+    hello.skip(2 + 32)?;
+    hello.vec8()?;
+    hello.vec16()?;
+    hello.vec8()?;
+
+    let mut extensions = Reader(hello.vec16()?);
+    while !extensions.0.is_empty() {
+        let kind = extensions.u16()?;
+        let data = extensions.vec16()?;
+
+        if kind != EXTENSION_SERVER_NAME {
+            continue;
+        }
+
+        let mut names = Reader(Reader(data).vec16()?);
+        while !names.0.is_empty() {
+            let name_type = names.u8()?;
+            let name = names.vec16()?;
+
+            if name_type == NAME_TYPE_HOST_NAME {
+                return std::str::from_utf8(name).ok().map(str::to_owned);
+            }
+        }
+    }
+
+    None
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Some(head)
+    }
+
+    fn skip(&mut self, len: usize) -> Option<()> {
+        self.take(len).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u24(&mut self) -> Option<usize> {
+        self.take(3)
+            .map(|b| u32::from_be_bytes([0, b[0], b[1], b[2]]) as usize)
+    }
+
+    fn vec8(&mut self) -> Option<&'a [u8]> {
+        let len = self.u8()? as usize;
+        self.take(len)
+    }
+
+    fn vec16(&mut self) -> Option<&'a [u8]> {
+        let len = self.u16()? as usize;
+        self.take(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn vec16(data: &[u8]) -> Vec<u8> {
+        let mut out = (data.len() as u16).to_be_bytes().to_vec();
+        out.extend_from_slice(data);
+        out
+    }
+
+    fn sni_extension(name: &str) -> (u16, Vec<u8>) {
+        let mut entry = vec![NAME_TYPE_HOST_NAME];
+        entry.extend(vec16(name.as_bytes()));
+        (EXTENSION_SERVER_NAME, vec16(&entry))
+    }
+
+    /// Builds a TLS record holding a ClientHello with the given extensions.
+    fn client_hello(extensions: &[(u16, Vec<u8>)]) -> Vec<u8> {
+        let mut hello = vec![0x03, 0x03];
+        hello.extend([0; 32]);
+        hello.push(0);
+        hello.extend(vec16(&[0x13, 0x01]));
+        hello.extend([1, 0]);
+
+        let mut encoded = Vec::new();
+        for (kind, data) in extensions {
+            encoded.extend(kind.to_be_bytes());
+            encoded.extend(vec16(data));
+        }
+        hello.extend(vec16(&encoded));
+
+        let mut handshake = vec![HANDSHAKE_CLIENT_HELLO];
+        handshake.extend(&(hello.len() as u32).to_be_bytes()[1..]);
+        handshake.extend(hello);
+
+        let mut record = vec![CONTENT_TYPE_HANDSHAKE, 0x03, 0x01];
+        record.extend(vec16(&handshake));
+        record
+    }
+
+    #[test]
+    fn extracts_server_name() {
+        let valid = client_hello(&[sni_extension("example.com")]);
+        let after_other = client_hello(&[(0x000a, vec![0, 2, 0, 0x1d]), sni_extension("a.test")]);
+        let without_sni = client_hello(&[(0x000a, vec![0, 2, 0, 0x1d])]);
+        let without_extensions = client_hello(&[]);
+        let truncated = valid[..valid.len() - 4].to_vec();
+        let mut oversized = valid.clone();
+        oversized[3..5].copy_from_slice(&u16::MAX.to_be_bytes());
+        let mut oversized_hello = valid.clone();
+        oversized_hello[6..9].copy_from_slice(&[0xff, 0xff, 0xff]);
+        let mut oversized_name = valid.clone();
+        let len = oversized_name.len();
+        oversized_name[len - 13..len - 11].copy_from_slice(&0x0100u16.to_be_bytes());
+        let mut application_data = valid.clone();
+        application_data[0] = 0x17;
+        let mut server_hello = valid.clone();
+        server_hello[5] = 0x02;
+
+        let cases: &[(&str, &[u8], Option<&str>)] = &[
+            ("valid SNI", &valid, Some("example.com")),
+            ("SNI after another extension", &after_other, Some("a.test")),
+            ("no SNI extension", &without_sni, None),
+            ("no extensions", &without_extensions, None),
+            ("truncated record", &truncated, None),
+            ("record length past the end", &oversized, None),
+            ("handshake length past the end", &oversized_hello, None),
+            ("name length past the end", &oversized_name, None),
+            ("non-handshake record", &application_data, None),
+            ("other handshake message", &server_hello, None),
+            ("empty", &[], None),
+        ];
+
+        for (name, record, expected) in cases {
+            assert_eq!(server_name(record).as_deref(), *expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn extracts_server_name_from_rustls() {
+        let config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(rustls::RootCertStore::empty())
+            .with_no_client_auth();
+        let mut client =
+            rustls::ClientConnection::new(Arc::new(config), "proxelar.example".try_into().unwrap())
+                .unwrap();
+        let mut record = Vec::new();
+        client.write_tls(&mut record).unwrap();
+
+        assert_eq!(record_len(&record), Some(record.len()));
+        assert_eq!(server_name(&record).as_deref(), Some("proxelar.example"));
+    }
+
+    #[test]
+    fn reads_record_len() {
+        assert_eq!(record_len(&[0x16, 0x03, 0x01, 0x02, 0x00]), Some(5 + 512));
+        assert_eq!(record_len(&[0x16, 0x03, 0x01, 0x02]), None);
+        assert_eq!(record_len(b"GET / HTTP/1.1\r\n"), None);
+    }
+}
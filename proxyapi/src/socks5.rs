@@ -0,0 +1,372 @@
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::upstream::Credentials;
+
+pub(crate) const VERSION: u8 = 0x05;
+
+pub(crate) const NO_AUTH: u8 = 0x00;
+pub(crate) const USERNAME_PASSWORD: u8 = 0x02;
+pub(crate) const NO_ACCEPTABLE_METHODS: u8 = 0xFF;
+
+pub(crate) const CMD_CONNECT: u8 = 0x01;
+
+pub(crate) const ATYP_IPV4: u8 = 0x01;
+pub(crate) const ATYP_DOMAIN: u8 = 0x03;
+pub(crate) const ATYP_IPV6: u8 = 0x04;
+
+pub(crate) const REPLY_SUCCEEDED: u8 = 0x00;
+pub(crate) const REPLY_COMMAND_NOT_SUPPORTED: u8 = 0x07;
+pub(crate) const REPLY_ADDRESS_TYPE_NOT_SUPPORTED: u8 = 0x08;
+
+/// Asks the SOCKS5 server on the other end of `stream` to connect to `host:port`.
+pub(crate) async fn connect<S>(
+    stream: &mut S,
+    host: &str,
+    port: u16,
+    auth: Option<&Credentials>,
+) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let method = if auth.is_some() {
+        USERNAME_PASSWORD
+    } else {
+        NO_AUTH
+    };
+    stream.write_all(&[VERSION, 1, method]).await?;
+
+    let mut reply = [0; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != VERSION {
+        return Err(invalid_data("upstream proxy is not a SOCKS5 server"));
+    }
+    if reply[1] != method {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "upstream SOCKS5 proxy refused the authentication method",
+        ));
+    }
+
+    if let Some(auth) = auth {
+        let mut request = vec![0x01];
+        for field in [&auth.username, &auth.password] {
+            let len = u8::try_from(field.len())
+                .map_err(|_| invalid_input("SOCKS5 credentials are longer than 255 bytes"))?;
+            request.push(len);
+            request.extend_from_slice(field.as_bytes());
+        }
+        stream.write_all(&request).await?;
+
+        stream.read_exact(&mut reply).await?;
+        if reply[1] != 0x00 {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "upstream SOCKS5 proxy rejected the credentials",
+            ));
+        }
+    }
+
+    let mut request = vec![VERSION, CMD_CONNECT, 0x00];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            request.push(ATYP_IPV4);
+            request.extend_from_slice(&ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            request.push(ATYP_IPV6);
+            request.extend_from_slice(&ip.octets());
+        }
+        Err(_) => {
+            let len = u8::try_from(host.len())
+                .map_err(|_| invalid_input("host name is longer than 255 bytes"))?;
+            request.push(ATYP_DOMAIN);
+            request.push(len);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0; 3];
+    stream.read_exact(&mut reply).await?;
+    if reply[1] != REPLY_SUCCEEDED {
+        return Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!(
+                "upstream SOCKS5 proxy failed to connect to {host}:{port} (reply {:#04x})",
+                reply[1]
+            ),
+        ));
+    }
+
+    // The address the proxy bound to is of no use to us.
+    read_addr(stream).await?;
+    Ok(())
+}
+
+/// Runs the server side of the handshake and returns the host and port the client asked for.
+///
+/// Only unauthenticated `CONNECT` requests are accepted, the success reply is sent right away.
+pub(crate) async fn accept<S>(stream: &mut S) -> io::Result<(String, u16)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut greeting = [0; 2];
+    stream.read_exact(&mut greeting).await?;
+    if greeting[0] != VERSION {
+        return Err(invalid_data("client does not speak SOCKS5"));
+    }
+
+    let mut methods = vec![0; greeting[1].into()];
+    stream.read_exact(&mut methods).await?;
+    if !methods.contains(&NO_AUTH) {
+        stream.write_all(&[VERSION, NO_ACCEPTABLE_METHODS]).await?;
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "client does not support unauthenticated SOCKS5",
+        ));
+    }
+    stream.write_all(&[VERSION, NO_AUTH]).await?;
+
+    let mut request = [0; 3];
+    stream.read_exact(&mut request).await?;
+    if request[0] != VERSION {
+        return Err(invalid_data("client does not speak SOCKS5"));
+    }
+
+    let target = match read_addr(stream).await {
+        Ok(target) => target,
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+            reply(stream, REPLY_ADDRESS_TYPE_NOT_SUPPORTED).await?;
+            return Err(e);
+        }
+        Err(e) => return Err(e),
+    };
+
+    if request[1] != CMD_CONNECT {
+        reply(stream, REPLY_COMMAND_NOT_SUPPORTED).await?;
+        return Err(invalid_data(format!(
+            "unsupported SOCKS5 command {:#04x}",
+            request[1]
+        )));
+    }
+
+    reply(stream, REPLY_SUCCEEDED).await?;
+    Ok(target)
+}
+
+async fn reply<S>(stream: &mut S, code: u8) -> io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    // The connection to the destination is made lazily, so there is no bound address to report.
+    stream
+        .write_all(&[VERSION, code, 0x00, ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+        .await
+}
+
+/// Reads an `ATYP`, address and port triple.
+pub(crate) async fn read_addr<S>(stream: &mut S) -> io::Result<(String, u16)>
+where
+    S: AsyncRead + Unpin,
+{
+    let host = match stream.read_u8().await? {
+        ATYP_IPV4 => {
+            let mut octets = [0; 4];
+            stream.read_exact(&mut octets).await?;
+            Ipv4Addr::from(octets).to_string()
+        }
+        ATYP_IPV6 => {
+            let mut octets = [0; 16];
+            stream.read_exact(&mut octets).await?;
+            Ipv6Addr::from(octets).to_string()
+        }
+        ATYP_DOMAIN => {
+            let len = stream.read_u8().await?;
+            let mut domain = vec![0; len.into()];
+            stream.read_exact(&mut domain).await?;
+            String::from_utf8(domain).map_err(|_| invalid_data("domain name is not UTF-8"))?
+        }
+        atyp => return Err(invalid_data(format!("unknown address type {atyp:#04x}"))),
+    };
+
+    Ok((host, stream.read_u16().await?))
+}
+
+fn invalid_data(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+fn invalid_input(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::{TcpListener, TcpStream};
+
+    async fn loopback() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap());
+        let (client, server) = tokio::join!(client, listener.accept());
+        (client.unwrap(), server.unwrap().0)
+    }
+
+    async fn handshake(host: &str, port: u16) -> (String, u16) {
+        let (mut client, mut server) = loopback().await;
+        let (connected, accepted) =
+            tokio::join!(connect(&mut client, host, port, None), accept(&mut server));
+        connected.unwrap();
+        accepted.unwrap()
+    }
+
+    #[tokio::test]
+    async fn connects_to_ipv4() {
+        assert_eq!(
+            handshake("192.0.2.1", 443).await,
+            ("192.0.2.1".to_owned(), 443)
+        );
+    }
+
+    #[tokio::test]
+    async fn connects_to_ipv6() {
+        assert_eq!(
+            handshake("2001:db8::1", 8443).await,
+            ("2001:db8::1".to_owned(), 8443)
+        );
+    }
+
+    #[tokio::test]
+    async fn connects_to_domain() {
+        assert_eq!(
+            handshake("example.com", 80).await,
+            ("example.com".to_owned(), 80)
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_authenticated_clients() {
+        let (mut client, mut server) = loopback().await;
+        let auth = Credentials {
+            username: "user".to_owned(),
+            password: "secret".to_owned(),
+        };
+
+        let (connected, accepted) = tokio::join!(
+            connect(&mut client, "example.com", 80, Some(&auth)),
+            accept(&mut server)
+        );
+
+        assert_eq!(
+            connected.unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+        assert_eq!(
+            accepted.unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+    }
+
+    #[tokio::test]
+    async fn replies_to_unsupported_command() {
+        let (mut client, mut server) = loopback().await;
+        let accepting = tokio::spawn(async move { accept(&mut server).await });
+
+        // BIND to 127.0.0.1:80
+        client.write_all(&[VERSION, 1, NO_AUTH]).await.unwrap();
+        client
+            .write_all(&[VERSION, 0x02, 0x00, ATYP_IPV4, 127, 0, 0, 1, 0, 80])
+            .await
+            .unwrap();
+
+        let mut reply = [0; 12];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply[..2], [VERSION, NO_AUTH]);
+        assert_eq!(reply[2..4], [VERSION, REPLY_COMMAND_NOT_SUPPORTED]);
+        assert_eq!(
+            accepting.await.unwrap().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[tokio::test]
+    async fn replies_to_unknown_address_type() {
+        let (mut client, mut server) = loopback().await;
+        let accepting = tokio::spawn(async move { accept(&mut server).await });
+
+        client.write_all(&[VERSION, 1, NO_AUTH]).await.unwrap();
+        client
+            .write_all(&[VERSION, CMD_CONNECT, 0x00, 0x09])
+            .await
+            .unwrap();
+
+        let mut reply = [0; 12];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply[2..4], [VERSION, REPLY_ADDRESS_TYPE_NOT_SUPPORTED]);
+        assert!(accepting.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn sends_credentials_to_upstream() {
+        let (mut client, mut server) = loopback().await;
+        let auth = Credentials {
+            username: "user".to_owned(),
+            password: "secret".to_owned(),
+        };
+
+        let upstream = async move {
+            let mut greeting = [0; 3];
+            server.read_exact(&mut greeting).await.unwrap();
+            assert_eq!(greeting, [VERSION, 1, USERNAME_PASSWORD]);
+            server
+                .write_all(&[VERSION, USERNAME_PASSWORD])
+                .await
+                .unwrap();
+
+            let mut credentials = [0; 13];
+            server.read_exact(&mut credentials).await.unwrap();
+            assert_eq!(&credentials, b"\x01\x04user\x06secret");
+            // Reject them.
+            server.write_all(&[0x01, 0x01]).await.unwrap();
+        };
+
+        let (connected, ()) = tokio::join!(
+            connect(&mut client, "example.com", 80, Some(&auth)),
+            upstream
+        );
+        assert_eq!(
+            connected.unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_connect_failures() {
+        let (mut client, mut server) = loopback().await;
+
+        let upstream = async move {
+            let mut greeting = [0; 3];
+            server.read_exact(&mut greeting).await.unwrap();
+            server.write_all(&[VERSION, NO_AUTH]).await.unwrap();
+            let mut request = [0; 10];
+            server.read_exact(&mut request).await.unwrap();
+            // Connection refused
+            server
+                .write_all(&[VERSION, 0x05, 0x00, ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+                .await
+                .unwrap();
+        };
+
+        let (connected, ()) = tokio::join!(connect(&mut client, "192.0.2.1", 443, None), upstream);
+        assert_eq!(
+            connected.unwrap_err().kind(),
+            io::ErrorKind::ConnectionRefused
+        );
+    }
+}
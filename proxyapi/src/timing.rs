@@ -0,0 +1,138 @@
+use std::sync::{
+    atomic::{AtomicI64, Ordering},
+    Arc,
+};
+
+use proxyapi_models::Timings;
+
+/// Current time in nanoseconds since the epoch, like the timestamps of the models.
+pub(crate) fn now() -> i64 {
+    chrono::Local::now()
+        .timestamp_nanos_opt()
+        .unwrap_or_default()
+}
+
+/// How long opening an upstream connection took, found in the extensions of the responses
+/// received on it.
+#[derive(Clone, Debug)]
+pub(crate) struct ConnectionTimings {
+    /// When connecting started.
+    start: i64,
+    dns: Option<i64>,
+    connect: i64,
+    tls: Option<i64>,
+    last_write: Arc<AtomicI64>,
+}
+
+impl ConnectionTimings {
+    pub(crate) fn new(start: i64, dns: Option<i64>, connect: i64, tls: Option<i64>) -> Self {
+        Self {
+            start,
+            dns,
+            connect,
+            tls,
+            last_write: Arc::new(AtomicI64::new(0)),
+        }
+    }
+
+    /// When the connection was ready to send requests.
+    fn ready(&self) -> i64 {
+        self.start + self.dns.unwrap_or_default() + self.connect + self.tls.unwrap_or_default()
+    }
+
+    /// Notes that data was just written to the connection.
+    pub(crate) fn record_write(&self) {
+        self.last_write.store(now(), Ordering::Relaxed);
+    }
+}
+
+/// Timings of an exchange forwarded at `start` whose response head arrived at `head`.
+///
+/// Without the timings of its connection, e.g. with a custom client, the whole exchange is
+/// counted as waiting.
+pub(crate) fn exchange_timings(
+    start: i64,
+    head: i64,
+    connection: Option<&ConnectionTimings>,
+) -> Timings {
+    let Some(connection) = connection else {
+        return Timings::new(start, head - start);
+    };
+
+    // A connection opened before the exchange was forwarded is being reused.
+    let opened = connection.start >= start;
+    let ready = if opened { connection.ready() } else { start }.min(head);
+    // With HTTP/1 the last write before the response head is the end of the request.
+    let sent = connection
+        .last_write
+        .load(Ordering::Relaxed)
+        .clamp(ready, head);
+
+    let timings = Timings::new(start, head - sent).with_send(sent - ready);
+    if opened {
+        timings.with_connection(connection.dns, connection.connect, connection.tls)
+    } else {
+        timings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proxyapi_models::Phase;
+
+    fn phases(timings: &Timings) -> Vec<Option<i64>> {
+        Phase::ALL
+            .into_iter()
+            .map(|phase| timings.get(phase))
+            .collect()
+    }
+
+    fn connection(start: i64, last_write: i64) -> ConnectionTimings {
+        let connection = ConnectionTimings::new(start, Some(10), 20, Some(30));
+        connection.last_write.store(last_write, Ordering::Relaxed);
+        connection
+    }
+
+    #[test]
+    fn splits_new_connections() {
+        // Ready at 1060, the request was written until 1080.
+        let timings = exchange_timings(1000, 1200, Some(&connection(1000, 1080)));
+
+        assert_eq!(timings.start(), 1000);
+        assert_eq!(
+            phases(&timings.with_download(50)),
+            [Some(10), Some(20), Some(30), Some(20), Some(120), Some(50)]
+        );
+    }
+
+    #[test]
+    fn omits_connecting_on_pooled_connections() {
+        let timings = exchange_timings(1000, 1200, Some(&connection(500, 1050)));
+
+        assert_eq!(
+            phases(&timings),
+            [None, None, None, Some(50), Some(150), None]
+        );
+    }
+
+    #[test]
+    fn clamps_writes_outside_the_exchange() {
+        // Written while the previous exchange was sent on a pooled connection.
+        let timings = exchange_timings(1000, 1200, Some(&connection(500, 900)));
+        assert_eq!(timings.get(Phase::Send), Some(0));
+        assert_eq!(timings.get(Phase::Wait), Some(200));
+
+        // A head arriving before the connection was deemed ready doesn't make phases negative.
+        let timings = exchange_timings(1000, 1040, Some(&connection(1000, 0)));
+        assert_eq!(timings.get(Phase::Send), Some(0));
+        assert_eq!(timings.get(Phase::Wait), Some(0));
+    }
+
+    #[test]
+    fn counts_everything_as_waiting_without_connection_timings() {
+        let timings = exchange_timings(1000, 1200, None);
+
+        assert_eq!(phases(&timings), [None, None, None, None, Some(200), None]);
+    }
+}
@@ -0,0 +1,400 @@
+use std::{
+    fs,
+    future::Future,
+    io,
+    path::Path,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::SystemTime,
+};
+
+use hyper::{
+    client::connect::{Connected, Connection},
+    service::Service,
+    Uri,
+};
+use hyper_rustls::MaybeHttpsStream;
+use proxyapi_models::TlsInfo;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpStream,
+};
+use tokio_rustls::{
+    rustls::{
+        self,
+        client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier},
+        Certificate, ClientConfig, CommonState, OwnedTrustAnchor, PrivateKey, ProtocolVersion,
+        RootCertStore, ServerName,
+    },
+    TlsConnector,
+};
+
+use crate::{
+    error::Error,
+    host_pattern::HostPattern,
+    timing::{self, ConnectionTimings},
+    upstream::UpstreamConnector,
+};
+
+/// How the proxy authenticates upstream servers, and itself to them.
+///
+/// Servers are verified against the webpki roots and any root added here. Hosts can be exempted
+/// from verification and given a client certificate to present.
+#[derive(Clone, Debug, Default)]
+pub struct TlsOptions {
+    roots: Vec<Certificate>,
+    insecure: Vec<HostPattern>,
+    client_certs: Vec<(HostPattern, Vec<Certificate>, PrivateKey)>,
+}
+
+impl TlsOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trusts the CA certificates in `pem`, e.g. a private CA signing internal services.
+    pub fn with_root_pem(mut self, pem: &[u8]) -> Result<Self, Error> {
+        let roots = certificates(pem)?;
+        if roots.is_empty() {
+            return Err(Error::InvalidCertificate(
+                "no certificate found in PEM".to_owned(),
+            ));
+        }
+
+        for root in &roots {
+            RootCertStore::empty()
+                .add(root)
+                .map_err(|e| Error::InvalidCertificate(e.to_string()))?;
+        }
+
+        self.roots.extend(roots);
+        Ok(self)
+    }
+
+    /// Trusts the CA certificates in the PEM file at `path`.
+    pub fn with_root_pem_file(self, path: impl AsRef<Path>) -> Result<Self, Error> {
+        self.with_root_pem(&fs::read(path)?)
+    }
+
+    /// Accepts any certificate from hosts matching `pattern`.
+    ///
+    /// Meant for self-signed staging hosts, the connection is not protected against MITM.
+    pub fn with_insecure(mut self, pattern: HostPattern) -> Self {
+        self.insecure.push(pattern);
+        self
+    }
+
+    /// Presents the certificate chain and private key in `pem` to hosts matching `pattern`
+    /// that ask for a client certificate.
+    pub fn with_client_cert_pem(mut self, pattern: HostPattern, pem: &[u8]) -> Result<Self, Error> {
+        let chain = certificates(pem)?;
+        if chain.is_empty() {
+            return Err(Error::InvalidCertificate(
+                "no certificate found in PEM".to_owned(),
+            ));
+        }
+
+        let key = private_key(pem)?;
+        ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(RootCertStore::empty())
+            .with_single_cert(chain.clone(), key.clone())
+            .map_err(|e| Error::InvalidCertificate(e.to_string()))?;
+
+        self.client_certs.push((pattern, chain, key));
+        Ok(self)
+    }
+
+    /// Presents the certificate chain and private key in the PEM files at `cert` and `key` to
+    /// hosts matching `pattern`.
+    pub fn with_client_cert_files(
+        self,
+        pattern: HostPattern,
+        cert: impl AsRef<Path>,
+        key: impl AsRef<Path>,
+    ) -> Result<Self, Error> {
+        let mut pem = fs::read(cert)?;
+        pem.push(b'\n');
+        pem.extend(fs::read(key)?);
+        self.with_client_cert_pem(pattern, &pem)
+    }
+}
+
+/// Client configurations built from [`TlsOptions`], one per client certificate.
+#[derive(Clone, Debug)]
+pub(crate) struct ClientConfigs {
+    default: Arc<ClientConfig>,
+    client_certs: Arc<Vec<(HostPattern, Arc<ClientConfig>)>>,
+}
+
+impl ClientConfigs {
+    /// Builds the configurations of `options`, offering `alpn` to servers.
+    pub(crate) fn new(options: &TlsOptions, alpn: &[&[u8]]) -> Self {
+        let mut roots = RootCertStore::empty();
+        roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|ta| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(
+                ta.subject,
+                ta.spki,
+                ta.name_constraints,
+            )
+        }));
+        for root in &options.roots {
+            roots
+                .add(root)
+                .expect("Root certificate was checked when added");
+        }
+
+        let verifier = Arc::new(Verifier {
+            webpki: WebPkiVerifier::new(roots, None),
+            insecure: options.insecure.clone(),
+        });
+        let builder = || {
+            ClientConfig::builder()
+                .with_safe_defaults()
+                .with_custom_certificate_verifier(verifier.clone())
+        };
+        let with_alpn = |mut config: ClientConfig| {
+            config.alpn_protocols = alpn.iter().map(|protocol| protocol.to_vec()).collect();
+            Arc::new(config)
+        };
+
+        let client_certs = options
+            .client_certs
+            .iter()
+            .map(|(pattern, chain, key)| {
+                let config = builder()
+                    .with_single_cert(chain.clone(), key.clone())
+                    .expect("Client certificate was checked when added");
+                (pattern.clone(), with_alpn(config))
+            })
+            .collect();
+
+        Self {
+            default: with_alpn(builder().with_no_client_auth()),
+            client_certs: Arc::new(client_certs),
+        }
+    }
+
+    /// Configuration for connections to `host`, given without brackets if it's an IPv6 address.
+    pub(crate) fn get(&self, host: &str) -> Arc<ClientConfig> {
+        self.client_certs
+            .iter()
+            .find(|(pattern, _)| pattern.matches(host))
+            .map_or(&self.default, |(_, config)| config)
+            .clone()
+    }
+}
+
+/// HTTPS connector applying [`TlsOptions`] on top of an [`UpstreamConnector`].
+#[derive(Clone)]
+pub struct UpstreamTlsConnector {
+    http: UpstreamConnector,
+    configs: ClientConfigs,
+}
+
+impl UpstreamTlsConnector {
+    pub fn new(http: UpstreamConnector, options: &TlsOptions) -> Self {
+        Self {
+            http,
+            configs: ClientConfigs::new(options, ALPN),
+        }
+    }
+}
+
+impl Service<Uri> for UpstreamTlsConnector {
+    type Response = UpstreamStream;
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.http.poll_ready(cx)
+    }
+
+    fn call(&mut self, dst: Uri) -> Self::Future {
+        let start = timing::now();
+        let connecting = self.http.connect_timed(dst.clone());
+
+        if dst.scheme() != Some(&http::uri::Scheme::HTTPS) {
+            return Box::pin(async move {
+                let (stream, dns) = connecting.await?;
+                let connect = timing::now() - start - dns.unwrap_or_default();
+                Ok(UpstreamStream {
+                    stream: MaybeHttpsStream::Http(stream),
+                    timings: ConnectionTimings::new(start, dns, connect, None),
+                })
+            });
+        }
+
+        let host = dst
+            .host()
+            .unwrap_or_default()
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_owned();
+        let connector = TlsConnector::from(self.configs.get(&host));
+
+        Box::pin(async move {
+            let server_name = ServerName::try_from(host.as_str())
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid dnsname"))?;
+            let (stream, dns) = connecting.await?;
+            let connected = timing::now();
+            let stream = connector.connect(server_name, stream).await?;
+            let tls = timing::now() - connected;
+
+            Ok(UpstreamStream {
+                stream: MaybeHttpsStream::Https(stream),
+                timings: ConnectionTimings::new(
+                    start,
+                    dns,
+                    connected - start - dns.unwrap_or_default(),
+                    Some(tls),
+                ),
+            })
+        })
+    }
+}
+
+/// Connection to an upstream server made by [`UpstreamTlsConnector`].
+///
+/// The [`TlsInfo`] of HTTPS connections and how long connecting took end up in the extensions
+/// of the responses received on them.
+pub struct UpstreamStream {
+    stream: MaybeHttpsStream<TcpStream>,
+    timings: ConnectionTimings,
+}
+
+impl Connection for UpstreamStream {
+    fn connected(&self) -> Connected {
+        let connected = self.stream.connected().extra(self.timings.clone());
+        match &self.stream {
+            MaybeHttpsStream::Https(stream) => match tls_info(stream.get_ref().1) {
+                Some(info) => connected.extra(info),
+                None => connected,
+            },
+            MaybeHttpsStream::Http(_) => connected,
+        }
+    }
+}
+
+impl AsyncRead for UpstreamStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for UpstreamStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let written = Pin::new(&mut self.stream).poll_write(cx, buf);
+        if matches!(written, Poll::Ready(Ok(len)) if len > 0) {
+            self.timings.record_write();
+        }
+        written
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let written = Pin::new(&mut self.stream).poll_write_vectored(cx, bufs);
+        if matches!(written, Poll::Ready(Ok(len)) if len > 0) {
+            self.timings.record_write();
+        }
+        written
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.stream.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+/// Version and cipher suite negotiated on a TLS connection, once its handshake is done.
+pub(crate) fn tls_info(session: &CommonState) -> Option<TlsInfo> {
+    let version = match session.protocol_version()? {
+        ProtocolVersion::TLSv1_2 => "TLS 1.2".to_owned(),
+        ProtocolVersion::TLSv1_3 => "TLS 1.3".to_owned(),
+        version => format!("{version:?}"),
+    };
+    let cipher = session.negotiated_cipher_suite()?.suite();
+
+    Some(TlsInfo::new(version, format!("{cipher:?}")))
+}
+
+/// Verifies certificates with webpki, except for hosts marked insecure.
+struct Verifier {
+    webpki: WebPkiVerifier,
+    insecure: Vec<HostPattern>,
+}
+
+impl ServerCertVerifier for Verifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let host = match server_name {
+            ServerName::DnsName(name) => Some(name.as_ref().to_owned()),
+            ServerName::IpAddress(ip) => Some(ip.to_string()),
+            _ => None,
+        };
+
+        if host.is_some_and(|host| self.insecure.iter().any(|p| p.matches(&host))) {
+            return Ok(ServerCertVerified::assertion());
+        }
+
+        self.webpki.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        )
+    }
+}
+
+/// Protocols offered to upstream servers.
+#[cfg(feature = "http2")]
+const ALPN: &[&[u8]] = &[b"h2", b"http/1.1"];
+#[cfg(not(feature = "http2"))]
+const ALPN: &[&[u8]] = &[b"http/1.1"];
+
+fn certificates(pem: &[u8]) -> Result<Vec<Certificate>, Error> {
+    Ok(rustls_pemfile::certs(&mut &*pem)?
+        .into_iter()
+        .map(Certificate)
+        .collect())
+}
+
+fn private_key(pem: &[u8]) -> Result<PrivateKey, Error> {
+    rustls_pemfile::read_all(&mut &*pem)?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| Error::InvalidCertificate("no private key found in PEM".to_owned()))
+}
@@ -0,0 +1,134 @@
+use std::{
+    io, mem,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+    os::fd::{AsRawFd, RawFd},
+};
+
+use tokio::net::{TcpListener, TcpSocket, TcpStream};
+
+/// Binds a listener for redirected connections.
+///
+/// `IP_TRANSPARENT` is set when the process is allowed to (`CAP_NET_ADMIN`), which TPROXY rules
+/// need. iptables `REDIRECT` works without it.
+pub(crate) fn bind(addr: SocketAddr) -> io::Result<TcpListener> {
+    let socket = match addr {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+
+    socket.set_reuseaddr(true)?;
+
+    let (level, name) = match addr {
+        SocketAddr::V4(_) => (libc::SOL_IP, libc::IP_TRANSPARENT),
+        SocketAddr::V6(_) => (libc::SOL_IPV6, libc::IPV6_TRANSPARENT),
+    };
+    if let Err(e) = set_flag(socket.as_raw_fd(), level, name) {
+        if e.kind() != io::ErrorKind::PermissionDenied {
+            return Err(e);
+        }
+    }
+
+    socket.bind(addr)?;
+    socket.listen(1024)
+}
+
+/// Recovers the address a redirected connection was originally sent to.
+///
+/// Connections redirected by iptables `REDIRECT` report it through `SO_ORIGINAL_DST`. TPROXY
+/// keeps the original destination as the local address of the accepted socket.
+pub(crate) fn original_dst(stream: &TcpStream) -> io::Result<SocketAddr> {
+    let local_addr = stream.local_addr()?;
+    let fd = stream.as_raw_fd();
+
+    let original = match local_addr {
+        SocketAddr::V4(_) => {
+            get_sockaddr::<libc::sockaddr_in>(fd, libc::SOL_IP, libc::SO_ORIGINAL_DST).map(|addr| {
+                SocketAddr::V4(SocketAddrV4::new(
+                    Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)),
+                    u16::from_be(addr.sin_port),
+                ))
+            })
+        }
+        SocketAddr::V6(_) => {
+            get_sockaddr::<libc::sockaddr_in6>(fd, libc::SOL_IPV6, libc::IP6T_SO_ORIGINAL_DST).map(
+                |addr| {
+                    SocketAddr::V6(SocketAddrV6::new(
+                        Ipv6Addr::from(addr.sin6_addr.s6_addr),
+                        u16::from_be(addr.sin6_port),
+                        addr.sin6_flowinfo,
+                        addr.sin6_scope_id,
+                    ))
+                },
+            )
+        }
+    };
+
+    match original {
+        Ok(addr) => Ok(addr),
+        // No NAT entry for the connection, or no connection tracking at all, so it wasn't
+        // redirected by REDIRECT.
+        Err(e) if matches!(e.raw_os_error(), Some(libc::ENOENT | libc::ENOPROTOOPT)) => {
+            Ok(local_addr)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn set_flag(fd: RawFd, level: libc::c_int, name: libc::c_int) -> io::Result<()> {
+    let value: libc::c_int = 1;
+
+    // SAFETY: `value` outlives the call and its size is passed along.
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            &value as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+fn get_sockaddr<T>(fd: RawFd, level: libc::c_int, name: libc::c_int) -> io::Result<T> {
+    let mut addr = mem::MaybeUninit::<T>::zeroed();
+    let mut len = mem::size_of::<T>() as libc::socklen_t;
+
+    // SAFETY: the kernel writes at most `len` bytes into `addr`, which is zeroed, plain old data.
+    let ret = unsafe {
+        libc::getsockopt(
+            fd,
+            level,
+            name,
+            addr.as_mut_ptr() as *mut libc::c_void,
+            &mut len,
+        )
+    };
+
+    if ret == 0 {
+        // SAFETY: see above.
+        Ok(unsafe { addr.assume_init() })
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn falls_back_to_the_local_address() {
+        let listener = bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let _client = TcpStream::connect(addr).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+
+        assert_eq!(original_dst(&stream).unwrap(), addr);
+    }
+}
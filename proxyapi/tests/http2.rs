@@ -0,0 +1,151 @@
+#![cfg(feature = "http2")]
+
+use std::{
+    convert::Infallible,
+    net::{SocketAddr, TcpListener},
+    sync::{mpsc, Arc, Mutex},
+    time::Duration,
+};
+
+use hyper::{server::conn::Http, service::service_fn, Body, Request, Response, Version};
+use proxyapi::{
+    ca::{CaStore, Ssl},
+    tls::TlsOptions,
+    FlowEvent, ProxyBuilder, ProxyHandler,
+};
+use rustls::{Certificate, ClientConfig, PrivateKey, RootCertStore, ServerConfig, ServerName};
+use tempfile::TempDir;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+/// Starts an HTTPS server for `localhost` that only speaks HTTP/2, returning its port, its
+/// certificate and the ALPN protocol its client negotiated.
+async fn start_upstream() -> (u16, String, Arc<Mutex<Option<Vec<u8>>>>) {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+    let mut config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![Certificate(cert.serialize_der().unwrap())],
+            PrivateKey(cert.serialize_private_key_der()),
+        )
+        .unwrap();
+    config.alpn_protocols = vec![b"h2".to_vec()];
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let negotiated = Arc::new(Mutex::new(None));
+
+    let alpn = Arc::clone(&negotiated);
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let stream = acceptor.accept(stream).await.unwrap();
+        *alpn.lock().unwrap() = stream.get_ref().1.alpn_protocol().map(<[u8]>::to_vec);
+
+        let service = service_fn(|req: Request<Body>| async move {
+            Ok::<_, Infallible>(Response::new(Body::from(format!("{:?}", req.version()))))
+        });
+        let _ = Http::new()
+            .http2_only(true)
+            .serve_connection(stream, service)
+            .await;
+    });
+
+    (port, cert.serialize_pem().unwrap(), negotiated)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn negotiates_http2_on_both_sides() {
+    let (port, upstream_pem, upstream_alpn) = start_upstream().await;
+
+    let ca = TempDir::new().unwrap();
+    let (ca_pem, ca_key) = CaStore::new(ca.path()).load_or_generate().unwrap();
+    let ssl = Ssl::new(ca_pem.as_bytes(), ca_key.as_bytes()).unwrap();
+
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let (tx, flows) = mpsc::sync_channel(100);
+    let proxy = ProxyBuilder::new()
+        .with_addr(addr)
+        .with_upstream_tls(
+            TlsOptions::new()
+                .with_root_pem(upstream_pem.as_bytes())
+                .unwrap(),
+        )
+        .with_rustls_client()
+        .with_ca(ssl)
+        .with_http_handler(ProxyHandler::default().with_flow_sender(tx))
+        .build();
+    tokio::spawn(async move { proxy.start(std::future::pending()).await });
+
+    let mut stream = connect(addr).await;
+    let target = format!("localhost:{port}");
+    stream
+        .write_all(format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n\r\n").as_bytes())
+        .await
+        .unwrap();
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        head.push(stream.read_u8().await.unwrap());
+    }
+    assert!(head.starts_with(b"HTTP/1.1 200"));
+
+    let mut roots = RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut ca_pem.as_bytes()).unwrap() {
+        roots.add(&Certificate(cert)).unwrap();
+    }
+    let mut config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    let stream = TlsConnector::from(Arc::new(config))
+        .connect(ServerName::try_from("localhost").unwrap(), stream)
+        .await
+        .unwrap();
+
+    // The proxy offers h2 to its clients.
+    assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+
+    let (mut sender, connection) = hyper::client::conn::Builder::new()
+        .http2_only(true)
+        .handshake::<_, Body>(stream)
+        .await
+        .unwrap();
+    tokio::spawn(connection);
+
+    let request = Request::get(format!("https://{target}/"))
+        .body(Body::empty())
+        .unwrap();
+    let response = sender.send_request(request).await.unwrap();
+    assert_eq!(response.version(), Version::HTTP_2);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(&body[..], b"HTTP/2.0");
+
+    // The proxy negotiated h2 with upstream as well.
+    assert_eq!(upstream_alpn.lock().unwrap().as_deref(), Some(&b"h2"[..]));
+
+    let recorded = loop {
+        match flows.recv_timeout(Duration::from_secs(5)).unwrap() {
+            FlowEvent::ResponseComplete { response, .. } => break response,
+            _ => continue,
+        }
+    };
+    assert_eq!(*recorded.version(), Version::HTTP_2);
+}
+
+async fn connect(addr: SocketAddr) -> TcpStream {
+    for _ in 0..50 {
+        if let Ok(stream) = TcpStream::connect(addr).await {
+            return stream;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("proxy didn't start listening on {addr}");
+}
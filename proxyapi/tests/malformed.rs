@@ -0,0 +1,268 @@
+use std::{
+    net::{SocketAddr, TcpListener},
+    sync::mpsc::{self, Receiver},
+    time::Duration,
+};
+
+use proxyapi::{
+    ca::{CaStore, Ssl},
+    ErrorKind, FlowEvent, ProxyBuilder, ProxyHandler, Tunnel,
+};
+use tempfile::TempDir;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::timeout,
+};
+
+/// Port nothing listens on, so forwarded requests fail fast.
+const CLOSED_PORT: u16 = 9;
+
+struct TestProxy {
+    addr: SocketAddr,
+    flows: Receiver<FlowEvent>,
+    tunnels: Receiver<Tunnel>,
+    _ca: TempDir,
+}
+
+fn start_proxy() -> TestProxy {
+    let ca = TempDir::new().unwrap();
+    let (cert, key) = CaStore::new(ca.path()).load_or_generate().unwrap();
+    let ssl = Ssl::new(cert.as_bytes(), key.as_bytes()).unwrap();
+
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let (tx, flows) = mpsc::sync_channel(100);
+    let (tunnel_tx, tunnels) = mpsc::sync_channel(100);
+
+    let proxy = ProxyBuilder::new()
+        .with_addr(addr)
+        .with_rustls_client()
+        .with_ca(ssl)
+        .with_http_handler(
+            ProxyHandler::default()
+                .with_flow_sender(tx)
+                .with_tunnel_sender(tunnel_tx),
+        )
+        .build();
+    tokio::spawn(async move { proxy.start(std::future::pending()).await });
+
+    TestProxy {
+        addr,
+        flows,
+        tunnels,
+        _ca: ca,
+    }
+}
+
+async fn connect(addr: SocketAddr) -> TcpStream {
+    for _ in 0..50 {
+        if let Ok(stream) = TcpStream::connect(addr).await {
+            return stream;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("proxy didn't start listening on {addr}");
+}
+
+/// Reads until the peer closes the connection or the response head is complete.
+async fn read_head(stream: &mut TcpStream) -> String {
+    let mut head = Vec::new();
+    let mut buf = [0; 1024];
+
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        let read = timeout(Duration::from_secs(5), stream.read(&mut buf))
+            .await
+            .expect("timed out waiting for the proxy")
+            .unwrap();
+        if read == 0 {
+            break;
+        }
+        head.extend_from_slice(&buf[..read]);
+    }
+
+    String::from_utf8_lossy(&head).into_owned()
+}
+
+async fn send(addr: SocketAddr, request: &[u8]) -> String {
+    let mut stream = connect(addr).await;
+    stream.write_all(request).await.unwrap();
+    read_head(&mut stream).await
+}
+
+async fn open_tunnel(addr: SocketAddr, target: &str) -> TcpStream {
+    let mut stream = connect(addr).await;
+    stream
+        .write_all(format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n\r\n").as_bytes())
+        .await
+        .unwrap();
+    let head = read_head(&mut stream).await;
+    assert!(head.starts_with("HTTP/1.1 200"), "{head}");
+    stream
+}
+
+/// Checks the proxy still serves requests, using the onboarding page so no upstream is needed.
+async fn assert_alive(addr: SocketAddr) {
+    let head = send(
+        addr,
+        b"GET http://proxelar.local/cert/pem HTTP/1.1\r\nHost: proxelar.local\r\n\r\n",
+    )
+    .await;
+    assert!(head.starts_with("HTTP/1.1 200"), "{head}");
+}
+
+#[tokio::test]
+async fn request_without_host_is_rejected() {
+    let proxy = start_proxy();
+
+    let head = send(proxy.addr, b"GET /index.html HTTP/1.1\r\n\r\n").await;
+    assert!(head.starts_with("HTTP/1.1 400"), "{head}");
+
+    assert_alive(proxy.addr).await;
+}
+
+#[tokio::test]
+async fn request_without_host_in_tunnel_is_rejected() {
+    let proxy = start_proxy();
+
+    let mut tunnel = open_tunnel(proxy.addr, &format!("127.0.0.1:{CLOSED_PORT}")).await;
+    tunnel
+        .write_all(b"GET /index.html HTTP/1.1\r\n\r\n")
+        .await
+        .unwrap();
+    let head = read_head(&mut tunnel).await;
+    assert!(head.starts_with("HTTP/1.1 400"), "{head}");
+
+    assert_alive(proxy.addr).await;
+}
+
+#[tokio::test]
+async fn request_with_invalid_host_in_tunnel_is_rejected() {
+    let proxy = start_proxy();
+
+    let mut tunnel = open_tunnel(proxy.addr, &format!("127.0.0.1:{CLOSED_PORT}")).await;
+    tunnel
+        .write_all(b"GET / HTTP/1.1\r\nHost: bad host:port\r\n\r\n")
+        .await
+        .unwrap();
+    let head = read_head(&mut tunnel).await;
+    assert!(head.starts_with("HTTP/1.1 400"), "{head}");
+
+    assert_alive(proxy.addr).await;
+}
+
+#[tokio::test]
+async fn rejected_request_is_recorded() {
+    let proxy = start_proxy();
+
+    let head = send(proxy.addr, b"GET /index.html HTTP/1.1\r\n\r\n").await;
+    assert!(head.starts_with("HTTP/1.1 400"), "{head}");
+
+    let events = (0..3)
+        .map(|_| proxy.flows.recv_timeout(Duration::from_secs(5)).unwrap())
+        .collect::<Vec<_>>();
+    assert!(matches!(events[0], FlowEvent::RequestReceived { .. }));
+    assert!(matches!(
+        &events[2],
+        FlowEvent::ResponseComplete { response, .. } if response.status().as_u16() == 400
+    ));
+}
+
+#[tokio::test]
+async fn connect_without_authority_is_rejected() {
+    let proxy = start_proxy();
+
+    let head = send(proxy.addr, b"CONNECT /nowhere HTTP/1.1\r\n\r\n").await;
+    assert!(head.starts_with("HTTP/1.1 400"), "{head}");
+
+    let head = send(proxy.addr, b"CONNECT [::1 HTTP/1.1\r\n\r\n").await;
+    assert!(head.starts_with("HTTP/1.1 400"), "{head}");
+
+    assert_alive(proxy.addr).await;
+}
+
+#[tokio::test]
+async fn truncated_client_hello_closes_tunnel() {
+    let proxy = start_proxy();
+
+    let mut tunnel = open_tunnel(proxy.addr, "example.test:443").await;
+    // Record header announcing a 512 byte ClientHello, followed by only its first byte.
+    tunnel.write_all(b"\x16\x03\x01\x02\x00\x01").await.unwrap();
+    tunnel.shutdown().await.unwrap();
+
+    let mut rest = Vec::new();
+    timeout(Duration::from_secs(5), tunnel.read_to_end(&mut rest))
+        .await
+        .expect("tunnel wasn't closed")
+        .unwrap();
+    assert!(rest.is_empty());
+
+    let tunnel = proxy.tunnels.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(tunnel.error().unwrap().kind(), ErrorKind::ClientAbort);
+
+    assert_alive(proxy.addr).await;
+}
+
+#[tokio::test]
+async fn garbage_client_hello_closes_tunnel() {
+    let proxy = start_proxy();
+
+    let mut tunnel = open_tunnel(proxy.addr, "example.test:443").await;
+    // A complete record whose ClientHello lengths point past its end.
+    tunnel
+        .write_all(b"\x16\x03\x01\x00\x08\x01\xff\xff\xff\x03\x03\x00\x00")
+        .await
+        .unwrap();
+
+    let mut rest = Vec::new();
+    timeout(Duration::from_secs(5), tunnel.read_to_end(&mut rest))
+        .await
+        .expect("tunnel wasn't closed")
+        .unwrap();
+
+    let tunnel = proxy.tunnels.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(tunnel.host(), "example.test");
+    assert_eq!(tunnel.port(), 443);
+    assert_eq!(tunnel.error().unwrap().kind(), ErrorKind::ClientTls);
+
+    assert_alive(proxy.addr).await;
+}
+
+#[tokio::test]
+async fn refused_relay_is_recorded() {
+    let proxy = start_proxy();
+
+    // Neither TLS nor HTTP, so it is relayed to a port nothing listens on.
+    let mut tunnel = open_tunnel(proxy.addr, &format!("127.0.0.1:{CLOSED_PORT}")).await;
+    tunnel.write_all(b"SSH-2.0-test\r\n").await.unwrap();
+
+    // The proxy may reset the connection, the data it didn't read is discarded.
+    let mut rest = Vec::new();
+    let _ = timeout(Duration::from_secs(5), tunnel.read_to_end(&mut rest))
+        .await
+        .expect("tunnel wasn't closed");
+
+    let tunnel = proxy.tunnels.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(tunnel.port(), CLOSED_PORT);
+    assert_eq!(tunnel.error().unwrap().kind(), ErrorKind::ConnectionRefused);
+
+    assert_alive(proxy.addr).await;
+}
+
+#[tokio::test]
+async fn websocket_upgrade_without_key_is_rejected() {
+    let proxy = start_proxy();
+
+    let request = format!(
+        "GET http://127.0.0.1:{CLOSED_PORT}/socket HTTP/1.1\r\n\
+         Host: 127.0.0.1:{CLOSED_PORT}\r\n\
+         Connection: Upgrade\r\n\
+         Upgrade: websocket\r\n\r\n"
+    );
+    let head = send(proxy.addr, request.as_bytes()).await;
+    assert!(head.starts_with("HTTP/1.1 400"), "{head}");
+
+    assert_alive(proxy.addr).await;
+}
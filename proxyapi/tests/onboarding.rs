@@ -0,0 +1,121 @@
+use std::{
+    net::{SocketAddr, TcpListener},
+    time::Duration,
+};
+
+use proxyapi::{
+    ca::{CaStore, Ssl},
+    openssl::{pkcs12::Pkcs12, x509::X509},
+    ProxyBuilder, ProxyHandler,
+};
+use rcgen::{BasicConstraints, Certificate, CertificateParams, DnType, IsCa};
+use tempfile::TempDir;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+async fn connect(addr: SocketAddr) -> TcpStream {
+    for _ in 0..50 {
+        if let Ok(stream) = TcpStream::connect(addr).await {
+            return stream;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("proxy didn't start listening on {addr}");
+}
+
+fn start_proxy(ssl: Ssl) -> SocketAddr {
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+
+    let proxy = ProxyBuilder::new()
+        .with_addr(addr)
+        .with_rustls_client()
+        .with_ca(ssl)
+        .with_http_handler(ProxyHandler::default())
+        .build();
+    tokio::spawn(async move { proxy.start(std::future::pending()).await });
+
+    addr
+}
+
+/// Downloads the body of the onboarding page at `path`.
+async fn download(proxy: SocketAddr, path: &str) -> Vec<u8> {
+    let mut stream = connect(proxy).await;
+    stream
+        .write_all(
+            format!(
+                "GET http://proxelar.local{path} HTTP/1.1\r\nHost: proxelar.local\r\nConnection: close\r\n\r\n"
+            )
+            .as_bytes(),
+        )
+        .await
+        .unwrap();
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    assert!(response.starts_with(b"HTTP/1.1 200"), "{path}");
+
+    let end = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+    response.split_off(end + 4)
+}
+
+/// Downloads the certificate in each format offered, checking they are all `expected`.
+async fn assert_serves(proxy: SocketAddr, expected: &[u8]) {
+    let pem = X509::from_pem(&download(proxy, "/cert/pem").await).unwrap();
+    assert_eq!(pem.to_der().unwrap(), expected);
+
+    let der = X509::from_der(&download(proxy, "/cert/der").await).unwrap();
+    assert_eq!(der.to_der().unwrap(), expected);
+
+    let p12 = Pkcs12::from_der(&download(proxy, "/cert/p12").await)
+        .unwrap()
+        .parse2("")
+        .unwrap();
+    // Without a private key to pair it with, the certificate is listed among the CAs.
+    let mut cas = p12.ca.unwrap();
+    assert_eq!(cas.len(), 1);
+    assert_eq!(cas.pop().unwrap().to_der().unwrap(), expected);
+}
+
+fn ca_params(name: &str) -> CertificateParams {
+    let mut params = CertificateParams::default();
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    params.distinguished_name.push(DnType::CommonName, name);
+    params
+}
+
+#[tokio::test]
+async fn serves_the_ca_certificate() {
+    let ca = TempDir::new().unwrap();
+    let (cert, key) = CaStore::new(ca.path()).load_or_generate().unwrap();
+    let proxy = start_proxy(Ssl::new(cert.as_bytes(), key.as_bytes()).unwrap());
+
+    let expected = X509::from_pem(cert.as_bytes()).unwrap().to_der().unwrap();
+    assert_serves(proxy, &expected).await;
+}
+
+#[tokio::test]
+async fn serves_the_root_of_an_intermediate_ca() {
+    let root = Certificate::from_params(ca_params("Proxelar Test Root")).unwrap();
+    let intermediate = Certificate::from_params(ca_params("Proxelar Test Intermediate")).unwrap();
+
+    // The intermediate signs, the root follows it in the chain.
+    let root_pem = root.serialize_pem().unwrap();
+    let cert_pem = intermediate.serialize_pem_with_signer(&root).unwrap() + &root_pem;
+    let ssl = Ssl::new(
+        cert_pem.as_bytes(),
+        intermediate.serialize_private_key_pem().as_bytes(),
+    )
+    .unwrap();
+    let proxy = start_proxy(ssl);
+
+    let expected = X509::from_pem(root_pem.as_bytes())
+        .unwrap()
+        .to_der()
+        .unwrap();
+    assert_serves(proxy, &expected).await;
+}
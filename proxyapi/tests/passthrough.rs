@@ -0,0 +1,97 @@
+use std::{
+    net::{SocketAddr, TcpListener},
+    sync::mpsc,
+    time::Duration,
+};
+
+use proxyapi::{
+    ca::{CaStore, Ssl},
+    ProxyBuilder, ProxyHandler,
+};
+use tempfile::TempDir;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+async fn connect(addr: SocketAddr) -> TcpStream {
+    for _ in 0..50 {
+        if let Ok(stream) = TcpStream::connect(addr).await {
+            return stream;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("proxy didn't start listening on {addr}");
+}
+
+/// A tunnel to a passthrough host is relayed even when it carries plain HTTP, or a protocol
+/// where the server speaks first.
+#[tokio::test(flavor = "multi_thread")]
+async fn relays_plain_connections_to_passthrough_hosts() {
+    let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_addr = upstream.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut stream, _) = upstream.accept().await.unwrap();
+        stream.write_all(b"220 ready\r\n").await.unwrap();
+
+        let mut request = [0; 18];
+        stream.read_exact(&mut request).await.unwrap();
+        assert_eq!(&request, b"GET / HTTP/1.1\r\n\r\n");
+        // Not a valid HTTP response, an intercepting proxy would answer with a 502.
+        stream.write_all(b"raw reply").await.unwrap();
+    });
+
+    let ca = TempDir::new().unwrap();
+    let (cert, key) = CaStore::new(ca.path()).load_or_generate().unwrap();
+    let ssl = Ssl::new(cert.as_bytes(), key.as_bytes()).unwrap();
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let (flow_tx, flows) = mpsc::sync_channel(100);
+    let (tunnel_tx, tunnels) = mpsc::sync_channel(100);
+    let proxy = ProxyBuilder::new()
+        .with_addr(addr)
+        .with_rustls_client()
+        .with_ca(ssl)
+        .with_http_handler(
+            ProxyHandler::default()
+                .with_flow_sender(flow_tx)
+                .with_tunnel_sender(tunnel_tx),
+        )
+        .with_passthrough("127.0.0.1".parse().unwrap())
+        .build();
+    tokio::spawn(async move { proxy.start(std::future::pending()).await });
+
+    let mut stream = connect(addr).await;
+    stream
+        .write_all(
+            format!("CONNECT {upstream_addr} HTTP/1.1\r\nHost: {upstream_addr}\r\n\r\n").as_bytes(),
+        )
+        .await
+        .unwrap();
+
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        head.push(stream.read_u8().await.unwrap());
+    }
+    assert!(head.starts_with(b"HTTP/1.1 200"));
+
+    let mut banner = [0; 11];
+    tokio::time::timeout(Duration::from_secs(5), stream.read_exact(&mut banner))
+        .await
+        .expect("the server's banner wasn't relayed")
+        .unwrap();
+    assert_eq!(&banner, b"220 ready\r\n");
+
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+    let mut reply = Vec::new();
+    stream.read_to_end(&mut reply).await.unwrap();
+    assert_eq!(reply, b"raw reply");
+    drop(stream);
+
+    let tunnel = tunnels.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(tunnel.host(), "127.0.0.1");
+    assert_eq!(tunnel.port(), upstream_addr.port());
+    assert!(flows.try_recv().is_err(), "relayed traffic isn't a flow");
+}
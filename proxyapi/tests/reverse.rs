@@ -0,0 +1,135 @@
+use std::{
+    net::{SocketAddr, TcpListener},
+    sync::Arc,
+    time::Duration,
+};
+
+use proxyapi::{
+    ca::{CaStore, Ssl},
+    reverse::ReverseConfig,
+    ProxyBuilder, ProxyHandler, ProxyMode,
+};
+use tempfile::TempDir;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+async fn connect(addr: SocketAddr) -> TcpStream {
+    for _ in 0..50 {
+        if let Ok(stream) = TcpStream::connect(addr).await {
+            return stream;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("proxy didn't start listening on {addr}");
+}
+
+/// Starts a backend answering every request with the head it received.
+async fn start_backend() -> SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut head = Vec::new();
+                while !head.ends_with(b"\r\n\r\n") {
+                    let Ok(byte) = stream.read_u8().await else {
+                        return;
+                    };
+                    head.push(byte);
+                }
+                let response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", head.len());
+                stream.write_all(response.as_bytes()).await.unwrap();
+                stream.write_all(&head).await.unwrap();
+            });
+        }
+    });
+
+    addr
+}
+
+fn start_proxy(reverse: ReverseConfig) -> (SocketAddr, TempDir) {
+    let ca = TempDir::new().unwrap();
+    let (cert, key) = CaStore::new(ca.path()).load_or_generate().unwrap();
+    let ssl = Ssl::new(cert.as_bytes(), key.as_bytes()).unwrap();
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+
+    let proxy = ProxyBuilder::new()
+        .with_addr(addr)
+        .with_rustls_client()
+        .with_ca(ssl)
+        .with_http_handler(ProxyHandler::default())
+        .with_mode(ProxyMode::Reverse(Arc::new(reverse)))
+        .build();
+    tokio::spawn(async move { proxy.start(std::future::pending()).await });
+
+    (addr, ca)
+}
+
+async fn get(proxy: SocketAddr, host: &str) -> String {
+    let mut stream = connect(proxy).await;
+    stream
+        .write_all(
+            format!("GET /users?id=1 HTTP/1.1\r\nHost: {host}\r\nConnection: close\r\n\r\n")
+                .as_bytes(),
+        )
+        .await
+        .unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+#[tokio::test]
+async fn routes_known_hosts_to_their_backend() {
+    let backend = start_backend().await;
+    let (proxy, _ca) = start_proxy(ReverseConfig::new().with_host(
+        "api.test".parse().unwrap(),
+        format!("http://{backend}/v1").parse().unwrap(),
+    ));
+
+    let response = get(proxy, "api.test").await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+
+    // The body is the request the backend received.
+    let request = response
+        .split_once("\r\n\r\n")
+        .unwrap()
+        .1
+        .to_ascii_lowercase();
+    assert!(
+        request.starts_with("get /v1/users?id=1 http/1.1\r\n"),
+        "{request}"
+    );
+    assert!(
+        request.contains(&format!("host: {backend}\r\n")),
+        "{request}"
+    );
+    assert!(
+        request.contains("x-forwarded-host: api.test\r\n"),
+        "{request}"
+    );
+    assert!(request.contains("x-forwarded-proto: http\r\n"), "{request}");
+}
+
+#[tokio::test]
+async fn answers_unknown_hosts_with_404() {
+    let backend = start_backend().await;
+    let (proxy, _ca) = start_proxy(ReverseConfig::new().with_host(
+        "api.test".parse().unwrap(),
+        format!("http://{backend}").parse().unwrap(),
+    ));
+
+    let response = get(proxy, "other.test").await;
+    assert!(response.starts_with("HTTP/1.1 404"), "{response}");
+    assert!(
+        response.ends_with("No virtual host for `other.test`"),
+        "{response}"
+    );
+}
@@ -0,0 +1,163 @@
+use std::{
+    net::{SocketAddr, TcpListener},
+    sync::mpsc::{self, Receiver},
+    time::Duration,
+};
+
+use proxyapi::{
+    ca::{CaStore, Ssl},
+    upstream::UpstreamConfig,
+    ErrorKind, FlowEvent, ProxyBuilder, ProxyHandler,
+};
+use tempfile::TempDir;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::timeout,
+};
+
+struct TestProxy {
+    addr: SocketAddr,
+    flows: Receiver<FlowEvent>,
+    _ca: TempDir,
+}
+
+fn start_proxy(upstream: UpstreamConfig, connect: Duration, response: Duration) -> TestProxy {
+    let ca = TempDir::new().unwrap();
+    let (cert, key) = CaStore::new(ca.path()).load_or_generate().unwrap();
+    let ssl = Ssl::new(cert.as_bytes(), key.as_bytes()).unwrap();
+
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let (tx, flows) = mpsc::sync_channel(100);
+
+    let proxy = ProxyBuilder::new()
+        .with_addr(addr)
+        .with_upstream(upstream)
+        .with_connect_timeout(connect)
+        .with_rustls_client()
+        .with_ca(ssl)
+        .with_http_handler(ProxyHandler::default().with_flow_sender(tx))
+        .with_response_timeout(response)
+        .build();
+    tokio::spawn(async move { proxy.start(std::future::pending()).await });
+
+    TestProxy {
+        addr,
+        flows,
+        _ca: ca,
+    }
+}
+
+/// Starts a server that accepts connections and never says a word.
+async fn start_silent() -> SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let mut connections = Vec::new();
+        while let Ok((stream, _)) = listener.accept().await {
+            connections.push(stream);
+        }
+    });
+
+    addr
+}
+
+async fn connect(addr: SocketAddr) -> TcpStream {
+    for _ in 0..50 {
+        if let Ok(stream) = TcpStream::connect(addr).await {
+            return stream;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("proxy didn't start listening on {addr}");
+}
+
+async fn get(proxy: SocketAddr, target: SocketAddr) -> String {
+    let mut stream = connect(proxy).await;
+
+    stream
+        .write_all(format!("GET http://{target}/ HTTP/1.1\r\nHost: {target}\r\n\r\n").as_bytes())
+        .await
+        .unwrap();
+
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        let byte = timeout(Duration::from_secs(5), stream.read_u8())
+            .await
+            .expect("the proxy didn't give up on upstream")
+            .unwrap();
+        head.push(byte);
+    }
+    String::from_utf8(head).unwrap()
+}
+
+fn recorded_error(flows: &Receiver<FlowEvent>) -> ErrorKind {
+    loop {
+        match flows.recv_timeout(Duration::from_secs(5)).unwrap() {
+            FlowEvent::Error { response, .. } => return response.error().unwrap().kind(),
+            _ => continue,
+        }
+    }
+}
+
+// The events are awaited blocking, the proxy needs a worker of its own.
+#[tokio::test(flavor = "multi_thread")]
+async fn silent_upstream_times_out() {
+    let upstream = start_silent().await;
+    let proxy = start_proxy(
+        UpstreamConfig::new(),
+        Duration::from_secs(5),
+        Duration::from_millis(200),
+    );
+
+    let head = get(proxy.addr, upstream).await;
+    assert!(head.starts_with("HTTP/1.1 504"), "{head}");
+    assert_eq!(recorded_error(&proxy.flows), ErrorKind::Timeout);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn silent_parent_proxy_times_out() {
+    let parent = start_silent().await;
+    let proxy = start_proxy(
+        UpstreamConfig::new().with_default(format!("http://{parent}").parse().unwrap()),
+        Duration::from_millis(200),
+        Duration::from_secs(5),
+    );
+
+    // The CONNECT to the parent proxy is never answered.
+    let head = get(proxy.addr, "127.0.0.1:9".parse().unwrap()).await;
+    assert!(head.starts_with("HTTP/1.1 504"), "{head}");
+    assert_eq!(recorded_error(&proxy.flows), ErrorKind::Timeout);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn client_leaving_while_waiting_is_recorded() {
+    let upstream = start_silent().await;
+    let proxy = start_proxy(
+        UpstreamConfig::new(),
+        Duration::from_secs(5),
+        Duration::from_secs(60),
+    );
+
+    let mut stream = connect(proxy.addr).await;
+    stream
+        .write_all(
+            format!("GET http://{upstream}/ HTTP/1.1\r\nHost: {upstream}\r\n\r\n").as_bytes(),
+        )
+        .await
+        .unwrap();
+    // Waits for the request to be forwarded before going away.
+    loop {
+        match proxy.flows.recv_timeout(Duration::from_secs(5)).unwrap() {
+            FlowEvent::RequestForwarded { .. } => break,
+            _ => continue,
+        }
+    }
+    drop(stream);
+
+    assert_eq!(recorded_error(&proxy.flows), ErrorKind::ClientAbort);
+}
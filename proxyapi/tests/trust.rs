@@ -0,0 +1,119 @@
+#![cfg(target_os = "linux")]
+
+use std::fs;
+
+use proxyapi::ca::{trust::TrustStore, CaStore};
+use tempfile::TempDir;
+
+fn ca_pem(dir: &TempDir) -> Vec<u8> {
+    let (cert, _) = CaStore::new(dir.path().join("ca"))
+        .load_or_generate()
+        .unwrap();
+    cert.into_bytes()
+}
+
+fn store(root: &TempDir, anchors: &str) -> TrustStore {
+    fs::create_dir_all(root.path().join(anchors)).unwrap();
+    TrustStore::new()
+        .with_root(root.path())
+        .with_home(root.path().join("home"))
+}
+
+#[test]
+fn installs_into_debian_anchors() {
+    let root = TempDir::new().unwrap();
+    let store = store(&root, "usr/local/share/ca-certificates");
+    let cert = ca_pem(&root);
+
+    assert!(!store.is_trusted(&cert));
+    store.install(&cert).unwrap();
+
+    let anchor = root
+        .path()
+        .join("usr/local/share/ca-certificates/proxelar.crt");
+    assert_eq!(store.anchor_path(), Some(anchor.clone()));
+    assert_eq!(fs::read(anchor).unwrap(), cert);
+    assert!(store.is_trusted(&cert));
+}
+
+#[test]
+fn installs_into_fedora_anchors() {
+    let root = TempDir::new().unwrap();
+    let store = store(&root, "etc/pki/ca-trust/source/anchors");
+    let cert = ca_pem(&root);
+
+    store.install(&cert).unwrap();
+
+    assert!(root
+        .path()
+        .join("etc/pki/ca-trust/source/anchors/proxelar.crt")
+        .is_file());
+    assert!(store.is_trusted(&cert));
+}
+
+#[test]
+fn uninstalls() {
+    let root = TempDir::new().unwrap();
+    let store = store(&root, "usr/local/share/ca-certificates");
+    let cert = ca_pem(&root);
+
+    store.install(&cert).unwrap();
+    store.uninstall().unwrap();
+
+    assert!(!store.anchor_path().unwrap().exists());
+    assert!(!store.is_trusted(&cert));
+    // Uninstalling twice is fine.
+    store.uninstall().unwrap();
+}
+
+#[test]
+fn other_certificate_is_not_trusted() {
+    let root = TempDir::new().unwrap();
+    let store = store(&root, "usr/local/share/ca-certificates");
+    let cert = ca_pem(&root);
+    let other = CaStore::new(root.path().join("other"))
+        .regenerate()
+        .unwrap()
+        .0
+        .into_bytes();
+
+    store.install(&other).unwrap();
+
+    assert!(!store.is_trusted(&cert));
+}
+
+#[test]
+fn fails_without_trust_store() {
+    let root = TempDir::new().unwrap();
+    let store = TrustStore::new().with_root(root.path());
+
+    assert_eq!(store.anchor_path(), None);
+    assert!(store.install(&ca_pem(&root)).is_err());
+}
+
+#[test]
+fn finds_nss_databases() {
+    let root = TempDir::new().unwrap();
+    let store = store(&root, "usr/local/share/ca-certificates");
+    let home = root.path().join("home");
+    for db in [
+        ".pki/nssdb",
+        ".mozilla/firefox/abc.default",
+        ".mozilla/firefox/empty",
+    ] {
+        fs::create_dir_all(home.join(db)).unwrap();
+    }
+    fs::write(home.join(".pki/nssdb/cert9.db"), "").unwrap();
+    fs::write(home.join(".mozilla/firefox/abc.default/cert9.db"), "").unwrap();
+
+    let mut databases = store.nss_databases();
+    databases.sort();
+
+    assert_eq!(
+        databases,
+        [
+            home.join(".mozilla/firefox/abc.default"),
+            home.join(".pki/nssdb"),
+        ]
+    );
+}
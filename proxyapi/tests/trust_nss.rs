@@ -0,0 +1,68 @@
+#![cfg(target_os = "linux")]
+
+//! Runs alone in its own binary, as it puts a fake `certutil` on the `PATH`.
+
+use std::{env, fs, os::unix::fs::PermissionsExt};
+
+use proxyapi::ca::{trust::TrustStore, CaStore};
+use tempfile::TempDir;
+
+/// Keeps the certificate added to a database in a file next to it.
+const FAKE_CERTUTIL: &str = r#"#!/bin/sh
+while [ $# -gt 0 ]; do
+    case "$1" in
+        -A|-L|-D) op=$1 ;;
+        -d) shift; db=${1#sql:} ;;
+    esac
+    shift
+done
+case "$op" in
+    -A) cat > "$db/proxelar.pem" ;;
+    -L) cat "$db/proxelar.pem" 2>/dev/null || exit 255 ;;
+    -D) rm "$db/proxelar.pem" ;;
+esac
+"#;
+
+#[test]
+fn checks_nss_databases() {
+    let root = TempDir::new().unwrap();
+    let bin = root.path().join("bin");
+    fs::create_dir(&bin).unwrap();
+    fs::write(bin.join("certutil"), FAKE_CERTUTIL).unwrap();
+    fs::set_permissions(bin.join("certutil"), fs::Permissions::from_mode(0o755)).unwrap();
+    let path = env::var_os("PATH").unwrap_or_default();
+    let mut paths = vec![bin];
+    paths.extend(env::split_paths(&path));
+    env::set_var("PATH", env::join_paths(paths).unwrap());
+
+    let anchors = root.path().join("usr/local/share/ca-certificates");
+    let nssdb = root.path().join("home/.pki/nssdb");
+    fs::create_dir_all(&anchors).unwrap();
+    fs::create_dir_all(&nssdb).unwrap();
+    fs::write(nssdb.join("cert9.db"), "").unwrap();
+    let store = TrustStore::new()
+        .with_root(root.path())
+        .with_home(root.path().join("home"));
+    let (cert, _) = CaStore::new(root.path().join("ca"))
+        .load_or_generate()
+        .unwrap();
+    let cert = cert.into_bytes();
+
+    // Only trusted system-wide, e.g. installed by hand.
+    fs::write(anchors.join("proxelar.crt"), &cert).unwrap();
+    assert!(!store.is_trusted(&cert));
+
+    store.install(&cert).unwrap();
+    assert!(store.is_trusted(&cert));
+
+    // A certificate replaced under the same name doesn't count.
+    let (other, _) = CaStore::new(root.path().join("other"))
+        .regenerate()
+        .unwrap();
+    fs::write(nssdb.join("proxelar.pem"), other).unwrap();
+    assert!(!store.is_trusted(&cert));
+
+    store.uninstall().unwrap();
+    assert!(!nssdb.join("proxelar.pem").exists());
+    assert!(!store.is_trusted(&cert));
+}
@@ -0,0 +1,300 @@
+use std::{
+    net::{SocketAddr, TcpListener},
+    sync::{
+        mpsc::{self, Receiver},
+        Arc,
+    },
+    time::Duration,
+};
+
+use futures::{SinkExt, StreamExt};
+use proxyapi::{
+    ca::{CaStore, Ssl},
+    tls::TlsOptions,
+    ProxyBuilder, ProxyHandler, WebSocketEvent,
+};
+use rustls::{Certificate, ClientConfig, PrivateKey, RootCertStore, ServerConfig, ServerName};
+use tempfile::TempDir;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use tokio_rustls::{client::TlsStream, TlsAcceptor, TlsConnector};
+use tokio_tungstenite::{
+    tungstenite::{
+        protocol::{frame::coding::CloseCode, CloseFrame},
+        Message,
+    },
+    WebSocketStream,
+};
+
+struct TestProxy {
+    addr: SocketAddr,
+    events: Receiver<WebSocketEvent>,
+    _ca: TempDir,
+}
+
+fn start_proxy() -> TestProxy {
+    let ca = TempDir::new().unwrap();
+    let (cert, key) = CaStore::new(ca.path()).load_or_generate().unwrap();
+    let ssl = Ssl::new(cert.as_bytes(), key.as_bytes()).unwrap();
+
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let (tx, events) = mpsc::sync_channel(100);
+    let handler = ProxyHandler::default().with_websocket_sender(tx);
+
+    let proxy = ProxyBuilder::new()
+        .with_addr(addr)
+        .with_rustls_client()
+        .with_ca(ssl)
+        .with_http_handler(handler.clone())
+        .with_websocket_handler(handler)
+        .build();
+    tokio::spawn(async move { proxy.start(std::future::pending()).await });
+
+    TestProxy {
+        addr,
+        events,
+        _ca: ca,
+    }
+}
+
+/// Starts an upstream that hands its single accepted session to `serve`.
+async fn start_upstream<F, Fut>(serve: F) -> SocketAddr
+where
+    F: FnOnce(WebSocketStream<TcpStream>) -> Fut + Send + 'static,
+    Fut: std::future::Future<Output = ()> + Send,
+{
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        serve(tokio_tungstenite::accept_async(stream).await.unwrap()).await;
+    });
+
+    addr
+}
+
+/// Opens a WebSocket session to `upstream` through a CONNECT tunnel of the proxy.
+async fn open_session(proxy: SocketAddr, upstream: SocketAddr) -> WebSocketStream<TcpStream> {
+    let mut stream = None;
+    for _ in 0..50 {
+        if let Ok(connected) = TcpStream::connect(proxy).await {
+            stream = Some(connected);
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let mut stream = stream.expect("proxy didn't start listening");
+
+    stream
+        .write_all(format!("CONNECT {upstream} HTTP/1.1\r\nHost: {upstream}\r\n\r\n").as_bytes())
+        .await
+        .unwrap();
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        let mut byte = [0];
+        stream.read_exact(&mut byte).await.unwrap();
+        head.push(byte[0]);
+    }
+    assert!(head.starts_with(b"HTTP/1.1 200"));
+
+    let (socket, _) = tokio_tungstenite::client_async(format!("ws://{upstream}/"), stream)
+        .await
+        .unwrap();
+    socket
+}
+
+fn next_closed(events: &Receiver<WebSocketEvent>) -> (u64, u16, String) {
+    loop {
+        match events.recv_timeout(Duration::from_secs(5)) {
+            Ok(WebSocketEvent::Closed {
+                session,
+                code,
+                reason,
+            }) => return (session, code, reason),
+            Ok(_) => continue,
+            Err(e) => panic!("no Closed event: {e}"),
+        }
+    }
+}
+
+// The events are awaited blocking, the proxy needs a worker of its own.
+#[tokio::test(flavor = "multi_thread")]
+async fn client_close_is_reported() {
+    let proxy = start_proxy();
+    let upstream = start_upstream(|mut socket| async move {
+        while let Some(Ok(message)) = socket.next().await {
+            if message.is_text() {
+                socket.send(message).await.unwrap();
+            }
+        }
+    })
+    .await;
+
+    let mut socket = open_session(proxy.addr, upstream).await;
+    socket.send(Message::Text("hello".into())).await.unwrap();
+    assert_eq!(
+        socket.next().await.unwrap().unwrap(),
+        Message::Text("hello".into())
+    );
+    socket
+        .close(Some(CloseFrame {
+            code: CloseCode::Normal,
+            reason: "bye".into(),
+        }))
+        .await
+        .unwrap();
+
+    let (_, code, reason) = next_closed(&proxy.events);
+    assert_eq!(code, 1000);
+    assert_eq!(reason, "bye");
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(
+        !proxy
+            .events
+            .try_iter()
+            .any(|event| matches!(event, WebSocketEvent::Closed { .. })),
+        "Closed is reported once"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn upstream_failure_is_reported_as_abnormal() {
+    let proxy = start_proxy();
+    // Dropping the socket ends the TCP connection without a close frame.
+    let upstream = start_upstream(|socket| async move { drop(socket) }).await;
+
+    let _socket = open_session(proxy.addr, upstream).await;
+
+    let (_, code, _) = next_closed(&proxy.events);
+    assert_eq!(code, 1006);
+}
+
+/// Starts a `wss` echo server for `localhost` with a self-signed certificate, returning its
+/// address and certificate.
+async fn start_secure_upstream() -> (SocketAddr, String) {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![Certificate(cert.serialize_der().unwrap())],
+            PrivateKey(cert.serialize_private_key_der()),
+        )
+        .unwrap();
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let Ok(stream) = acceptor.accept(stream).await else {
+                continue;
+            };
+            let Ok(mut socket) = tokio_tungstenite::accept_async(stream).await else {
+                continue;
+            };
+            while let Some(Ok(message)) = socket.next().await {
+                if message.is_text() {
+                    socket.send(message).await.unwrap();
+                }
+            }
+        }
+    });
+
+    (addr, cert.serialize_pem().unwrap())
+}
+
+/// Opens a `wss` session to `localhost:port` through a tunnel the proxy intercepts.
+async fn open_secure_session(
+    proxy: SocketAddr,
+    ca_pem: &str,
+    port: u16,
+) -> WebSocketStream<TlsStream<TcpStream>> {
+    let mut stream = None;
+    for _ in 0..50 {
+        if let Ok(connected) = TcpStream::connect(proxy).await {
+            stream = Some(connected);
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let mut stream = stream.expect("proxy didn't start listening");
+
+    let target = format!("localhost:{port}");
+    stream
+        .write_all(format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n\r\n").as_bytes())
+        .await
+        .unwrap();
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        head.push(stream.read_u8().await.unwrap());
+    }
+    assert!(head.starts_with(b"HTTP/1.1 200"));
+
+    let mut roots = RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut ca_pem.as_bytes()).unwrap() {
+        roots.add(&Certificate(cert)).unwrap();
+    }
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let stream = TlsConnector::from(Arc::new(config))
+        .connect(ServerName::try_from("localhost").unwrap(), stream)
+        .await
+        .unwrap();
+
+    let (socket, _) = tokio_tungstenite::client_async(format!("wss://{target}/"), stream)
+        .await
+        .unwrap();
+    socket
+}
+
+async fn echo_through_proxy(tls: TlsOptions) -> Option<Message> {
+    let (upstream, _) = start_secure_upstream().await;
+
+    let ca = TempDir::new().unwrap();
+    let (ca_pem, ca_key) = CaStore::new(ca.path()).load_or_generate().unwrap();
+    let ssl = Ssl::new(ca_pem.as_bytes(), ca_key.as_bytes()).unwrap();
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let proxy = ProxyBuilder::new()
+        .with_addr(addr)
+        .with_upstream_tls(tls)
+        .with_rustls_client()
+        .with_ca(ssl)
+        .build();
+    tokio::spawn(async move { proxy.start(std::future::pending()).await });
+
+    let mut socket = open_secure_session(addr, &ca_pem, upstream.port()).await;
+    socket.send(Message::Text("hello".into())).await.unwrap();
+    match tokio::time::timeout(Duration::from_secs(5), socket.next()).await {
+        Ok(Some(Ok(message))) => Some(message),
+        _ => None,
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn secure_upstream_follows_tls_options() {
+    let echoed =
+        echo_through_proxy(TlsOptions::new().with_insecure("localhost".parse().unwrap())).await;
+    assert_eq!(echoed, Some(Message::Text("hello".into())));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn secure_upstream_is_verified() {
+    // The self-signed certificate isn't trusted, the upstream session fails and is closed.
+    let echoed = echo_through_proxy(TlsOptions::new()).await;
+    assert!(
+        !matches!(echoed, Some(Message::Text(_))),
+        "untrusted upstream was reached: {echoed:?}"
+    );
+}
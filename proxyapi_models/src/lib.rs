@@ -1,10 +1,22 @@
 use bytes::Bytes;
 use http::{HeaderMap, Method, StatusCode, Uri, Version};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt, net::SocketAddr};
+
+/// Version of the models written by this crate.
+///
+/// Captures serialized before the models were versioned read as version 1. Version 2 added the
+/// connection metadata and body sizes, version 3 the timings.
+pub const MODEL_VERSION: u32 = 3;
+
+fn unversioned() -> u32 {
+    1
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct ProxiedRequest {
+    #[serde(default = "unversioned")]
+    model_version: u32,
     #[serde(with = "http_serde::method")]
     method: Method,
     #[serde(with = "http_serde::uri")]
@@ -15,6 +27,13 @@ pub struct ProxiedRequest {
     headers: HeaderMap,
     body: Bytes,
     time: i64,
+    /// Set when only the beginning of the body was captured.
+    #[serde(default)]
+    truncated: bool,
+    #[serde(default)]
+    client: ClientInfo,
+    #[serde(default)]
+    body_size: Option<BodySize>,
 }
 
 impl ProxiedRequest {
@@ -27,15 +46,42 @@ impl ProxiedRequest {
         time: i64,
     ) -> Self {
         Self {
+            model_version: MODEL_VERSION,
             method,
             uri,
             version,
             headers,
             body,
             time,
+            truncated: false,
+            client: ClientInfo::default(),
+            body_size: None,
+        }
+    }
+
+    pub fn with_body(self, body: Bytes) -> Self {
+        Self { body, ..self }
+    }
+
+    pub fn with_truncated(self, truncated: bool) -> Self {
+        Self { truncated, ..self }
+    }
+
+    pub fn with_client(self, client: ClientInfo) -> Self {
+        Self { client, ..self }
+    }
+
+    pub fn with_body_size(self, body_size: BodySize) -> Self {
+        Self {
+            body_size: Some(body_size),
+            ..self
         }
     }
 
+    pub fn model_version(&self) -> u32 {
+        self.model_version
+    }
+
     pub fn method(&self) -> &Method {
         &self.method
     }
@@ -59,10 +105,26 @@ impl ProxiedRequest {
     pub fn time(&self) -> i64 {
         self.time
     }
+
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// Client the request came from and how the proxy got to see it.
+    pub fn client(&self) -> &ClientInfo {
+        &self.client
+    }
+
+    /// Size of the whole body, known once it streamed through.
+    pub fn body_size(&self) -> Option<&BodySize> {
+        self.body_size.as_ref()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct ProxiedResponse {
+    #[serde(default = "unversioned")]
+    model_version: u32,
     #[serde(with = "http_serde::status_code")]
     status: StatusCode,
     #[serde(with = "http_serde::version")]
@@ -71,6 +133,18 @@ pub struct ProxiedResponse {
     headers: HeaderMap,
     body: Bytes,
     time: i64,
+    /// Set when only the beginning of the body was captured.
+    #[serde(default)]
+    truncated: bool,
+    /// Why the request couldn't be forwarded, when the response was made up by the proxy.
+    #[serde(default)]
+    error: Option<FlowError>,
+    #[serde(default)]
+    server: ServerInfo,
+    #[serde(default)]
+    body_size: Option<BodySize>,
+    #[serde(default)]
+    timings: Option<Timings>,
 }
 
 impl ProxiedResponse {
@@ -82,14 +156,53 @@ impl ProxiedResponse {
         time: i64,
     ) -> Self {
         Self {
+            model_version: MODEL_VERSION,
             status,
             version,
             headers,
             body,
             time,
+            truncated: false,
+            error: None,
+            server: ServerInfo::default(),
+            body_size: None,
+            timings: None,
+        }
+    }
+
+    pub fn with_truncated(self, truncated: bool) -> Self {
+        Self { truncated, ..self }
+    }
+
+    pub fn with_error(self, error: FlowError) -> Self {
+        Self {
+            error: Some(error),
+            ..self
         }
     }
 
+    pub fn with_server(self, server: ServerInfo) -> Self {
+        Self { server, ..self }
+    }
+
+    pub fn with_body_size(self, body_size: BodySize) -> Self {
+        Self {
+            body_size: Some(body_size),
+            ..self
+        }
+    }
+
+    pub fn with_timings(self, timings: Timings) -> Self {
+        Self {
+            timings: Some(timings),
+            ..self
+        }
+    }
+
+    pub fn model_version(&self) -> u32 {
+        self.model_version
+    }
+
     pub fn status(&self) -> &StatusCode {
         &self.status
     }
@@ -109,12 +222,409 @@ impl ProxiedResponse {
     pub fn time(&self) -> i64 {
         self.time
     }
+
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+
+    pub fn error(&self) -> Option<&FlowError> {
+        self.error.as_ref()
+    }
+
+    /// Upstream server the response came from.
+    pub fn server(&self) -> &ServerInfo {
+        &self.server
+    }
+
+    /// Size of the whole body, known once it streamed through.
+    pub fn body_size(&self) -> Option<&BodySize> {
+        self.body_size.as_ref()
+    }
+
+    /// How long forwarding the exchange took, the download is only known once the body
+    /// streamed through.
+    pub fn timings(&self) -> Option<&Timings> {
+        self.timings.as_ref()
+    }
+}
+
+/// How the proxy got to see an exchange.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum InterceptionMode {
+    /// Plain HTTP request sent to the proxy.
+    Forward,
+    /// Plain HTTP request inside a tunnel.
+    Tunnel,
+    /// HTTPS request decrypted by presenting a certificate signed by the proxy's CA.
+    Mitm,
+    /// Request to the reverse proxy, over HTTP or HTTPS.
+    Reverse,
+}
+
+impl InterceptionMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Forward => "Forward",
+            Self::Tunnel => "Tunnel",
+            Self::Mitm => "MITM",
+            Self::Reverse => "Reverse",
+        }
+    }
+}
+
+impl fmt::Display for InterceptionMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Parameters negotiated for a TLS connection.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct TlsInfo {
+    version: String,
+    cipher: String,
+}
+
+impl TlsInfo {
+    pub fn new(version: String, cipher: String) -> Self {
+        Self { version, cipher }
+    }
+
+    /// Protocol version, e.g. `TLS 1.3`.
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// Cipher suite, e.g. `TLS13_AES_128_GCM_SHA256`.
+    pub fn cipher(&self) -> &str {
+        &self.cipher
+    }
+}
+
+impl fmt::Display for TlsInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.version, self.cipher)
+    }
+}
+
+/// Client side of an exchange. Everything is unknown for captures older than version 2.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ClientInfo {
+    addr: Option<SocketAddr>,
+    connection_id: Option<u64>,
+    mode: Option<InterceptionMode>,
+    sni: Option<String>,
+    tls: Option<TlsInfo>,
+}
+
+impl ClientInfo {
+    pub fn new(addr: SocketAddr, connection_id: u64, mode: InterceptionMode) -> Self {
+        Self {
+            addr: Some(addr),
+            connection_id: Some(connection_id),
+            mode: Some(mode),
+            sni: None,
+            tls: None,
+        }
+    }
+
+    /// Sets the server name the client asked for and the TLS parameters negotiated with it.
+    pub fn with_tls(self, sni: Option<String>, tls: TlsInfo) -> Self {
+        Self {
+            sni,
+            tls: Some(tls),
+            ..self
+        }
+    }
+
+    pub fn addr(&self) -> Option<SocketAddr> {
+        self.addr
+    }
+
+    /// Id of the client connection, shared by the exchanges sent over it.
+    pub fn connection_id(&self) -> Option<u64> {
+        self.connection_id
+    }
+
+    pub fn mode(&self) -> Option<InterceptionMode> {
+        self.mode
+    }
+
+    pub fn sni(&self) -> Option<&str> {
+        self.sni.as_deref()
+    }
+
+    pub fn tls(&self) -> Option<&TlsInfo> {
+        self.tls.as_ref()
+    }
+}
+
+/// Upstream side of an exchange, unknown for responses made up by the proxy.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ServerInfo {
+    addr: Option<SocketAddr>,
+    tls: Option<TlsInfo>,
+}
+
+impl ServerInfo {
+    pub fn new(addr: Option<SocketAddr>, tls: Option<TlsInfo>) -> Self {
+        Self { addr, tls }
+    }
+
+    /// Address connected to, the parent proxy's when going through one.
+    pub fn addr(&self) -> Option<SocketAddr> {
+        self.addr
+    }
+
+    pub fn tls(&self) -> Option<&TlsInfo> {
+        self.tls.as_ref()
+    }
+}
+
+/// Size of a body as transferred and once its content encoding is undone.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BodySize {
+    wire: u64,
+    decoded: Option<u64>,
+}
+
+impl BodySize {
+    pub fn new(wire: u64, decoded: Option<u64>) -> Self {
+        Self { wire, decoded }
+    }
+
+    /// Bytes transferred, still content encoded.
+    pub fn wire(&self) -> u64 {
+        self.wire
+    }
+
+    /// Bytes once decoded, unknown when the body was truncated or couldn't be decoded.
+    pub fn decoded(&self) -> Option<u64> {
+        self.decoded
+    }
+}
+
+/// Phase of forwarding an exchange upstream, in the order they happen.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum Phase {
+    Dns,
+    Connect,
+    Tls,
+    /// Sending the request, from the moment the connection is ready.
+    Send,
+    /// Waiting for the response head, the time to first byte.
+    Wait,
+    /// Receiving the response body.
+    Download,
+}
+
+impl Phase {
+    pub const ALL: [Phase; 6] = [
+        Self::Dns,
+        Self::Connect,
+        Self::Tls,
+        Self::Send,
+        Self::Wait,
+        Self::Download,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Dns => "DNS",
+            Self::Connect => "Connect",
+            Self::Tls => "TLS",
+            Self::Send => "Send",
+            Self::Wait => "Wait",
+            Self::Download => "Download",
+        }
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Durations of the phases of forwarding an exchange, in nanoseconds.
+///
+/// The connection phases are only known for exchanges that opened a new upstream connection,
+/// the others reuse one.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Timings {
+    start: i64,
+    dns: Option<i64>,
+    connect: Option<i64>,
+    tls: Option<i64>,
+    send: Option<i64>,
+    wait: i64,
+    download: Option<i64>,
+}
+
+impl Timings {
+    /// Timings of an exchange forwarded at `start` whose response head came `wait` later.
+    pub fn new(start: i64, wait: i64) -> Self {
+        Self {
+            start,
+            dns: None,
+            connect: None,
+            tls: None,
+            send: None,
+            wait,
+            download: None,
+        }
+    }
+
+    /// Sets the phases of opening the connection, DNS isn't resolved for IP addresses and
+    /// there is no TLS for plain HTTP.
+    pub fn with_connection(self, dns: Option<i64>, connect: i64, tls: Option<i64>) -> Self {
+        Self {
+            dns,
+            connect: Some(connect),
+            tls,
+            ..self
+        }
+    }
+
+    pub fn with_send(self, send: i64) -> Self {
+        Self {
+            send: Some(send),
+            ..self
+        }
+    }
+
+    pub fn with_download(self, download: i64) -> Self {
+        Self {
+            download: Some(download),
+            ..self
+        }
+    }
+
+    /// When the exchange was forwarded, in nanoseconds since the epoch.
+    pub fn start(&self) -> i64 {
+        self.start
+    }
+
+    pub fn get(&self, phase: Phase) -> Option<i64> {
+        match phase {
+            Phase::Dns => self.dns,
+            Phase::Connect => self.connect,
+            Phase::Tls => self.tls,
+            Phase::Send => self.send,
+            Phase::Wait => Some(self.wait),
+            Phase::Download => self.download,
+        }
+    }
+
+    /// Known phases with their offset from [`Timings::start`] and duration.
+    pub fn phases(&self) -> Vec<(Phase, i64, i64)> {
+        let mut offset = 0;
+        Phase::ALL
+            .into_iter()
+            .filter_map(|phase| {
+                let duration = self.get(phase)?;
+                offset += duration;
+                Some((phase, offset - duration, duration))
+            })
+            .collect()
+    }
+
+    /// Duration of all known phases.
+    pub fn total(&self) -> i64 {
+        Phase::ALL
+            .into_iter()
+            .filter_map(|phase| self.get(phase))
+            .sum()
+    }
+}
+
+/// Kind of failure that kept the proxy from completing an exchange or a tunnel.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    /// The upstream host name couldn't be resolved.
+    Dns,
+    /// The upstream server, or the parent proxy, refused the connection.
+    ConnectionRefused,
+    /// The upstream server took too long to accept the connection or answer.
+    Timeout,
+    /// The TLS handshake with the upstream server failed, e.g. its certificate isn't trusted.
+    TlsHandshake,
+    /// The upstream server sent an invalid response or closed the connection before answering.
+    UpstreamProtocol,
+    /// The client went away before its request was sent upstream.
+    ClientAbort,
+    /// The TLS handshake with the client failed, e.g. it doesn't trust the proxy's CA.
+    ClientTls,
+    Other,
+}
+
+impl ErrorKind {
+    pub const ALL: [ErrorKind; 8] = [
+        Self::Dns,
+        Self::ConnectionRefused,
+        Self::Timeout,
+        Self::TlsHandshake,
+        Self::UpstreamProtocol,
+        Self::ClientAbort,
+        Self::ClientTls,
+        Self::Other,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Dns => "DNS failure",
+            Self::ConnectionRefused => "Connection refused",
+            Self::Timeout => "Timeout",
+            Self::TlsHandshake => "TLS handshake failure",
+            Self::UpstreamProtocol => "Upstream protocol error",
+            Self::ClientAbort => "Client abort",
+            Self::ClientTls => "Client TLS failure",
+            Self::Other => "Other error",
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Why an exchange failed, its kind and the description of the underlying error.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FlowError {
+    kind: ErrorKind,
+    message: String,
+}
+
+impl FlowError {
+    pub fn new(kind: ErrorKind, message: String) -> Self {
+        Self { kind, message }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for FlowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.kind, self.message)
+    }
 }
 
+impl std::error::Error for FlowError {}
+
+#[allow(dead_code)]
 trait ToString {
     fn to_string(&self) -> String;
 }
 
+#[allow(dead_code)]
 trait ToHashString {
     fn to_hash_string(&self) -> HashMap<String, String>;
 }
@@ -147,3 +657,487 @@ impl ToString for Version {
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct RequestInfo(pub Option<ProxiedRequest>, pub Option<ProxiedResponse>);
+
+/// Step in the life of an exchange, sent as soon as it happens.
+///
+/// Every event carries the id of its flow, unique to the exchange. A flow starts with
+/// [`FlowEvent::RequestReceived`] and ends with either [`FlowEvent::ResponseComplete`] or
+/// [`FlowEvent::Error`].
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum FlowEvent {
+    /// The request headers were received from the client, its body isn't captured yet.
+    RequestReceived {
+        flow: u64,
+        request: Box<ProxiedRequest>,
+    },
+    /// The request was sent upstream.
+    RequestForwarded { flow: u64, time: i64 },
+    /// The response headers were received, its body is still streaming.
+    ResponseHeaders {
+        flow: u64,
+        response: Box<ProxiedResponse>,
+    },
+    /// The response body streamed through, both bodies are captured.
+    ResponseComplete {
+        flow: u64,
+        request: Box<ProxiedRequest>,
+        response: Box<ProxiedResponse>,
+    },
+    /// The request couldn't be forwarded, `response` was made up by the proxy and tells why.
+    Error {
+        flow: u64,
+        response: Box<ProxiedResponse>,
+    },
+}
+
+impl FlowEvent {
+    pub fn flow(&self) -> u64 {
+        match self {
+            Self::RequestReceived { flow, .. }
+            | Self::RequestForwarded { flow, .. }
+            | Self::ResponseHeaders { flow, .. }
+            | Self::ResponseComplete { flow, .. }
+            | Self::Error { flow, .. } => *flow,
+        }
+    }
+}
+
+/// How far a flow got.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum FlowState {
+    /// Received from the client, not forwarded yet.
+    Received,
+    /// Forwarded upstream, waiting for the response.
+    Forwarded,
+    /// Response headers received, the body is streaming.
+    Receiving,
+    Complete,
+    Failed,
+}
+
+impl FlowState {
+    /// Whether the flow is still in flight.
+    pub fn is_pending(&self) -> bool {
+        matches!(self, Self::Received | Self::Forwarded | Self::Receiving)
+    }
+}
+
+/// An exchange as known from the events of its flow so far.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Flow {
+    id: u64,
+    state: FlowState,
+    request: ProxiedRequest,
+    response: Option<ProxiedResponse>,
+}
+
+impl Flow {
+    /// Starts tracking a flow from its first event, `None` for any other event.
+    pub fn from_event(event: FlowEvent) -> Option<Self> {
+        match event {
+            FlowEvent::RequestReceived { flow, request } => Some(Self {
+                id: flow,
+                state: FlowState::Received,
+                request: *request,
+                response: None,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Updates the flow with one of its later events.
+    pub fn apply(&mut self, event: FlowEvent) {
+        match event {
+            FlowEvent::RequestReceived { .. } => {}
+            FlowEvent::RequestForwarded { .. } => self.state = FlowState::Forwarded,
+            FlowEvent::ResponseHeaders { response, .. } => {
+                self.state = FlowState::Receiving;
+                self.response = Some(*response);
+            }
+            FlowEvent::ResponseComplete {
+                request, response, ..
+            } => {
+                self.state = FlowState::Complete;
+                self.request = *request;
+                self.response = Some(*response);
+            }
+            FlowEvent::Error { response, .. } => {
+                self.state = FlowState::Failed;
+                self.response = Some(*response);
+            }
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn state(&self) -> FlowState {
+        self.state
+    }
+
+    pub fn request(&self) -> &ProxiedRequest {
+        &self.request
+    }
+
+    pub fn response(&self) -> Option<&ProxiedResponse> {
+        self.response.as_ref()
+    }
+
+    /// Why the request couldn't be forwarded, if the flow failed.
+    pub fn error(&self) -> Option<&FlowError> {
+        self.response.as_ref().and_then(ProxiedResponse::error)
+    }
+}
+
+/// A connection relayed without being intercepted, only its endpoints and volume are known.
+///
+/// Tunnels the proxy failed to relay or intercept are recorded too, with their error.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Tunnel {
+    host: String,
+    port: u16,
+    bytes_up: u64,
+    bytes_down: u64,
+    time: i64,
+    duration: i64,
+    #[serde(default)]
+    error: Option<FlowError>,
+}
+
+impl Tunnel {
+    pub fn new(
+        host: String,
+        port: u16,
+        bytes_up: u64,
+        bytes_down: u64,
+        time: i64,
+        duration: i64,
+    ) -> Self {
+        Self {
+            host,
+            port,
+            bytes_up,
+            bytes_down,
+            time,
+            duration,
+            error: None,
+        }
+    }
+
+    pub fn with_error(self, error: FlowError) -> Self {
+        Self {
+            error: Some(error),
+            ..self
+        }
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Bytes sent by the client.
+    pub fn bytes_up(&self) -> u64 {
+        self.bytes_up
+    }
+
+    /// Bytes sent by the server.
+    pub fn bytes_down(&self) -> u64 {
+        self.bytes_down
+    }
+
+    pub fn time(&self) -> i64 {
+        self.time
+    }
+
+    /// Nanoseconds the tunnel was open for.
+    pub fn duration(&self) -> i64 {
+        self.duration
+    }
+
+    /// Why the tunnel failed, if it did.
+    pub fn error(&self) -> Option<&FlowError> {
+        self.error.as_ref()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum WebSocketDirection {
+    ClientToServer,
+    ServerToClient,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum WebSocketMessage {
+    Text(String),
+    Binary(Bytes),
+    Ping(Bytes),
+    Pong(Bytes),
+    Close(Option<(u16, String)>),
+}
+
+impl WebSocketMessage {
+    pub fn is_control(&self) -> bool {
+        matches!(self, Self::Ping(_) | Self::Pong(_) | Self::Close(_))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct WebSocketFrame {
+    direction: WebSocketDirection,
+    message: WebSocketMessage,
+    time: i64,
+}
+
+impl WebSocketFrame {
+    pub fn new(direction: WebSocketDirection, message: WebSocketMessage, time: i64) -> Self {
+        Self {
+            direction,
+            message,
+            time,
+        }
+    }
+
+    pub fn direction(&self) -> WebSocketDirection {
+        self.direction
+    }
+
+    pub fn message(&self) -> &WebSocketMessage {
+        &self.message
+    }
+
+    pub fn time(&self) -> i64 {
+        self.time
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct WebSocketSession {
+    id: u64,
+    request: ProxiedRequest,
+    response: ProxiedResponse,
+    frames: Vec<WebSocketFrame>,
+    #[serde(default)]
+    closed: Option<(u16, String)>,
+}
+
+impl WebSocketSession {
+    pub fn new(id: u64, request: ProxiedRequest, response: ProxiedResponse) -> Self {
+        Self {
+            id,
+            request,
+            response,
+            frames: Vec::new(),
+            closed: None,
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn request(&self) -> &ProxiedRequest {
+        &self.request
+    }
+
+    pub fn response(&self) -> &ProxiedResponse {
+        &self.response
+    }
+
+    pub fn frames(&self) -> &[WebSocketFrame] {
+        &self.frames
+    }
+
+    pub fn push_frame(&mut self, frame: WebSocketFrame) {
+        self.frames.push(frame);
+    }
+
+    /// Close code and reason the session ended with, `None` while it is still open.
+    pub fn closed(&self) -> Option<(u16, &str)> {
+        self.closed
+            .as_ref()
+            .map(|(code, reason)| (*code, reason.as_str()))
+    }
+
+    pub fn close(&mut self, code: u16, reason: String) {
+        self.closed = Some((code, reason));
+    }
+}
+
+/// Incremental update of a WebSocket session, sent as soon as it happens.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum WebSocketEvent {
+    /// The handshake completed on both sides, the session has no frames yet.
+    Opened(Box<WebSocketSession>),
+    /// A frame was relayed in the session with the given id.
+    Frame { session: u64, frame: WebSocketFrame },
+    /// Either side closed the session with the given id, or the connection failed.
+    ///
+    /// Sessions that ended without a close frame report 1006 (abnormal closure).
+    Closed {
+        session: u64,
+        code: u16,
+        reason: String,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> ProxiedRequest {
+        let mut headers = HeaderMap::new();
+        headers.insert(http::header::HOST, "example.com".parse().unwrap());
+
+        ProxiedRequest::new(
+            Method::POST,
+            "http://example.com/submit".parse().unwrap(),
+            Version::HTTP_11,
+            headers,
+            Bytes::from_static(b"name=value"),
+            1_000,
+        )
+        .with_client(
+            ClientInfo::new(
+                "127.0.0.1:50000".parse().unwrap(),
+                7,
+                InterceptionMode::Mitm,
+            )
+            .with_tls(
+                Some("example.com".to_owned()),
+                TlsInfo::new("TLSv1.3".to_owned(), "TLS13_AES_128_GCM_SHA256".to_owned()),
+            ),
+        )
+        .with_body_size(BodySize::new(10, None))
+    }
+
+    fn response() -> ProxiedResponse {
+        ProxiedResponse::new(
+            StatusCode::OK,
+            Version::HTTP_11,
+            HeaderMap::new(),
+            Bytes::from_static(b"ok"),
+            2_000,
+        )
+        .with_server(ServerInfo::new(
+            Some("93.184.216.34:80".parse().unwrap()),
+            None,
+        ))
+        .with_body_size(BodySize::new(22, Some(2)))
+        .with_timings(
+            Timings::new(1_000, 400)
+                .with_connection(Some(100), 200, None)
+                .with_send(50)
+                .with_download(250),
+        )
+    }
+
+    fn round_trip<T: Serialize + for<'de> Deserialize<'de>>(value: &T) -> T {
+        serde_json::from_str(&serde_json::to_string(value).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn reads_unversioned_requests() {
+        let request: ProxiedRequest = serde_json::from_str(
+            r#"{
+                "method": "GET",
+                "uri": "http://example.com/",
+                "version": "HTTP/1.1",
+                "headers": {"host": "example.com"},
+                "body": [104, 105],
+                "time": 1000
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(request.model_version(), 1);
+        assert_eq!(request.method(), Method::GET);
+        assert_eq!(request.uri(), "http://example.com/");
+        assert_eq!(request.headers()[http::header::HOST], "example.com");
+        assert_eq!(request.body(), "hi");
+        assert!(!request.truncated());
+        assert_eq!(request.client(), &ClientInfo::default());
+        assert_eq!(request.body_size(), None);
+    }
+
+    #[test]
+    fn reads_unversioned_responses() {
+        let response: ProxiedResponse = serde_json::from_str(
+            r#"{
+                "status": 404,
+                "version": "HTTP/1.1",
+                "headers": {},
+                "body": [],
+                "time": 2000
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(response.model_version(), 1);
+        assert_eq!(*response.status(), StatusCode::NOT_FOUND);
+        assert!(!response.truncated());
+        assert_eq!(response.error(), None);
+        assert_eq!(response.server(), &ServerInfo::default());
+        assert_eq!(response.body_size(), None);
+        assert_eq!(response.timings(), None);
+    }
+
+    #[test]
+    fn round_trips_flow_events() {
+        let events = [
+            FlowEvent::RequestReceived {
+                flow: 1,
+                request: Box::new(request()),
+            },
+            FlowEvent::RequestForwarded {
+                flow: 1,
+                time: 1_100,
+            },
+            FlowEvent::ResponseHeaders {
+                flow: 1,
+                response: Box::new(response()),
+            },
+            FlowEvent::ResponseComplete {
+                flow: 1,
+                request: Box::new(request()),
+                response: Box::new(response()),
+            },
+            FlowEvent::Error {
+                flow: 2,
+                response: Box::new(response().with_error(FlowError::new(
+                    ErrorKind::Timeout,
+                    "no response within 120s".to_owned(),
+                ))),
+            },
+        ];
+
+        for event in events {
+            assert_eq!(round_trip(&event), event);
+        }
+    }
+
+    #[test]
+    fn round_trips_flows() {
+        let mut flow = Flow::from_event(FlowEvent::RequestReceived {
+            flow: 1,
+            request: Box::new(request()),
+        })
+        .unwrap();
+        flow.apply(FlowEvent::ResponseComplete {
+            flow: 1,
+            request: Box::new(request()),
+            response: Box::new(response()),
+        });
+
+        let read = round_trip(&flow);
+        assert_eq!(read, flow);
+        assert_eq!(read.state(), FlowState::Complete);
+        assert_eq!(read.request().model_version(), MODEL_VERSION);
+        assert_eq!(read.response().unwrap().model_version(), MODEL_VERSION);
+        assert_eq!(read.response().unwrap().timings(), response().timings());
+    }
+}
@@ -1,4 +1,7 @@
-use proxyapi::Proxy;
+use proxyapi::{
+    ca::{CaStore, Ssl},
+    ProxyBuilder, ProxyHandler,
+};
 use std::net::SocketAddr;
 use tokio::sync::oneshot::Sender;
 
@@ -8,7 +11,8 @@ use tauri::{
     AppHandle, Manager, Runtime, State,
 };
 
-use proxyapi_models::RequestInfo;
+/// Events the proxy may get ahead of the UI by, it drops the ones beyond that rather than wait.
+const EVENT_BUFFER: usize = 1024;
 
 type ProxyState = Mutex<Option<(Sender<()>, tauri::async_runtime::JoinHandle<()>)>>;
 
@@ -18,10 +22,24 @@ async fn start_proxy<R: Runtime>(
     proxy: State<'_, ProxyState>,
     addr: SocketAddr,
 ) -> Result<(), String> {
-    let (tx, rx) = std::sync::mpsc::sync_channel(1);
+    let ca = Ssl::from_store(&CaStore::default())
+        .map_err(|e| format!("Failed to load the root CA: {e}"))?;
+    let (flow_tx, flow_rx) = std::sync::mpsc::sync_channel(EVENT_BUFFER);
+    let (websocket_tx, websocket_rx) = std::sync::mpsc::sync_channel(EVENT_BUFFER);
+    let (tunnel_tx, tunnel_rx) = std::sync::mpsc::sync_channel(EVENT_BUFFER);
     let (close_tx, close_rx) = tokio::sync::oneshot::channel();
     let thread = tauri::async_runtime::spawn(async move {
-        if let Err(e) = Proxy::new(addr, Some(tx.clone()))
+        if let Err(e) = ProxyBuilder::new()
+            .with_addr(addr)
+            .with_rustls_client()
+            .with_ca(ca)
+            .with_http_handler(
+                ProxyHandler::default()
+                    .with_flow_sender(flow_tx)
+                    .with_tunnel_sender(tunnel_tx),
+            )
+            .with_websocket_handler(ProxyHandler::default().with_websocket_sender(websocket_tx))
+            .build()
             .start(async move {
                 let _ = close_rx.await;
             })
@@ -34,11 +52,31 @@ async fn start_proxy<R: Runtime>(
     let mut proxy = proxy.lock().await;
     proxy.replace((close_tx, thread));
 
-    tauri::async_runtime::spawn(async move {
-        for exchange in rx.iter() {
-            let (request, response) = exchange.to_parts();
-            app.emit_all("proxy_event", RequestInfo(request, response))
-                .unwrap();
+    // The receivers block, they are drained on threads of their own rather than the async
+    // runtime the proxy runs on. Each ends once the proxy stops and drops its senders.
+    let websocket_app = app.clone();
+    std::thread::spawn(move || {
+        for event in websocket_rx.iter() {
+            if let Err(e) = websocket_app.emit_all("websocket_event", event) {
+                eprintln!("Failed to emit WebSocket event: {e}");
+            }
+        }
+    });
+
+    let tunnel_app = app.clone();
+    std::thread::spawn(move || {
+        for tunnel in tunnel_rx.iter() {
+            if let Err(e) = tunnel_app.emit_all("tunnel_event", tunnel) {
+                eprintln!("Failed to emit tunnel event: {e}");
+            }
+        }
+    });
+
+    std::thread::spawn(move || {
+        for event in flow_rx.iter() {
+            if let Err(e) = app.emit_all("flow_event", event) {
+                eprintln!("Failed to emit flow event: {e}");
+            }
         }
     });
 
@@ -1,6 +1,6 @@
 use gloo_utils::format::JsValueSerdeExt;
 use js_sys::{Function, Promise};
-use proxyapi_models::RequestInfo;
+use proxyapi_models::{FlowEvent, Tunnel, WebSocketEvent};
 use serde::{Deserialize, Serialize};
 use std::net::SocketAddr;
 use wasm_bindgen::prelude::*;
@@ -16,23 +16,26 @@ extern "C" {
     fn listen_(event: &str, handler: &Closure<dyn FnMut(JsValue)>) -> Promise;
 }
 
-pub struct EventListener(Promise, Closure<dyn FnMut(JsValue)>);
+pub struct EventListener(Promise, Option<Closure<dyn FnMut(JsValue)>>);
 impl Drop for EventListener {
     fn drop(&mut self) {
         let promise = self.0.clone();
+        // The handler can still be called until unlisten ran, so it's only freed after.
+        let handler = self.1.take();
         spawn_local(async move {
             let unlisten: Function = wasm_bindgen_futures::JsFuture::from(promise)
                 .await
                 .unwrap()
                 .into();
             unlisten.call0(&JsValue::undefined()).unwrap();
+            drop(handler);
         });
     }
 }
 
 fn listen(event: &str, handler: Closure<dyn FnMut(JsValue)>) -> EventListener {
     let promise = listen_(event, &handler);
-    EventListener(promise, handler)
+    EventListener(promise, Some(handler))
 }
 
 #[derive(Serialize)]
@@ -67,18 +70,42 @@ pub fn stop_proxy(on_stop: Option<Callback<()>>) {
 }
 
 #[derive(Deserialize)]
-struct ProxyEvent {
-    payload: RequestInfo,
+struct ProxyEvent<T> {
+    payload: T,
 }
 
-pub fn listen_proxy_event(on_request: Option<Callback<RequestInfo>>) -> EventListener {
+pub fn listen_flow_event(on_event: Option<Callback<FlowEvent>>) -> EventListener {
     let closure = Closure::new(move |event: JsValue| {
-        let on_request = on_request.clone();
-        if let Ok(ProxyEvent { payload }) = event.into_serde() {
-            if let Some(on_request) = on_request {
-                on_request.emit(payload);
+        let on_event = on_event.clone();
+        if let Ok(ProxyEvent::<FlowEvent> { payload }) = event.into_serde() {
+            if let Some(on_event) = on_event {
+                on_event.emit(payload);
             }
         }
     });
-    listen("proxy_event", closure)
+    listen("flow_event", closure)
+}
+
+pub fn listen_websocket_event(on_event: Option<Callback<WebSocketEvent>>) -> EventListener {
+    let closure = Closure::new(move |event: JsValue| {
+        let on_event = on_event.clone();
+        if let Ok(ProxyEvent::<WebSocketEvent> { payload }) = event.into_serde() {
+            if let Some(on_event) = on_event {
+                on_event.emit(payload);
+            }
+        }
+    });
+    listen("websocket_event", closure)
+}
+
+pub fn listen_tunnel_event(on_tunnel: Option<Callback<Tunnel>>) -> EventListener {
+    let closure = Closure::new(move |event: JsValue| {
+        let on_tunnel = on_tunnel.clone();
+        if let Ok(ProxyEvent::<Tunnel> { payload }) = event.into_serde() {
+            if let Some(on_tunnel) = on_tunnel {
+                on_tunnel.emit(payload);
+            }
+        }
+    });
+    listen("tunnel_event", closure)
 }
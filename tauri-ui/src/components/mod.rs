@@ -4,3 +4,5 @@ pub mod proxy_off;
 pub mod proxy_on;
 pub mod request;
 pub mod title_bar;
+pub mod tunnel;
+pub mod websocket;
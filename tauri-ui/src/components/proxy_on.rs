@@ -3,6 +3,8 @@ use yew::prelude::*;
 
 use crate::api::stop_proxy;
 use crate::components::request::RequestTable;
+use crate::components::tunnel::TunnelTable;
+use crate::components::websocket::WebSocketTable;
 
 #[derive(Clone, PartialEq, Properties)]
 pub struct Props {
@@ -13,15 +15,23 @@ pub struct Props {
 pub fn proxy_on(props: &Props) -> Html {
     let paused = use_state(|| false);
     let requests = use_mut_ref(Vec::new);
+    let sessions = use_mut_ref(Vec::new);
+    let tunnels = use_mut_ref(Vec::new);
     let onclick = {
         let requests = requests.clone();
+        let sessions = sessions.clone();
+        let tunnels = tunnels.clone();
         let stop = props.stop.clone();
         Callback::from(move |_| {
             let requests = requests.clone();
+            let sessions = sessions.clone();
+            let tunnels = tunnels.clone();
             let stop = stop.clone();
             let on_stop = Callback::from(move |_: ()| {
                 let mut r = requests.borrow_mut();
                 r.drain(..);
+                sessions.borrow_mut().clear();
+                tunnels.borrow_mut().clear();
                 stop.emit(());
             });
             stop_proxy(Some(on_stop));
@@ -74,6 +84,8 @@ pub fn proxy_on(props: &Props) -> Html {
                 <button {onclick} ~innerText={"⏹"} />
             </div>
             <RequestTable paused={is_paused} {requests} />
+            <WebSocketTable paused={is_paused} {sessions} />
+            <TunnelTable paused={is_paused} {tunnels} />
         </div>
     }
 }
@@ -3,6 +3,7 @@ mod request_tab;
 mod response_tab;
 mod row;
 mod tab_view;
+mod waterfall;
 
 const OPTIONS: [&str; 10] = [
     "POST", "GET", "PUT", "DELETE", "PATCH", "HEAD", "OPTIONS", "CONNECT", "TRACE", "OTHERS",
@@ -10,16 +11,17 @@ const OPTIONS: [&str; 10] = [
 
 use self::details::RequestDetails;
 use self::row::RequestRow;
-use crate::api::listen_proxy_event;
+use self::waterfall::timeline;
+use crate::api::listen_flow_event;
 use crate::components::input::MultipleSelectInput;
-use proxyapi_models::RequestInfo;
+use proxyapi_models::{BodySize, ErrorKind, Flow, FlowEvent};
 use std::{cell::RefCell, rc::Rc};
 use stylist::yew::use_style;
 use yew::prelude::*;
 
 #[derive(Clone, PartialEq, Properties)]
 pub struct Props {
-    pub requests: Rc<RefCell<Vec<RequestInfo>>>,
+    pub requests: Rc<RefCell<Vec<Flow>>>,
     pub paused: bool,
 }
 
@@ -28,6 +30,32 @@ pub fn filter_request(method: String, filters: &[String]) -> bool {
         || (!OPTIONS.contains(&method.as_str()) && filters.contains(&"OTHERS".to_string()))
 }
 
+/// Options of the status filter: complete, pending, or the kind of error the flow failed with.
+fn status_options() -> Vec<String> {
+    ["Complete", "Pending"]
+        .into_iter()
+        .map(str::to_string)
+        .chain(ErrorKind::ALL.iter().map(ErrorKind::to_string))
+        .collect()
+}
+
+pub fn flow_status(flow: &Flow) -> String {
+    match flow.error() {
+        Some(error) => error.kind().to_string(),
+        None if flow.state().is_pending() => "Pending".to_string(),
+        None => "Complete".to_string(),
+    }
+}
+
+pub fn body_size(size: &BodySize) -> String {
+    match size.decoded() {
+        Some(decoded) if decoded != size.wire() => {
+            format!("{} bytes ({} bytes decoded)", size.wire(), decoded)
+        }
+        _ => format!("{} bytes", size.wire()),
+    }
+}
+
 #[function_component(RequestTable)]
 pub fn request_table(props: &Props) -> Html {
     let options = OPTIONS.iter().map(|x| x.to_string()).collect::<Vec<_>>();
@@ -36,6 +64,8 @@ pub fn request_table(props: &Props) -> Html {
     let paused = props.paused;
     let selected = use_state_eq(|| None as Option<usize>);
     let filters = use_state_eq(|| options.clone());
+    let status_options = status_options();
+    let status_filters = use_state_eq(|| status_options.clone());
     let onselect = {
         let requests = requests.clone();
         let selected = selected.clone();
@@ -52,8 +82,8 @@ pub fn request_table(props: &Props) -> Html {
         let selected = selected.clone();
         Callback::from(move |new_value: Vec<String>| {
             if let Some(idx) = *selected {
-                if let Some(RequestInfo(Some(req), _)) = requests.borrow().get(idx) {
-                    if !filter_request(req.method().to_string(), &new_value) {
+                if let Some(flow) = requests.borrow().get(idx) {
+                    if !filter_request(flow.request().method().to_string(), &new_value) {
                         selected.set(None);
                     }
                 }
@@ -61,6 +91,21 @@ pub fn request_table(props: &Props) -> Html {
             filters.set(new_value)
         })
     };
+    let onstatusfilterchange = {
+        let status_filters = status_filters.clone();
+        let requests = requests.clone();
+        let selected = selected.clone();
+        Callback::from(move |new_value: Vec<String>| {
+            if let Some(idx) = *selected {
+                if let Some(flow) = requests.borrow().get(idx) {
+                    if !new_value.contains(&flow_status(flow)) {
+                        selected.set(None);
+                    }
+                }
+            }
+            status_filters.set(new_value)
+        })
+    };
     let ondelete = {
         let requests = requests.clone();
         let trigger = trigger.clone();
@@ -84,14 +129,18 @@ pub fn request_table(props: &Props) -> Html {
         move |(requests, paused)| {
             let requests = requests.clone();
             let paused = *paused;
-            let on_request = Callback::from(move |request_info| {
+            let on_event = Callback::from(move |event: FlowEvent| {
                 let mut r = requests.borrow_mut();
-                if !paused {
-                    r.push(request_info);
-                    trigger.force_update();
+                // Flows that were already captured keep being updated while paused, so they
+                // don't stay pending forever.
+                match r.iter_mut().find(|flow| flow.id() == event.flow()) {
+                    Some(flow) => flow.apply(event),
+                    None if !paused => r.extend(Flow::from_event(event)),
+                    None => return,
                 }
+                trigger.force_update();
             });
-            let listener = listen_proxy_event(Some(on_request));
+            let listener = listen_flow_event(Some(on_event));
             move || drop(listener)
         },
         (requests.clone(), paused),
@@ -148,12 +197,22 @@ pub fn request_table(props: &Props) -> Html {
         .request-table th{
             padding: 10px;
         }
+        .request-table th.waterfall{
+            width: 25%;
+        }
         .request-table tr td:first-child,
         .request-table tr th:first-child
         {
             width: 100%;
             min-width:100%;
         }
+        .request-table .pending {
+            opacity: 0.6;
+            font-style: italic;
+        }
+        .request-table .failed {
+            color: var(--delete);
+        }
         .request-table tr td:last-child,
         .request-table tr th:last-child
         {
@@ -216,32 +275,39 @@ pub fn request_table(props: &Props) -> Html {
     "#
     );
 
+    // Every row is drawn on the same timeline, so the waterfall lines up across rows.
+    let timeline = timeline(&requests.borrow());
+
     html! {
-        if requests.borrow().len() > 0 {
+        if !requests.borrow().is_empty() {
             <div class={style}>
                 <table class="request-table">
                     <tr>
                         <th ~innerText="Path"/>
-                        <th class={method_filter_style}>
+                        <th class={method_filter_style.clone()}>
                             <span ~innerText={"Method ↓"} />
                             <MultipleSelectInput {options} onchange={onfilterchange} />
                         </th>
-                        <th ~innerText="Status"/>
+                        <th class={method_filter_style}>
+                            <span ~innerText={"Status ↓"} />
+                            <MultipleSelectInput options={status_options} onchange={onstatusfilterchange} />
+                        </th>
                         <th ~innerText="Size"/>
                         <th ~innerText="Time"/>
+                        <th class="waterfall" ~innerText="Waterfall"/>
                         <th ~innerText="Action"/>
                     </tr>
                     {
                         requests.borrow().iter().cloned().enumerate().filter_map(
-                            |(idx, exchange)| {
+                            |(idx, flow)| {
                                 let ondelete = ondelete.clone();
                                 let onselect = onselect.clone();
-                                if let Some(ref req) = exchange.0{
-                                    if filter_request(req.method().to_string(), &filters) {
-                                        return Some(html!{
-                                            <RequestRow {onselect} {idx} {ondelete} {exchange}/>
-                                        })
-                                    }
+                                if filter_request(flow.request().method().to_string(), &filters)
+                                    && status_filters.contains(&flow_status(&flow))
+                                {
+                                    return Some(html!{
+                                        <RequestRow {onselect} {idx} {ondelete} {flow} {timeline}/>
+                                    })
                                 }
                                 None
                             }
@@ -249,8 +315,10 @@ pub fn request_table(props: &Props) -> Html {
                     }
                 </table>
                 if let Some(idx) = *selected {
-                    if let Some(RequestInfo(Some(req), Some(res))) = requests.borrow().get(idx) {
-                        <RequestDetails {ondeselect} response={res.clone()} request={req.clone()} />
+                    if let Some(flow) = requests.borrow().get(idx) {
+                        if let Some(res) = flow.response() {
+                            <RequestDetails {ondeselect} response={res.clone()} request={flow.request().clone()} />
+                        }
                     }
                 }
             </div>
@@ -26,6 +26,48 @@ pub fn request_tab(props: &Props) -> Html {
                 <strong ~innerText="Timestamp: " />
                 <p ~innerText={format!("{:?}", req.time())} />
             </div>
+            if let Some(addr) = req.client().addr() {
+                <div class="single_header">
+                    <strong ~innerText="Client:" />
+                    <p ~innerText={addr.to_string()} />
+                </div>
+            }
+            if let Some(connection) = req.client().connection_id() {
+                <div class="single_header">
+                    <strong ~innerText="Connection:" />
+                    <p ~innerText={format!("#{connection}")} />
+                </div>
+            }
+            if let Some(mode) = req.client().mode() {
+                <div class="single_header">
+                    <strong ~innerText="Mode:" />
+                    <p ~innerText={mode.to_string()} />
+                </div>
+            }
+            if let Some(sni) = req.client().sni() {
+                <div class="single_header">
+                    <strong ~innerText="SNI:" />
+                    <p ~innerText={sni.to_owned()} />
+                </div>
+            }
+            if let Some(tls) = req.client().tls() {
+                <div class="single_header">
+                    <strong ~innerText="TLS:" />
+                    <p ~innerText={tls.to_string()} />
+                </div>
+            }
+            if let Some(size) = req.body_size() {
+                <div class="single_header">
+                    <strong ~innerText="Size:" />
+                    <p ~innerText={super::body_size(size)} />
+                </div>
+            }
+            if req.truncated() {
+                <div class="single_header">
+                    <strong ~innerText="Body:" />
+                    <p ~innerText={format!("truncated to {} bytes", req.body().len())} />
+                </div>
+            }
         </TabView>
     }
 }
@@ -1,4 +1,5 @@
 use super::tab_view::TabView;
+use super::waterfall::format_ms;
 use proxyapi_models::ProxiedResponse;
 use yew::prelude::*;
 
@@ -26,6 +27,49 @@ pub fn response_tab(props: &Props) -> Html {
                 <strong ~innerText="Timestamp: " />
                 <p ~innerText={format!("{:?}", res.time())} />
             </div>
+            if let Some(error) = res.error() {
+                <div class="single_header">
+                    <strong ~innerText="Error:" />
+                    <p ~innerText={error.to_string()} />
+                </div>
+            }
+            if let Some(addr) = res.server().addr() {
+                <div class="single_header">
+                    <strong ~innerText="Server:" />
+                    <p ~innerText={addr.to_string()} />
+                </div>
+            }
+            if let Some(tls) = res.server().tls() {
+                <div class="single_header">
+                    <strong ~innerText="TLS:" />
+                    <p ~innerText={tls.to_string()} />
+                </div>
+            }
+            if let Some(size) = res.body_size() {
+                <div class="single_header">
+                    <strong ~innerText="Size:" />
+                    <p ~innerText={super::body_size(size)} />
+                </div>
+            }
+            if let Some(timings) = res.timings() {
+                <div class="single_header">
+                    <strong ~innerText="Timing:" />
+                    <p ~innerText={
+                        timings
+                            .phases()
+                            .into_iter()
+                            .map(|(phase, _, duration)| format!("{phase} {}", format_ms(duration)))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    } />
+                </div>
+            }
+            if res.truncated() {
+                <div class="single_header">
+                    <strong ~innerText="Body:" />
+                    <p ~innerText={format!("truncated to {} bytes", res.body().len())} />
+                </div>
+            }
         </TabView>
     }
 }
@@ -1,14 +1,16 @@
-use proxyapi_models::RequestInfo;
+use super::waterfall::{format_ms, Waterfall};
+use proxyapi_models::{Flow, FlowState};
 use stylist::yew::use_style;
 use url::Url;
 use yew::prelude::*;
 
 #[derive(Clone, PartialEq, Properties)]
 pub struct RowProps {
-    pub exchange: RequestInfo,
+    pub flow: Flow,
     pub idx: usize,
     pub ondelete: Callback<usize>,
     pub onselect: Callback<usize>,
+    pub timeline: (i64, i64),
 }
 
 #[function_component(RequestRow)]
@@ -65,17 +67,38 @@ pub fn request_row(props: &RowProps) -> Html {
        } 
        "#
     );
-    match props.exchange {
-        RequestInfo(Some(ref req), Some(ref res)) => {
+    let req = props.flow.request();
+    match req.uri().authority() {
+        Some(authority) => {
             let idx = props.idx;
             let method = req.method().to_string();
-            let authority = req.uri().authority().unwrap().to_string();
+            let authority = authority.to_string();
             let query = Url::parse(&req.uri().to_string()).unwrap();
             let query = query
                 .query_pairs()
                 .map(|(key, value)| (key.to_string(), value.to_string()));
             let ondelete = props.ondelete.clone();
             let onselect = props.onselect.clone();
+            let flow = &props.flow;
+            let state_class = match flow.state() {
+                FlowState::Failed => "failed",
+                state if state.is_pending() => "pending",
+                _ => "",
+            };
+            let status = match (flow.state(), flow.response()) {
+                (FlowState::Failed, _) => super::flow_status(flow),
+                (_, Some(res)) => res.status().to_string(),
+                (_, None) => "Pending".to_string(),
+            };
+            let error = flow.error().map(ToString::to_string);
+            let time = match flow.response() {
+                Some(res) if flow.state() == FlowState::Complete => match res.timings() {
+                    Some(timings) => format_ms(timings.total()),
+                    None => format_ms(res.time() - req.time()),
+                },
+                _ => "-".to_string(),
+            };
+            let timeline = props.timeline;
             html! {
                 <tr class="grid-body" onclick={move |_| {onselect.emit(idx)}}>
                     <td class={path_style}>
@@ -91,9 +114,10 @@ pub fn request_row(props: &RowProps) -> Html {
                         </div>
                     </td>
                     <td  class={classes!("method", &method)} >{method}</td>
-                    <td>{res.status().to_string()}</td>
+                    <td class={state_class} title={error}>{status}</td>
                     <td >{req.body().len()}</td>
-                    <td >{((res.time() - req.time()) as f64 * 1e-6).trunc()}</td>
+                    <td >{time}</td>
+                    <td><Waterfall flow={flow.clone()} {timeline} /></td>
                     <td>
                         <button title={"Delete"} class={delete_style}
                             onclick={move |e: MouseEvent| {ondelete.emit(idx); e.stop_immediate_propagation();}}
@@ -0,0 +1,110 @@
+use proxyapi_models::{Flow, FlowState, Phase};
+use stylist::yew::use_style;
+use yew::prelude::*;
+
+#[derive(Clone, PartialEq, Properties)]
+pub struct Props {
+    pub flow: Flow,
+    /// Start and end of the timeline shared by all rows, in nanoseconds since the epoch.
+    pub timeline: (i64, i64),
+}
+
+/// Start and end of a flow on the timeline.
+fn bounds(flow: &Flow) -> (i64, i64) {
+    let start = flow.request().time();
+    let end = match flow.response() {
+        Some(res) => match res.timings() {
+            Some(timings) => timings.start() + timings.total(),
+            None => res.time(),
+        },
+        None => start,
+    };
+    (start, end.max(start))
+}
+
+/// Timeline spanning all of `flows`.
+pub fn timeline(flows: &[Flow]) -> (i64, i64) {
+    flows
+        .iter()
+        .map(bounds)
+        .reduce(|(start, end), (s, e)| (start.min(s), end.max(e)))
+        .unwrap_or_default()
+}
+
+fn phase_class(phase: Phase) -> &'static str {
+    match phase {
+        Phase::Dns => "dns",
+        Phase::Connect => "connect",
+        Phase::Tls => "tls",
+        Phase::Send => "send",
+        Phase::Wait => "wait",
+        Phase::Download => "download",
+    }
+}
+
+pub fn format_ms(nanos: i64) -> String {
+    format!("{:.2} ms", nanos as f64 * 1e-6)
+}
+
+#[function_component(Waterfall)]
+pub fn waterfall(props: &Props) -> Html {
+    let style = use_style!(
+        r#"
+        position: relative;
+        height: 10px;
+
+        div {
+            position: absolute;
+            top: 0;
+            height: 100%;
+            min-width: 2px;
+        }
+        .dns { background: #1abc9c; }
+        .connect { background: #e67e22; }
+        .tls { background: #9b59b6; }
+        .send { background: #3498db; }
+        .wait { background: #2ecc71; }
+        .download { background: #2980b9; }
+        .failed { background: #e74c3c; }
+        .pending { background: var(--little-contrast); }
+        "#
+    );
+
+    let (origin, end) = props.timeline;
+    let span = (end - origin).max(1) as f64;
+    let bar = |class: &'static str, start: i64, duration: i64, title: String| {
+        let left = (start - origin) as f64 / span * 100.0;
+        let width = duration as f64 / span * 100.0;
+        html! {
+            <div {class} {title} style={format!("left: {left:.3}%; width: {width:.3}%;")} />
+        }
+    };
+
+    let flow = &props.flow;
+    let bars = match flow.response().and_then(|res| res.timings()) {
+        Some(timings) => timings
+            .phases()
+            .into_iter()
+            .map(|(phase, offset, duration)| {
+                bar(
+                    phase_class(phase),
+                    timings.start() + offset,
+                    duration,
+                    format!("{phase}: {}", format_ms(duration)),
+                )
+            })
+            .collect::<Html>(),
+        None => {
+            let (start, end) = bounds(flow);
+            let class = match flow.state() {
+                FlowState::Failed => "failed",
+                _ => "pending",
+            };
+            bar(class, start, end - start, super::flow_status(flow))
+        }
+    };
+
+    html! {
+        <div class={style}>{bars}</div>
+    }
+}
@@ -0,0 +1,100 @@
+use crate::api::listen_tunnel_event;
+use proxyapi_models::Tunnel;
+use std::{cell::RefCell, rc::Rc};
+use stylist::yew::use_style;
+use yew::prelude::*;
+
+#[derive(Clone, PartialEq, Properties)]
+pub struct Props {
+    pub tunnels: Rc<RefCell<Vec<Tunnel>>>,
+    pub paused: bool,
+}
+
+#[function_component(TunnelTable)]
+pub fn tunnel_table(props: &Props) -> Html {
+    let trigger = use_force_update();
+    let tunnels = props.tunnels.clone();
+    let paused = props.paused;
+    use_effect_with_deps(
+        move |(tunnels, paused)| {
+            let tunnels = tunnels.clone();
+            let paused = *paused;
+            let on_tunnel = Callback::from(move |tunnel| {
+                if !paused {
+                    tunnels.borrow_mut().push(tunnel);
+                    trigger.force_update();
+                }
+            });
+            let listener = listen_tunnel_event(Some(on_tunnel));
+            move || drop(listener)
+        },
+        (tunnels.clone(), paused),
+    );
+    let style = use_style!(
+        r#"
+        width: 95%;
+        margin: 0 auto 25px;
+        border-collapse: collapse;
+        table-layout: fixed;
+        color: var(--font-color);
+        border-radius: 10px;
+        box-shadow: var(--box-shadow);
+        overflow: hidden;
+
+        tr {
+            border-bottom: 1px solid var(--little-contrast);
+            background: var(--bg-color-secondary);
+            font-size: 0.8rem;
+        }
+        td, th {
+            padding: 5px 10px;
+            white-space: nowrap;
+            overflow: hidden;
+            text-overflow: ellipsis;
+            text-align: left;
+            width: 100px;
+        }
+        th {
+            padding: 10px;
+        }
+        tr td:first-child,
+        tr th:first-child {
+            width: 100%;
+        }
+        "#
+    );
+
+    if tunnels.borrow().is_empty() {
+        return html! {};
+    }
+
+    html! {
+        <table class={style}>
+            <tr>
+                <th ~innerText="Tunnel"/>
+                <th ~innerText="Up"/>
+                <th ~innerText="Down"/>
+                <th ~innerText="Duration"/>
+                <th ~innerText="Error"/>
+            </tr>
+            {
+                tunnels.borrow().iter().map(|tunnel| {
+                    let duration = (tunnel.duration() as f64 * 1e-6).trunc();
+                    let error = tunnel
+                        .error()
+                        .map(|error| error.kind().to_string())
+                        .unwrap_or_default();
+                    html! {
+                        <tr>
+                            <td>{format!("{}:{}", tunnel.host(), tunnel.port())}</td>
+                            <td>{tunnel.bytes_up()}</td>
+                            <td>{tunnel.bytes_down()}</td>
+                            <td>{format!("{duration} ms")}</td>
+                            <td title={tunnel.error().map(|error| error.message().to_owned())}>{error}</td>
+                        </tr>
+                    }
+                }).collect::<Html>()
+            }
+        </table>
+    }
+}
@@ -0,0 +1,124 @@
+use proxyapi_models::{WebSocketDirection, WebSocketMessage, WebSocketSession};
+use stylist::yew::use_style;
+use yew::prelude::*;
+
+#[derive(Clone, PartialEq, Properties)]
+pub struct Props {
+    pub session: WebSocketSession,
+    pub ondeselect: Callback<()>,
+}
+
+fn describe(message: &WebSocketMessage) -> (&'static str, String) {
+    match message {
+        WebSocketMessage::Text(text) => ("text", text.clone()),
+        WebSocketMessage::Binary(data) => ("binary", format!("{} bytes {:02X?}", data.len(), data)),
+        WebSocketMessage::Ping(data) => ("ping", format!("{:02X?}", data)),
+        WebSocketMessage::Pong(data) => ("pong", format!("{:02X?}", data)),
+        WebSocketMessage::Close(Some((code, reason))) => ("close", format!("{code} {reason}")),
+        WebSocketMessage::Close(None) => ("close", String::new()),
+    }
+}
+
+#[function_component(MessageLog)]
+pub fn message_log(props: &Props) -> Html {
+    let style = use_style!(
+        r#"
+        position:fixed;
+        margin: auto;
+        top:0;
+        bottom:0;
+        left: 0;
+        right: 0;
+        width: 750px;
+        height: 450px;
+        background: var(--bg-color-secondary);
+        z-index: 999999;
+        padding:20px;
+        border-radius: 7px;
+        overflow:auto;
+
+        .close_button{
+            color: var(--font-color);
+            border: 1px solid var(--little-contrast);
+            position:absolute;
+            right: 10px;
+            top: 10px;
+            height: 25px;
+            width: 25px;
+            text-align:center;
+            background: transparent;
+            border-radius: 5px;
+            padding:0;
+            font-size: 18px;
+        }
+        .frame{
+            font-size:.7rem;
+            display: flex;
+            justify-content: flex-start;
+            border-bottom: 1px solid var(--little-contrast);
+            padding: 8px 0;
+        }
+        .frame > span{
+            width: 80px;
+            flex-shrink: 0;
+        }
+        .frame > p{
+            margin:0;
+            word-break: break-all;
+        }
+        .control{
+            opacity: .6;
+        }
+        "#
+    );
+    let background = use_style!(
+        r#"
+            position:fixed;
+            top:0;
+            bottom:0;
+            left:0;
+            right:0;
+            width:100vw;
+            height: 100vh;
+            background:var(--font-color);
+            opacity: .9;
+            content: "";
+            z-index: 99999;
+        "#
+    );
+    let ondeselect = {
+        let ondeselect = props.ondeselect.clone();
+        Callback::from(move |_| {
+            ondeselect.emit(());
+        })
+    };
+    let session = &props.session;
+    let start = session.response().time();
+    html! {
+        <div>
+            <div class={background} onclick={&ondeselect}/>
+            <div class={style}>
+                <button class="close_button" onclick={&ondeselect} ~innerText="×" />
+                <strong ~innerText={session.request().uri().to_string()} />
+                {
+                    session.frames().iter().map(|frame| {
+                        let arrow = match frame.direction() {
+                            WebSocketDirection::ClientToServer => "↑",
+                            WebSocketDirection::ServerToClient => "↓",
+                        };
+                        let (kind, payload) = describe(frame.message());
+                        let elapsed = ((frame.time() - start) as f64 * 1e-6).trunc();
+                        html! {
+                            <div class={classes!("frame", frame.message().is_control().then_some("control"))}>
+                                <span ~innerText={arrow} />
+                                <span ~innerText={format!("{elapsed} ms")} />
+                                <span ~innerText={kind} />
+                                <p ~innerText={payload} />
+                            </div>
+                        }
+                    }).collect::<Html>()
+                }
+            </div>
+        </div>
+    }
+}
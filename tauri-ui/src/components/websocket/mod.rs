@@ -0,0 +1,139 @@
+mod message_log;
+
+use self::message_log::MessageLog;
+use crate::api::listen_websocket_event;
+use proxyapi_models::{WebSocketEvent, WebSocketSession};
+use std::{cell::RefCell, rc::Rc};
+use stylist::yew::use_style;
+use yew::prelude::*;
+
+#[derive(Clone, PartialEq, Properties)]
+pub struct Props {
+    pub sessions: Rc<RefCell<Vec<WebSocketSession>>>,
+    pub paused: bool,
+}
+
+#[function_component(WebSocketTable)]
+pub fn websocket_table(props: &Props) -> Html {
+    let trigger = use_force_update();
+    let sessions = props.sessions.clone();
+    let paused = props.paused;
+    let selected = use_state_eq(|| None as Option<u64>);
+    use_effect_with_deps(
+        move |(sessions, paused)| {
+            let sessions = sessions.clone();
+            let paused = *paused;
+            let on_event = Callback::from(move |event| {
+                let mut s = sessions.borrow_mut();
+                match event {
+                    WebSocketEvent::Opened(session) => {
+                        if !paused {
+                            s.push(*session);
+                        }
+                    }
+                    // Frames of a session that was already captured are kept even while paused,
+                    // so the message log never has holes in it.
+                    WebSocketEvent::Frame { session, frame } => {
+                        if let Some(session) = s.iter_mut().find(|s| s.id() == session) {
+                            session.push_frame(frame);
+                        }
+                    }
+                    WebSocketEvent::Closed {
+                        session,
+                        code,
+                        reason,
+                    } => {
+                        if let Some(session) = s.iter_mut().find(|s| s.id() == session) {
+                            session.close(code, reason);
+                        }
+                    }
+                }
+                trigger.force_update();
+            });
+            let listener = listen_websocket_event(Some(on_event));
+            move || drop(listener)
+        },
+        (sessions.clone(), paused),
+    );
+    let ondeselect = {
+        let selected = selected.clone();
+        Callback::from(move |()| selected.set(None))
+    };
+    let style = use_style!(
+        r#"
+        width: 95%;
+        margin: 0 auto 25px;
+        border-collapse: collapse;
+        table-layout: fixed;
+        color: var(--font-color);
+        border-radius: 10px;
+        box-shadow: var(--box-shadow);
+        overflow: hidden;
+
+        tr {
+            border-bottom: 1px solid var(--little-contrast);
+            background: var(--bg-color-secondary);
+            font-size: 0.8rem;
+            cursor: pointer;
+        }
+        td, th {
+            padding: 5px 10px;
+            white-space: nowrap;
+            overflow: hidden;
+            text-overflow: ellipsis;
+            text-align: left;
+            width: 100px;
+        }
+        th {
+            padding: 10px;
+        }
+        tr td:first-child,
+        tr th:first-child {
+            width: 100%;
+        }
+        "#
+    );
+
+    if sessions.borrow().is_empty() {
+        return html! {};
+    }
+
+    html! {
+        <>
+            <table class={style}>
+                <tr>
+                    <th ~innerText="WebSocket"/>
+                    <th ~innerText="Status"/>
+                    <th ~innerText="State"/>
+                    <th ~innerText="Messages"/>
+                </tr>
+                {
+                    sessions.borrow().iter().map(|session| {
+                        let id = session.id();
+                        let onclick = {
+                            let selected = selected.clone();
+                            move |_| selected.set(Some(id))
+                        };
+                        let state = match session.closed() {
+                            Some((code, _)) => format!("Closed ({code})"),
+                            None => "Open".to_owned(),
+                        };
+                        html! {
+                            <tr {onclick}>
+                                <td>{session.request().uri().to_string()}</td>
+                                <td>{session.response().status().to_string()}</td>
+                                <td title={session.closed().map(|(_, reason)| reason.to_owned())}>{state}</td>
+                                <td>{session.frames().len()}</td>
+                            </tr>
+                        }
+                    }).collect::<Html>()
+                }
+            </table>
+            if let Some(id) = *selected {
+                if let Some(session) = sessions.borrow().iter().find(|s| s.id() == id) {
+                    <MessageLog {ondeselect} session={session.clone()} />
+                }
+            }
+        </>
+    }
+}